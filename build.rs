@@ -0,0 +1,389 @@
+//! Generate the tag, block and primitive enums from the dictionaries in fiff/.
+//!
+//! Each dictionary becomes an enum with one variant per row (named after the row, in camel case)
+//! and a catch-all Unknown(i32), along with from_code / to_code, name / description lookups and
+//! FromStr.  Updating from upstream fiff-constants is then just a matter of replacing the files.
+//!
+//! tag_blocks.tsv lists the blocks each tag is usually found in, for describing tags.
+//!
+//! The value enumerations in fiff/values/ (channel types, units, coil types, ...) are generated
+//! the same way, along with lookups from the tags declared as enum(..) in tags.tsv to them.
+
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+// block_start and block_end delimit blocks rather than holding data, see BlockTagKind
+const BLOCK_TAGS: [i32; 2] = [104, 105];
+
+struct Row {
+    name: String,
+    code: i32,
+    fields: Vec<(&'static str, String)>,
+}
+
+fn main() {
+    let dicts = [
+        (
+            "DataTagKind",
+            "tags.tsv",
+            vec!["dtype", "unit", "description"],
+        ),
+        ("BlockKind", "blocks.tsv", vec!["description"]),
+        ("DType", "primitives.tsv", vec!["description"]),
+    ];
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let mut out = String::new();
+    let mut tags = Vec::new();
+    let mut blocks = Vec::new();
+
+    for (kind, file, fields) in dicts {
+        let path = Path::new("fiff").join(file);
+        println!("cargo:rerun-if-changed={}", path.display());
+
+        let mut rows = read_rows(&path, &fields);
+        if kind == "DataTagKind" {
+            rows.retain(|x| !BLOCK_TAGS.contains(&x.code));
+            tags = rows
+                .iter()
+                .map(|x| (x.name.clone(), field_value(x, "dtype")))
+                .collect();
+        }
+        if kind == "BlockKind" {
+            blocks = rows.iter().map(|x| x.name.clone()).collect();
+        }
+
+        write_enum(&mut out, kind, &rows, &fields, "name");
+    }
+
+    write_tag_blocks(&mut out, &tags, &blocks);
+
+    fs::write(Path::new(&out_dir).join("enums.rs"), out).unwrap();
+
+    let values_dir = Path::new("fiff").join("values");
+    println!("cargo:rerun-if-changed={}", values_dir.display());
+
+    let mut files: Vec<_> = fs::read_dir(&values_dir)
+        .unwrap()
+        .map(|x| x.unwrap().path())
+        .filter(|x| x.extension().is_some_and(|x| x == "tsv"))
+        .collect();
+    files.sort();
+
+    let mut out = String::new();
+    let mut values = Vec::new();
+    let fields = ["symbol", "description"];
+
+    for path in files {
+        println!("cargo:rerun-if-changed={}", path.display());
+
+        let name = path.file_stem().unwrap().to_str().unwrap().to_owned();
+        let kind = camel_case(&name);
+        let rows = read_rows(&path, &fields);
+
+        write_enum(&mut out, &kind, &rows, &fields, "symbol");
+        values.push((name, kind));
+    }
+
+    write_value_lookups(&mut out, &tags, &values);
+    fs::write(Path::new(&out_dir).join("values.rs"), out).unwrap();
+}
+
+fn read_rows(path: &Path, fields: &[&'static str]) -> Vec<Row> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .from_path(path)
+        .unwrap_or_else(|e| panic!("could not read {path:?}: {e}"));
+
+    let headers = reader.headers().unwrap().clone();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|x| x == name)
+            .unwrap_or_else(|| panic!("{path:?} has no {name} column"))
+    };
+
+    let name = column("name");
+    let code = column("code");
+    let columns: Vec<(&'static str, usize)> = fields.iter().map(|x| (*x, column(x))).collect();
+
+    reader
+        .records()
+        .map(|record| {
+            let record = record.unwrap();
+            Row {
+                name: record[name].to_owned(),
+                code: record[code]
+                    .parse()
+                    .unwrap_or_else(|e| panic!("bad code in {path:?}: {e}")),
+                fields: columns
+                    .iter()
+                    .map(|(field, i)| (*field, record[*i].to_owned()))
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+fn field_value(row: &Row, name: &str) -> String {
+    row.fields
+        .iter()
+        .find(|(field, _)| *field == name)
+        .map(|(_, value)| value.clone())
+        .unwrap()
+}
+
+fn camel_case(name: &str) -> String {
+    name.split('_')
+        .map(|x| {
+            let mut chars = x.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+// display is the lookup used for Display: name for tags, symbol (e.g. EEG, T/m) for values
+fn write_enum(out: &mut String, kind: &str, rows: &[Row], fields: &[&str], display: &str) {
+    // a row named unknown (e.g. sex 0) would clash with the Unknown(code) catch-all
+    let variant = |x: &Row| match camel_case(&x.name).as_str() {
+        "Unknown" => String::from("Unspecified"),
+        name => name.to_owned(),
+    };
+
+    writeln!(
+        out,
+        "#[derive(Debug, PartialEq, Default, Clone, Eq, Hash, Serialize)]"
+    )
+    .unwrap();
+    writeln!(out, "pub enum {kind} {{").unwrap();
+    for (i, row) in rows.iter().enumerate() {
+        if i == 0 {
+            writeln!(out, "    #[default]").unwrap();
+        }
+        writeln!(out, "    #[serde(rename = {:?})]", row.name).unwrap();
+        writeln!(out, "    {},", variant(row)).unwrap();
+    }
+    writeln!(out, "    #[serde(untagged)]\n    Unknown(i32),\n}}\n").unwrap();
+
+    writeln!(out, "impl {kind} {{").unwrap();
+    writeln!(out, "    pub const ALL: &'static [{kind}] = &[").unwrap();
+    for row in rows {
+        writeln!(out, "        {kind}::{},", variant(row)).unwrap();
+    }
+    writeln!(out, "    ];\n").unwrap();
+
+    writeln!(
+        out,
+        "    pub fn from_code(code: i32) -> Self {{\n        match code {{"
+    )
+    .unwrap();
+    for row in rows {
+        writeln!(out, "            {} => {kind}::{},", row.code, variant(row)).unwrap();
+    }
+    writeln!(
+        out,
+        "            _ => {kind}::Unknown(code),\n        }}\n    }}\n"
+    )
+    .unwrap();
+
+    writeln!(
+        out,
+        "    pub fn to_code(&self) -> i32 {{\n        match self {{"
+    )
+    .unwrap();
+    for row in rows {
+        writeln!(out, "            {kind}::{} => {},", variant(row), row.code).unwrap();
+    }
+    writeln!(
+        out,
+        "            {kind}::Unknown(code) => *code,\n        }}\n    }}\n"
+    )
+    .unwrap();
+
+    let mut lookup = |method: &str, value: &dyn Fn(&Row) -> String, unknown: &str| {
+        writeln!(
+            out,
+            "    pub fn {method}(&self) -> &'static str {{\n        match self {{"
+        )
+        .unwrap();
+        for row in rows {
+            writeln!(
+                out,
+                "            {kind}::{} => {:?},",
+                variant(row),
+                value(row)
+            )
+            .unwrap();
+        }
+        writeln!(
+            out,
+            "            {kind}::Unknown(_) => {unknown:?},\n        }}\n    }}\n"
+        )
+        .unwrap();
+    };
+
+    lookup("name", &|x| x.name.clone(), "unknown");
+    for field in fields {
+        let unknown = match *field {
+            "description" => "Unrecognized code",
+            "unit" => "-",
+            _ => "unknown",
+        };
+        lookup(field, &|x| field_value(x, field), unknown);
+    }
+    writeln!(out, "}}\n").unwrap();
+
+    writeln!(
+        out,
+        "impl std::fmt::Display for {kind} {{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {{
+        match self {{
+            {kind}::Unknown(code) => write!(f, \"{{code}}\"),
+            x => write!(f, \"{{}}\", x.{display}()),
+        }}
+    }}
+}}
+"
+    )
+    .unwrap();
+
+    writeln!(
+        out,
+        "impl std::str::FromStr for {kind} {{
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {{
+        match name {{"
+    )
+    .unwrap();
+    for row in rows {
+        writeln!(
+            out,
+            "            {:?} => Ok({kind}::{}),",
+            row.name,
+            variant(row)
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "            _ => Self::ALL
+                .iter()
+                .find(|x| {{
+                    x.name().eq_ignore_ascii_case(name) || x.to_string().eq_ignore_ascii_case(name)
+                }})
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!(\"Unrecognized name for {kind}: {{name:?}}\")),
+        }}
+    }}
+}}
+"
+    )
+    .unwrap();
+}
+
+// DataTagKind::blocks, checking that every tag and block in tag_blocks.tsv is in the dictionaries
+fn write_tag_blocks(out: &mut String, tags: &[(String, String)], blocks: &[String]) {
+    let path = Path::new("fiff").join("tag_blocks.tsv");
+    println!("cargo:rerun-if-changed={}", path.display());
+
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .from_path(&path)
+        .unwrap_or_else(|e| panic!("could not read {path:?}: {e}"));
+
+    writeln!(
+        out,
+        "impl DataTagKind {{
+    /// Blocks this tag is usually found in, empty if that isn't known.
+    pub fn blocks(&self) -> &'static [BlockKind] {{
+        match self {{"
+    )
+    .unwrap();
+    for record in reader.records() {
+        let record = record.unwrap();
+        let tag = &record[0];
+        if !tags.iter().any(|(x, _)| x == tag) {
+            panic!("unknown tag {tag:?} in {path:?}");
+        }
+
+        let variants: Vec<String> = record[1]
+            .split(',')
+            .map(|block| {
+                if !blocks.iter().any(|x| x == block) {
+                    panic!("unknown block {block:?} for {tag} in {path:?}");
+                }
+                format!("BlockKind::{}", camel_case(block))
+            })
+            .collect();
+        writeln!(
+            out,
+            "            DataTagKind::{} => &[{}],",
+            camel_case(tag),
+            variants.join(", ")
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "            _ => &[],
+        }}
+    }}
+}}
+"
+    )
+    .unwrap();
+}
+
+// DataTagKind::value_name / parse_value for the tags whose dtype is one of the value enums
+fn write_value_lookups(out: &mut String, tags: &[(String, String)], values: &[(String, String)]) {
+    let tags: Vec<(String, &str)> = tags
+        .iter()
+        .filter_map(|(tag, dtype)| {
+            let name = dtype.strip_prefix("enum(")?.strip_suffix(')')?;
+            let (_, kind) = values.iter().find(|(x, _)| x == name)?;
+            Some((camel_case(tag), kind.as_str()))
+        })
+        .collect();
+
+    writeln!(
+        out,
+        "impl DataTagKind {{
+    /// Symbolic name of a value of this tag, for tags whose values are enumerated.
+    pub fn value_name(&self, code: i32) -> Option<String> {{
+        match self {{"
+    )
+    .unwrap();
+    for (tag, kind) in &tags {
+        writeln!(
+            out,
+            "            DataTagKind::{tag} => Some({kind}::from_code(code).to_string()),"
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "            _ => None,
+        }}
+    }}
+
+    /// Read a value of this tag given by name or code, for tags whose values are enumerated.
+    pub fn parse_value(&self, value: &str) -> Option<Result<i32>> {{
+        let code = value.trim().parse::<i32>().ok();
+        match self {{"
+    )
+    .unwrap();
+    for (tag, kind) in &tags {
+        writeln!(
+            out,
+            "            DataTagKind::{tag} => Some(code.map(Ok).unwrap_or_else(|| value.trim().parse::<{kind}>().map(|x| x.to_code()))),"
+        )
+        .unwrap();
+    }
+    writeln!(out, "            _ => None,\n        }}\n    }}\n}}").unwrap();
+}
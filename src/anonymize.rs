@@ -0,0 +1,454 @@
+//! Remove or obscure identifying information in .fif files.
+//!
+//! Each tag kind can be given a rule: dropped, replaced with a fixed value, or (for dates)
+//! shifted by a number of days.  By default subject names, birthday, hospital id and comments are
+//! dropped along with the experimenter and project members, while the measurement date and the
+//! timestamps in file/block ids are shifted.
+//!
+//! Applying the same rules to every file of a split recording keeps them consistent: all ids are
+//! shifted by the same amount, so references between the parts still match.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+
+use crate::date::MeasDate;
+use crate::enums::DataTagKind;
+use crate::graph::Tree;
+use crate::parser::FifParser;
+use crate::tag::{Data, FiffNode, LabelledData};
+use crate::writer::FifWriter;
+
+const SECONDS_PER_DAY: i64 = 86400;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Rule {
+    Keep,
+    Drop,
+    Replace(Data),
+    ShiftDays(i64),
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let out = match self {
+            Rule::Keep => "keep",
+            Rule::Drop => "drop",
+            Rule::Replace(_) => "replace",
+            Rule::ShiftDays(_) => "shift",
+        };
+
+        write!(f, "{out}")
+    }
+}
+
+/// One tag changed by anonymization, `after` is None for dropped tags.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Change {
+    pub file: PathBuf,
+    pub kind: DataTagKind,
+    pub rule: Rule,
+    pub before: String,
+    pub after: Option<String>,
+}
+
+/// Audit trail of everything that was changed, displayed as csv.
+#[derive(Debug, Default)]
+pub struct Report(pub Vec<Change>);
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut wtr = csv::WriterBuilder::new()
+            .delimiter(b',')
+            .from_writer(vec![]);
+
+        wtr.serialize(("file", "tag", "action", "before", "after"))
+            .unwrap();
+
+        for change in self.0.iter() {
+            wtr.serialize((
+                change.file.to_string_lossy(),
+                &change.kind,
+                change.rule.to_string(),
+                &change.before,
+                change.after.as_deref().unwrap_or(""),
+            ))
+            .unwrap();
+        }
+
+        let data = String::from_utf8(wtr.into_inner().unwrap()).unwrap();
+        write!(f, "{}", data)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Anonymizer {
+    rules: HashMap<DataTagKind, Rule>,
+}
+
+impl Anonymizer {
+    /// Default rules, shifting all dates by the given number of days.
+    pub fn new(shift_days: i64) -> Self {
+        let mut rules = HashMap::new();
+
+        for kind in [
+            DataTagKind::SubjFirstName,
+            DataTagKind::SubjMiddleName,
+            DataTagKind::SubjLastName,
+            DataTagKind::SubjBirthDay,
+            DataTagKind::SubjHisId,
+            DataTagKind::SubjComment,
+            DataTagKind::Experimenter,
+            DataTagKind::ProjPersons,
+        ] {
+            rules.insert(kind, Rule::Drop);
+        }
+
+        for kind in [
+            DataTagKind::MeasDate,
+            DataTagKind::FileId,
+            DataTagKind::BlockId,
+            DataTagKind::ParentFileId,
+            DataTagKind::ParentBlockId,
+            DataTagKind::RefFileId,
+            DataTagKind::RefBlockId,
+        ] {
+            rules.insert(kind, Rule::ShiftDays(shift_days));
+        }
+
+        Anonymizer { rules }
+    }
+
+    /// Override the rule for one kind of tag.
+    pub fn rule(mut self, kind: DataTagKind, rule: Rule) -> Self {
+        self.rules.insert(kind, rule);
+        self
+    }
+
+    /// Apply the rules to every tag in a tree, returning what was changed.
+    pub fn anonymize_tree(&self, file: &Path, tree: &mut Tree<FiffNode>) -> Result<Vec<Change>> {
+        let mut changes = vec![];
+
+        for node in tree.depth_first(tree.root) {
+            let FiffNode::Tag { kind, data } = &tree[node] else {
+                continue;
+            };
+
+            let rule = self.rules.get(kind).unwrap_or(&Rule::Keep);
+
+            let after = match rule {
+                Rule::Keep => continue,
+                Rule::Drop => None,
+                Rule::Replace(new) => Some(new.clone()),
+                Rule::ShiftDays(0) => continue,
+                Rule::ShiftDays(days) => match shift_days(data, *days)? {
+                    Some(data) => Some(data),
+                    None => {
+                        warn!("Don't know how to shift {kind:?} in {file:?}, leaving as is");
+                        continue;
+                    }
+                },
+            };
+
+            changes.push(Change {
+                file: file.to_owned(),
+                kind: kind.clone(),
+                rule: rule.clone(),
+                before: LabelledData::new(kind.clone(), data.clone()).to_string(),
+                after: after
+                    .as_ref()
+                    .map(|x| LabelledData::new(kind.clone(), x.clone()).to_string()),
+            });
+
+            match after {
+                Some(after) => {
+                    tree[node] = FiffNode::Tag {
+                        kind: kind.clone(),
+                        data: after,
+                    }
+                }
+                None => tree.remove(node),
+            }
+        }
+
+        Ok(changes)
+    }
+
+    /// Write an anonymized copy of `input` to `output`.
+    ///
+    /// Refuses to overwrite the input, see `anonymize_in_place` for that.
+    pub fn anonymize_file(&self, input: &Path, output: &Path) -> Result<Vec<Change>> {
+        if output.exists() && fs::canonicalize(output)? == fs::canonicalize(input)? {
+            return Err(anyhow!(
+                "Refusing to overwrite {input:?}, anonymize it in place instead"
+            ));
+        }
+
+        let mut tree = FifParser::parse(input.to_owned())?;
+        let changes = self.anonymize_tree(input, &mut tree)?;
+
+        // keep the tag directory if the original had one, it needs rewriting anyway
+        let has_directory = tree.children(tree.root).into_iter().any(|x| {
+            matches!(
+                tree[x],
+                FiffNode::Tag {
+                    kind: DataTagKind::Dir,
+                    ..
+                }
+            )
+        });
+
+        let mut writer = FifWriter::create(output)?.with_source(File::open(input)?);
+        if has_directory {
+            writer = writer.with_directory();
+        }
+
+        writer.write_tree(&tree)?;
+        writer.finish()?;
+
+        Ok(changes)
+    }
+
+    /// Anonymize a file, replacing the original once the new copy is complete.
+    pub fn anonymize_in_place(&self, file: &Path) -> Result<Vec<Change>> {
+        let mut temp = file.as_os_str().to_owned();
+        temp.push(".anonymizing");
+        let temp = PathBuf::from(temp);
+
+        let changes = self.anonymize_file(file, &temp).and_then(|changes| {
+            fs::rename(&temp, file).with_context(|| format!("Could not replace {file:?}"))?;
+            Ok(changes)
+        });
+
+        // a partial copy may still hold the identifiers, so it mustn't be left behind
+        if changes.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+
+        changes
+    }
+}
+
+// move a date forwards (or backwards, for negative days).  None if the data holds no date
+fn shift_days(data: &Data, days: i64) -> Result<Option<Data>> {
+    let shift_secs = |secs: i32| -> Result<i32> {
+        i32::try_from(secs as i64 + days * SECONDS_PER_DAY)
+            .map_err(|_| anyhow!("Shifting {secs} by {days} days is out of range"))
+    };
+
+    // unset dates are left unset
+    let shifted = match data {
+        Data::Int32(x) if MeasDate::from_ints(x).is_ok_and(|x| x.is_unset()) => Some(data.clone()),
+        Data::Int32(x) if !x.is_empty() => {
+            let mut x = x.clone();
+            x[0] = shift_secs(x[0])?;
+            Some(Data::Int32(x))
+        }
+        Data::IdStruct(id) => {
+            let mut id = id.clone();
+            id.secs = shift_secs(id.secs)?;
+            Some(Data::IdStruct(id))
+        }
+        Data::JulianDate(x) => {
+            let x: Result<Vec<i32>> = x
+                .iter()
+                .map(|x| {
+                    if *x == 0 {
+                        return Ok(0);
+                    }
+
+                    i32::try_from(*x as i64 + days)
+                        .map_err(|_| anyhow!("Shifting {x} by {days} days is out of range"))
+                })
+                .collect();
+            Some(Data::JulianDate(x?))
+        }
+        _ => None,
+    };
+
+    Ok(shifted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::BlockKind;
+    use crate::tag::IdStruct;
+
+    #[test]
+    fn can_anonymize_tree() {
+        let mut tree = default_tree();
+        let anonymizer = Anonymizer::new(-10).rule(
+            DataTagKind::ProjPersons,
+            Rule::Replace(Data::String("anonymous".into())),
+        );
+
+        let changes = anonymizer
+            .anonymize_tree(Path::new("test.fif"), &mut tree)
+            .unwrap();
+
+        let kinds: Vec<DataTagKind> = changes.iter().map(|x| x.kind.clone()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                DataTagKind::FileId,
+                DataTagKind::MeasDate,
+                DataTagKind::SubjFirstName,
+                DataTagKind::SubjLastName,
+                DataTagKind::ProjPersons
+            ]
+        );
+
+        let mut expected = Tree::new();
+        expected.add_child(FiffNode::Tag {
+            kind: DataTagKind::FileId,
+            data: Data::IdStruct(id(1646226731 - 10 * 86400)),
+        });
+        let block = expected.add_child(FiffNode::Block {
+            kind: BlockKind::MeasInfo,
+        });
+        expected.move_to(block);
+        expected.add_child(FiffNode::Tag {
+            kind: DataTagKind::MeasDate,
+            data: Data::Int32(vec![1646226731 - 10 * 86400, 138511]),
+        });
+        expected.add_child(FiffNode::Tag {
+            kind: DataTagKind::Sfreq,
+            data: Data::Float(vec![1000.0]),
+        });
+        let subject = expected.add_child(FiffNode::Block {
+            kind: BlockKind::Subject,
+        });
+        expected.add_child(FiffNode::Tag {
+            kind: DataTagKind::ProjPersons,
+            data: Data::String("anonymous".into()),
+        });
+        expected.move_to(subject);
+        expected.add_child(FiffNode::Tag {
+            kind: DataTagKind::SubjId,
+            data: Data::Int32(vec![7]),
+        });
+
+        assert_eq!(tree, expected);
+    }
+
+    #[test]
+    fn shifting_out_of_range_fails() {
+        let mut tree = default_tree();
+        let anonymizer = Anonymizer::new(-100000);
+
+        assert!(anonymizer
+            .anonymize_tree(Path::new("test.fif"), &mut tree)
+            .is_err());
+    }
+
+    #[test]
+    fn keeps_input_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.fif");
+        let output = dir.path().join("output.fif");
+
+        let mut writer = FifWriter::create(&input).unwrap();
+        writer.write_tree(&default_tree()).unwrap();
+        writer.finish().unwrap();
+        let original = fs::read(&input).unwrap();
+
+        let anonymizer = Anonymizer::new(1);
+        assert!(anonymizer.anonymize_file(&input, &input).is_err());
+
+        let changes = anonymizer.anonymize_file(&input, &output).unwrap();
+        assert_eq!(changes.len(), 5);
+        assert_eq!(fs::read(&input).unwrap(), original);
+        assert!(fs::read(&output).unwrap().len() < original.len());
+
+        let changes = anonymizer.anonymize_in_place(&input).unwrap();
+        assert_eq!(changes.len(), 5);
+        assert_eq!(
+            fs::read(&input).unwrap().len(),
+            fs::read(&output).unwrap().len()
+        );
+    }
+
+    #[test]
+    fn cleans_up_after_failing_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("truncated.fif");
+
+        // a large tag cut short, which only fails once its data is copied
+        let mut tree = default_tree();
+        tree.add_child(FiffNode::Tag {
+            kind: DataTagKind::GantryModel,
+            data: Data::String("x".repeat(1000)),
+        });
+        let mut writer = FifWriter::create(&file).unwrap();
+        writer.write_tree(&tree).unwrap();
+        writer.finish().unwrap();
+        let truncated = fs::metadata(&file).unwrap().len() - 100;
+        File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_len(truncated)
+            .unwrap();
+
+        let anonymizer = Anonymizer::new(1);
+        assert!(anonymizer.anonymize_in_place(&file).is_err());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    }
+
+    fn id(secs: i32) -> IdStruct {
+        IdStruct {
+            version: 65539,
+            machid: (1, 2),
+            secs,
+            usecs: 3,
+        }
+    }
+
+    fn default_tree() -> Tree<FiffNode> {
+        let mut tree = Tree::new();
+
+        tree.add_child(FiffNode::Tag {
+            kind: DataTagKind::FileId,
+            data: Data::IdStruct(id(1646226731)),
+        });
+        let block = tree.add_child(FiffNode::Block {
+            kind: BlockKind::MeasInfo,
+        });
+        tree.move_to(block);
+        tree.add_child(FiffNode::Tag {
+            kind: DataTagKind::MeasDate,
+            data: Data::Int32(vec![1646226731, 138511]),
+        });
+        tree.add_child(FiffNode::Tag {
+            kind: DataTagKind::Sfreq,
+            data: Data::Float(vec![1000.0]),
+        });
+        let subject = tree.add_child(FiffNode::Block {
+            kind: BlockKind::Subject,
+        });
+        tree.add_child(FiffNode::Tag {
+            kind: DataTagKind::ProjPersons,
+            data: Data::String("Someone Else".into()),
+        });
+        tree.move_to(subject);
+        tree.add_child(FiffNode::Tag {
+            kind: DataTagKind::SubjId,
+            data: Data::Int32(vec![7]),
+        });
+        tree.add_child(FiffNode::Tag {
+            kind: DataTagKind::SubjFirstName,
+            data: Data::String("Jane".into()),
+        });
+        tree.add_child(FiffNode::Tag {
+            kind: DataTagKind::SubjLastName,
+            data: Data::String("Doe".into()),
+        });
+
+        tree
+    }
+}
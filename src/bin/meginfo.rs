@@ -2,86 +2,550 @@
 
 use anyhow::anyhow;
 use atty::Stream;
-use clap::Parser;
-use fiff::{config::Config, run};
+use clap::{Args, Parser, Subcommand};
+use fiff::channels::ChannelsFormat;
+use fiff::config::{
+    AnonymizeConfig, ChannelsConfig, Config, DiffConfig, DigConfig, DumpConfig, IndexCommand,
+    IndexConfig, InfoConfig, QueryConfig, SetConfig, StatsConfig, TagsConfig, TreeConfig,
+    ValidateConfig,
+};
+use fiff::diff::{DiffFormat, DEFAULT_TOLERANCE};
+use fiff::dig::DigFormat;
+use fiff::files::{find_files, FileOptions};
+use fiff::run;
+use fiff::stats::StatsFormat;
+use fiff::view::TreeFormat;
 use std::io::{self, BufRead};
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 
-use env_logger;
 use log::LevelFilter;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)] // Read from `Cargo.toml`
 struct Cli {
-    #[arg(long, short)]
-    files: Vec<String>,
+    #[command(subcommand)]
+    command: Command,
 
-    #[arg(long, short)]
-    tags: Vec<String>,
+    #[arg(long, short, global = true)]
+    log: Option<LevelFilter>,
+}
 
-    #[arg(long, short)]
-    show_tree: bool,
+// files to read, either given as arguments or one per line on stdin
+#[derive(Args)]
+struct Input {
+    /// Files or directories to read, read from stdin if none are given
+    paths: Vec<PathBuf>,
 
-    #[arg(long, short)]
-    describe: bool,
+    /// Only read files matching this glob, e.g. '*-raw.fif' (all .fif files by default)
+    #[arg(long)]
+    glob: Vec<String>,
 
-    #[arg(long, short)]
-    log: Option<LevelFilter>,
-}
+    /// Leave out files matching this glob, e.g. '*-1.fif'
+    #[arg(long)]
+    exclude: Vec<String>,
+
+    /// Follow symlinks when searching directories
+    #[arg(long)]
+    follow_links: bool,
 
-const MAX_LINES_IN: u32 = 20000;
+    /// Search hidden files and directories
+    #[arg(long)]
+    hidden: bool,
 
-fn strings_to_filepaths(input: Vec<String>) -> Vec<PathBuf> {
-    input
-        .iter()
-        .map(|x| Path::new(x).canonicalize().unwrap())
-        .filter(|x| x.is_file())
-        .collect()
+    /// Only search this many directories deep
+    #[arg(long)]
+    max_depth: Option<usize>,
 }
 
-fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
+#[derive(Subcommand)]
+enum Command {
+    /// Search files for tags, printing a row per file as CSV
+    Query {
+        #[command(flatten)]
+        input: Input,
 
-    let log_level = cli.log.unwrap_or(LevelFilter::Warn);
-    env_logger::Builder::new().filter_level(log_level).init();
+        /// Tags (or derived fields) to show, by name or code
+        #[arg(long, short)]
+        tags: Vec<String>,
 
-    let mut files: Vec<String> = vec![];
-    let mut line_count = 0;
+        /// Print the description of each tag before the results
+        #[arg(long, short)]
+        describe: bool,
 
-    // read files, either from stdin or parsing arguments
-    let files = if atty::isnt(Stream::Stdin) {
-        if !cli.files.is_empty() {
-            return Err(anyhow!(
-                "files should be supplied either from stdin or with --file (-f) argument, not both"
-            ));
-        }
+        /// Show times in the offset from UTC recorded in each file, rather than in UTC
+        #[arg(long)]
+        local_time: bool,
 
-        let stdin = io::stdin();
+        /// Only show files where a tag has the given value, as tag=value, e.g. subj_sex=female.
+        /// Short for --where 'tag = value', and combined with it and other filters by &&
+        #[arg(long)]
+        filter: Vec<String>,
 
-        for line in stdin.lock().lines() {
-            files.push(line?);
-            line_count += 1;
+        /// Only show files matching a condition, e.g. 'sfreq >= 1000 && bad_chs contains "MEG 2443"'
+        #[arg(long = "where", value_name = "CONDITION")]
+        condition: Option<String>,
 
-            if line_count > MAX_LINES_IN {
-                return Err(anyhow!(
-                    "refusing to read more than {MAX_LINES_IN} lines from stdin"
-                ));
-            }
-        }
+        /// Answer from this index, only reading files which aren't in it or have changed.
+        /// Without files, as arguments or on stdin, all indexed files are searched.  Channel info and data aren't indexed,
+        /// so asking for ch_info, n_meg, n_eeg, n_stim or duration still reads every file
+        #[arg(long)]
+        index: Option<PathBuf>,
+    },
+
+    /// Count files and summarize tags across them, grouped by the values of tags
+    Stats {
+        #[command(flatten)]
+        input: Input,
+
+        /// Group files by this tag, with a period for dates, e.g. device_model or meas_date:month
+        #[arg(long)]
+        group_by: Vec<String>,
+
+        /// Show the count, minimum, maximum and mean of this tag (or derived field)
+        #[arg(long)]
+        summary: Vec<String>,
+
+        /// Count files with each value of this tag
+        #[arg(long)]
+        histogram: Vec<String>,
+
+        /// Split histograms into this many bins of equal width
+        #[arg(long)]
+        bins: Option<usize>,
+
+        /// Count the files each item of this tag turns up in, e.g. bad_chs
+        #[arg(long)]
+        frequency: Vec<String>,
+
+        /// Only show this many of the most frequent items
+        #[arg(long)]
+        top: Option<usize>,
+
+        /// Only count files where a tag has the given value, as tag=value
+        #[arg(long)]
+        filter: Vec<String>,
+
+        /// Only count files matching a condition, as for query
+        #[arg(long = "where", value_name = "CONDITION")]
+        condition: Option<String>,
+
+        /// Answer from this index, as for query
+        #[arg(long)]
+        index: Option<PathBuf>,
+
+        /// Output format: csv or json
+        #[arg(long, default_value = "csv")]
+        format: StatsFormat,
+    },
+
+    /// Print the tree of blocks and tags of files
+    Tree {
+        #[command(flatten)]
+        input: Input,
+
+        /// Only show this many levels of blocks
+        #[arg(long)]
+        depth: Option<usize>,
+
+        /// Only show blocks, not the tags in them
+        #[arg(long)]
+        blocks_only: bool,
+
+        /// Only show tags of this kind, by name or code
+        #[arg(long)]
+        include: Vec<String>,
+
+        /// Leave out tags of this kind, by name or code
+        #[arg(long)]
+        hide: Vec<String>,
+
+        /// Show every tag, rather than collapsing runs of tags of the same kind
+        #[arg(long)]
+        expand: bool,
+
+        /// Output format: text, json or dot (Graphviz)
+        #[arg(long, default_value = "text")]
+        format: TreeFormat,
+    },
+
+    /// Print a summary of each measurement
+    Info {
+        #[command(flatten)]
+        input: Input,
+    },
+
+    /// List the channels of measurements as a BIDS-style channels.tsv
+    Channels {
+        #[command(flatten)]
+        input: Input,
+
+        /// Only channels of this type (e.g. eeg or MEGMAG), or with names matching this regex
+        #[arg(long)]
+        pick: Vec<String>,
+
+        /// Output format: tsv or csv
+        #[arg(long, default_value = "tsv")]
+        format: ChannelsFormat,
+    },
+
+    /// Export the digitized fiducials, HPI coils, EEG electrodes and head shape points of a file
+    Dig {
+        file: PathBuf,
+
+        /// Output format: csv, pos (head shape in cm) or bids (electrodes.tsv and coordsystem.json)
+        #[arg(long, default_value = "csv")]
+        format: DigFormat,
+
+        /// Coordinate frame to give points in, e.g. head, device or mri
+        #[arg(long)]
+        frame: Option<String>,
+
+        /// Also use the transformations in this file, e.g. a -trans.fif from co-registration
+        #[arg(long)]
+        trans: Option<PathBuf>,
+
+        /// File to write, or the prefix of the files for bids, e.g. sub-01_ses-01
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+
+    /// List every tag in files as read, with its position, header and the start of its data
+    Dump {
+        #[command(flatten)]
+        input: Input,
+
+        /// Only tags with headers at or after this byte
+        #[arg(long)]
+        from: Option<u64>,
+
+        /// Only tags with headers before this byte
+        #[arg(long)]
+        to: Option<u64>,
+
+        /// Only tags with this name or code, e.g. ch_info, block_start or 3999
+        #[arg(long)]
+        code: Vec<String>,
+
+        /// Number of bytes of data to show for each tag
+        #[arg(long, default_value_t = 16)]
+        preview: usize,
+    },
+
+    /// Search the dictionaries of tags, blocks and types, or list all tags
+    #[command(visible_alias = "describe")]
+    Tags {
+        /// Names, codes or parts of descriptions to look for
+        terms: Vec<String>,
+
+        /// List every block
+        #[arg(long)]
+        blocks: bool,
+
+        /// List every primitive type
+        #[arg(long)]
+        dtypes: bool,
+
+        /// List the tags whose data is of this type, e.g. float or julian
+        #[arg(long)]
+        dtype: Option<String>,
+    },
 
-        files
+    /// Check the structure of files, failing if any have problems
+    Validate {
+        #[command(flatten)]
+        input: Input,
+    },
+
+    /// Keep an index of the tags in files, for fast queries with --index
+    Index {
+        #[command(subcommand)]
+        action: IndexAction,
+    },
+
+    /// Compare two files block by block, failing if they differ
+    Diff {
+        a: PathBuf,
+        b: PathBuf,
+
+        /// Relative difference below which floats are taken to be the same
+        #[arg(long, default_value_t = DEFAULT_TOLERANCE)]
+        tolerance: f32,
+
+        /// Compare the contents of data buffers too
+        #[arg(long)]
+        data: bool,
+
+        /// Don't compare the values of tags of this kind, by name or code
+        #[arg(long)]
+        ignore: Vec<String>,
+
+        /// Output format: text or json
+        #[arg(long, default_value = "text")]
+        format: DiffFormat,
+    },
+
+    /// Write copies of files with subject and date identifiers removed
+    Anonymize {
+        #[command(flatten)]
+        input: Input,
+
+        /// Directory for the anonymized files, which keep their names
+        #[arg(long, short, required_unless_present = "in_place")]
+        output_dir: Option<PathBuf>,
+
+        /// Overwrite the input files instead of writing copies
+        #[arg(long, conflicts_with = "output_dir")]
+        in_place: bool,
+
+        /// Number of days to shift dates by, negative to move them back
+        #[arg(long, allow_hyphen_values = true)]
+        shift_days: i64,
+
+        /// Tags to leave untouched
+        #[arg(long)]
+        keep: Vec<String>,
+
+        /// Additional tags to remove
+        #[arg(long)]
+        drop: Vec<String>,
+
+        /// Replace a tag's value instead of removing it, as tag=value
+        #[arg(long)]
+        replace: Vec<String>,
+
+        /// Save the report of changed tags here instead of printing it
+        #[arg(long)]
+        report: Option<PathBuf>,
+    },
+
+    /// Change the value of a tag, overwriting it in place where possible
+    Set {
+        /// Tag to change, with an index in brackets if it occurs more than once, e.g. bad_chs[1]
+        selector: String,
+
+        /// New value, lists of numbers are separated by spaces or commas
+        #[arg(allow_hyphen_values = true)]
+        value: String,
+
+        #[command(flatten)]
+        input: Input,
+
+        /// Only show what would change
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum IndexAction {
+    /// Add files to an index, reading only those which are new or have changed
+    Update {
+        /// Index file, created if it doesn't exist
+        index: PathBuf,
+
+        #[command(flatten)]
+        input: Input,
+    },
+
+    /// Check that an index matches the files in it, failing if it doesn't
+    Verify {
+        /// Index file
+        index: PathBuf,
+    },
+
+    /// Read every file in an index again, dropping those which no longer exist
+    Rebuild {
+        /// Index file
+        index: PathBuf,
+    },
+}
+
+// read files from arguments, or from stdin if there are none.  Scripts often run with stdin
+// not a terminal, so arguments take precedence rather than being an error
+fn read_files(input: Input) -> anyhow::Result<Vec<PathBuf>> {
+    let paths = if input.paths.is_empty() && atty::isnt(Stream::Stdin) {
+        let lines: Vec<String> = io::stdin().lock().lines().collect::<Result<_, _>>()?;
+        lines.iter().map(PathBuf::from).collect()
     } else {
-        cli.files
+        input.paths
     };
 
-    if files.is_empty() {
+    if paths.is_empty() {
         return Err(anyhow!(
-            "Need at least one file to read.  Use --file (-f) or pass file list to stdin."
+            "Need at least one file to read.  Pass files or directories as arguments or a file list to stdin."
         ));
     }
 
-    let files = strings_to_filepaths(files);
-    let config = Config::new(files, cli.show_tree, cli.tags, cli.describe)?;
-    run(config)?;
-    Ok(())
+    let options = FileOptions {
+        globs: input.glob,
+        exclude: input.exclude,
+        follow_links: input.follow_links,
+        hidden: input.hidden,
+        max_depth: input.max_depth,
+    };
+    let files = find_files(&paths, &options)?;
+
+    if files.is_empty() {
+        return Err(anyhow!("No files found to read"));
+    }
+
+    Ok(files)
+}
+
+// an index can stand in for a list of files, when there are none as arguments or on stdin
+fn read_files_or_index(input: Input, index: &Option<PathBuf>) -> anyhow::Result<Vec<PathBuf>> {
+    match index {
+        Some(_) if input.paths.is_empty() && atty::is(Stream::Stdin) => Ok(vec![]),
+        _ => read_files(input),
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    let log_level = cli.log.unwrap_or(LevelFilter::Warn);
+    env_logger::Builder::new().filter_level(log_level).init();
+
+    let config = match cli.command {
+        Command::Query {
+            input,
+            tags,
+            describe,
+            local_time,
+            filter,
+            condition,
+            index,
+        } => {
+            let files = read_files_or_index(input, &index)?;
+            Config::Query(QueryConfig::new(
+                files, tags, describe, local_time, filter, condition, index,
+            )?)
+        }
+        Command::Stats {
+            input,
+            group_by,
+            summary,
+            histogram,
+            bins,
+            frequency,
+            top,
+            filter,
+            condition,
+            index,
+            format,
+        } => {
+            let files = read_files_or_index(input, &index)?;
+            Config::Stats(StatsConfig::new(
+                files, group_by, summary, histogram, bins, frequency, top, format, filter,
+                condition, index,
+            )?)
+        }
+        Command::Tree {
+            input,
+            depth,
+            blocks_only,
+            include,
+            hide,
+            expand,
+            format,
+        } => Config::Tree(TreeConfig::new(
+            read_files(input)?,
+            format,
+            depth,
+            blocks_only,
+            include,
+            hide,
+            expand,
+        )?),
+        Command::Info { input } => Config::Info(InfoConfig {
+            files: read_files(input)?,
+        }),
+        Command::Channels {
+            input,
+            pick,
+            format,
+        } => Config::Channels(ChannelsConfig::new(read_files(input)?, pick, format)?),
+        Command::Dig {
+            file,
+            format,
+            frame,
+            trans,
+            output,
+        } => Config::Dig(DigConfig::new(file, format, frame, trans, output)?),
+        Command::Dump {
+            input,
+            from,
+            to,
+            code,
+            preview,
+        } => Config::Dump(DumpConfig::new(
+            read_files(input)?,
+            from,
+            to,
+            code,
+            preview,
+        )?),
+        Command::Tags {
+            terms,
+            blocks,
+            dtypes,
+            dtype,
+        } => Config::Tags(TagsConfig::new(terms, blocks, dtypes, dtype)?),
+        Command::Validate { input } => Config::Validate(ValidateConfig {
+            files: read_files(input)?,
+        }),
+        Command::Index { action } => Config::Index(match action {
+            IndexAction::Update { index, input } => IndexConfig {
+                index,
+                action: IndexCommand::Update(read_files(input)?),
+            },
+            IndexAction::Verify { index } => IndexConfig {
+                index,
+                action: IndexCommand::Verify,
+            },
+            IndexAction::Rebuild { index } => IndexConfig {
+                index,
+                action: IndexCommand::Rebuild,
+            },
+        }),
+        Command::Diff {
+            a,
+            b,
+            tolerance,
+            data,
+            ignore,
+            format,
+        } => Config::Diff(DiffConfig::new(a, b, format, tolerance, data, ignore)?),
+        Command::Anonymize {
+            input,
+            output_dir,
+            in_place: _,
+            shift_days,
+            keep,
+            drop,
+            replace,
+            report,
+        } => Config::Anonymize(AnonymizeConfig::new(
+            read_files(input)?,
+            output_dir,
+            shift_days,
+            keep,
+            drop,
+            replace,
+            report,
+        )?),
+        Command::Set {
+            selector,
+            value,
+            input,
+            dry_run,
+        } => Config::Set(SetConfig::new(
+            read_files(input)?,
+            selector,
+            value,
+            dry_run,
+        )?),
+    };
+
+    run(config)
 }
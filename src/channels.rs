@@ -0,0 +1,566 @@
+//! A row per channel of a measurement, like the channels.tsv of BIDS, for `meginfo channels`.
+//!
+//! MEG coil positions and orientations are stored in device coordinates, and are also given in
+//! head coordinates when the file has the device to head transformation from its HPI fit.  EEG
+//! electrode positions are stored in head coordinates, and other channels have no position.
+
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use petgraph::stable_graph::NodeIndex;
+use regex::Regex;
+
+use crate::enums::{BlockKind, DataTagKind};
+use crate::graph::Tree;
+use crate::parser::FifParser;
+use crate::tag::{trim_string, ChInfo, CoordTrans, Data, FiffNode};
+use crate::values::{ChType, Coord, Unit};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ChannelsFormat {
+    #[default]
+    Tsv,
+    Csv,
+}
+
+impl FromStr for ChannelsFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "tsv" => ChannelsFormat::Tsv,
+            "csv" => ChannelsFormat::Csv,
+            _ => bail!("Unknown channels format {s}, expected tsv or csv"),
+        })
+    }
+}
+
+/// Which channels to list: those of a FIFF channel type (e.g. eeg), a BIDS type (e.g. MEGMAG),
+/// or with names matching a regex.
+#[derive(Debug, Clone)]
+pub enum Pick {
+    ChType(ChType),
+    BidsType(String),
+    Name(Regex),
+}
+
+impl Pick {
+    pub fn new(pick: &str) -> Result<Self> {
+        if let Ok(kind) = pick.parse::<ChType>() {
+            return Ok(Pick::ChType(kind));
+        }
+
+        let upper = pick.to_uppercase();
+        if BIDS_TYPES.contains(&upper.as_str()) {
+            return Ok(Pick::BidsType(upper));
+        }
+
+        let regex = Regex::new(pick).with_context(|| format!("Invalid regex {pick:?}"))?;
+        Ok(Pick::Name(regex))
+    }
+
+    pub fn matches(&self, channel: &Channel) -> bool {
+        match self {
+            Pick::ChType(kind) => channel.info.ch_type() == *kind,
+            Pick::BidsType(name) => channel.bids_type() == name,
+            Pick::Name(regex) => regex.is_match(channel.info.ch_name()),
+        }
+    }
+}
+
+// channel types used in BIDS, as MNE-BIDS maps FIFF channel types to them
+const BIDS_TYPES: [&str; 21] = [
+    "MEGMAG",
+    "MEGGRADPLANAR",
+    "MEGGRADAXIAL",
+    "MEGREFMAG",
+    "MEGREFGRADAXIAL",
+    "MEGOTHER",
+    "EEG",
+    "EOG",
+    "ECG",
+    "EMG",
+    "TRIG",
+    "MISC",
+    "RESP",
+    "SEEG",
+    "DBS",
+    "ECOG",
+    "TEMP",
+    "GSR",
+    "EYEGAZE",
+    "FITERR",
+    "OTHER",
+];
+
+/// Position of a coil or electrode (r0), and for MEG coils their orientation, the normal of the
+/// coil (ez).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Location {
+    pub position: [f32; 3],
+    pub orientation: Option<[f32; 3]>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Channel {
+    /// Position in the file's list of channels, counting from 0
+    pub index: usize,
+    pub info: ChInfo,
+    pub bad: bool,
+    /// Location in device coordinates, for MEG channels
+    pub device: Option<Location>,
+    /// Location in head coordinates, for EEG channels and MEG channels if the device to head
+    /// transformation is known
+    pub head: Option<Location>,
+}
+
+impl Channel {
+    /// Channel type as named in BIDS, e.g. MEGGRADPLANAR for a planar gradiometer.
+    pub fn bids_type(&self) -> &'static str {
+        let coil = self.info.coil().to_string();
+
+        match self.info.ch_type() {
+            ChType::Meg if coil.contains("planar") => "MEGGRADPLANAR",
+            ChType::Meg if coil.contains("grad") => "MEGGRADAXIAL",
+            ChType::Meg => "MEGMAG",
+            ChType::RefMeg if coil.contains("grad") => "MEGREFGRADAXIAL",
+            ChType::RefMeg => "MEGREFMAG",
+            ChType::Ias | ChType::Syst | ChType::Exci => "MEGOTHER",
+            ChType::Eeg => "EEG",
+            ChType::Eog => "EOG",
+            ChType::Ecg => "ECG",
+            ChType::Emg => "EMG",
+            ChType::Stim => "TRIG",
+            ChType::Misc | ChType::Bio => "MISC",
+            ChType::Resp => "RESP",
+            ChType::Seeg => "SEEG",
+            ChType::Dbs => "DBS",
+            ChType::Ecog => "ECOG",
+            ChType::Temperature => "TEMP",
+            ChType::Galvanic => "GSR",
+            ChType::Eyetrack => "EYEGAZE",
+            ChType::GoodnessFit => "FITERR",
+            _ => "OTHER",
+        }
+    }
+
+    /// Unit with the decimal prefix given by unit_mul, e.g. fT for T with a unit_mul of -15, or
+    /// None for channels without a unit (e.g. triggers).
+    pub fn units(&self) -> Option<String> {
+        let unit = self.info.unit();
+        if matches!(unit, Unit::None | Unit::Unitless) {
+            return None;
+        }
+
+        let prefix = match self.info.unit_mul {
+            -15 => "f",
+            -12 => "p",
+            -9 => "n",
+            -6 => "µ",
+            -3 => "m",
+            3 => "k",
+            6 => "M",
+            _ => "",
+        };
+
+        Some(format!("{prefix}{unit}"))
+    }
+}
+
+/// The channels of a measurement, with the sampling frequency and filter they were recorded with.
+#[derive(Debug, Clone)]
+pub struct Channels {
+    pub file: PathBuf,
+    pub channels: Vec<Channel>,
+    pub sfreq: Option<f32>,
+    pub highpass: Option<f32>,
+    pub lowpass: Option<f32>,
+    pub device_to_head: Option<CoordTrans>,
+}
+
+impl Channels {
+    pub fn read(file: &Path) -> Result<Self> {
+        let tree = FifParser::parse(file.to_owned())?;
+        Ok(Self::from_tree(file, &tree))
+    }
+
+    pub fn from_tree(file: &Path, tree: &Tree<FiffNode>) -> Self {
+        let meas_info = tree
+            .blocks(&BlockKind::MeasInfo)
+            .first()
+            .copied()
+            .unwrap_or(tree.root);
+
+        let float = |kind| match tree.find_tag(meas_info, kind) {
+            Some(Data::Float(x)) => x.first().copied(),
+            _ => None,
+        };
+
+        let device_to_head = device_to_head(tree, meas_info);
+        let bads = Bads::new(tree, meas_info);
+
+        let infos = tree
+            .tags_in(meas_info, &DataTagKind::ChInfo)
+            .into_iter()
+            .flat_map(|x| match x {
+                Data::ChInfoStruct(x) => x.clone(),
+                _ => vec![],
+            });
+
+        let channels = infos
+            .enumerate()
+            .map(|(index, info)| {
+                let (device, head) = locations(&info, device_to_head.as_ref());
+
+                Channel {
+                    index,
+                    bad: bads.contains(&info),
+                    info,
+                    device,
+                    head,
+                }
+            })
+            .collect();
+
+        Channels {
+            file: file.to_owned(),
+            channels,
+            sfreq: float(&DataTagKind::Sfreq),
+            highpass: float(&DataTagKind::Highpass),
+            lowpass: float(&DataTagKind::Lowpass),
+            device_to_head,
+        }
+    }
+
+    /// Keep only the channels matching any of the picks, or all of them if there are none.
+    pub fn pick(mut self, picks: &[Pick]) -> Self {
+        if !picks.is_empty() {
+            self.channels
+                .retain(|x| picks.iter().any(|pick| pick.matches(x)));
+        }
+        self
+    }
+}
+
+// locations in device and head coordinates, depending on the frame a channel's is stored in
+fn locations(
+    info: &ChInfo,
+    device_to_head: Option<&CoordTrans>,
+) -> (Option<Location>, Option<Location>) {
+    match info.ch_type() {
+        ChType::Meg | ChType::RefMeg => {
+            let device = Location {
+                position: info.r0,
+                orientation: Some(info.ez),
+            };
+            let head = device_to_head.map(|x| Location {
+                position: x.apply(info.r0),
+                orientation: Some(x.rotate(info.ez)),
+            });
+            (Some(device), head)
+        }
+        // the rest of an EEG channel's location is the reference electrode, not an orientation
+        ChType::Eeg => {
+            let head = Location {
+                position: info.r0,
+                orientation: None,
+            };
+            (None, Some(head))
+        }
+        _ => (None, None),
+    }
+}
+
+// the device to head transformation, which may be stored the other way round
+fn device_to_head(tree: &Tree<FiffNode>, meas_info: NodeIndex) -> Option<CoordTrans> {
+    tree.tags_in(meas_info, &DataTagKind::CoordTrans)
+        .into_iter()
+        .find_map(|x| match x {
+            Data::CoordTransStruct(x) => match (x.from_frame(), x.to_frame()) {
+                (Coord::Device, Coord::Head) => Some(x.clone()),
+                (Coord::Head, Coord::Device) => Some(x.inverse()),
+                _ => None,
+            },
+            _ => None,
+        })
+}
+
+// bad channels, from MNE's list of names if there is one, or else bad_chs, which holds channel
+// (scan) numbers in MEGIN files, or names in some others
+enum Bads {
+    Names(Vec<String>),
+    Numbers(Vec<i32>),
+}
+
+impl Bads {
+    fn new(tree: &Tree<FiffNode>, meas_info: NodeIndex) -> Self {
+        let mne = tree
+            .blocks(&BlockKind::BadChannels)
+            .into_iter()
+            .find_map(|x| tree.tag_in(x, &DataTagKind::MneChNameList));
+
+        match mne.or_else(|| tree.find_tag(meas_info, &DataTagKind::BadChs)) {
+            Some(Data::String(names)) => Bads::Names(
+                trim_string(names)
+                    .split(':')
+                    .filter(|x| !x.is_empty())
+                    .map(String::from)
+                    .collect(),
+            ),
+            Some(Data::Int32(numbers)) => Bads::Numbers(numbers.clone()),
+            _ => Bads::Names(vec![]),
+        }
+    }
+
+    fn contains(&self, info: &ChInfo) -> bool {
+        match self {
+            Bads::Names(names) => names.iter().any(|x| x == info.ch_name()),
+            Bads::Numbers(numbers) => numbers.contains(&info.scan_no),
+        }
+    }
+}
+
+const HEADER: [&str; 24] = [
+    "name",
+    "type",
+    "units",
+    "low_cutoff",
+    "high_cutoff",
+    "sampling_frequency",
+    "status",
+    "index",
+    "kind",
+    "coil_type",
+    "cal",
+    "range",
+    "x_device",
+    "y_device",
+    "z_device",
+    "ox_device",
+    "oy_device",
+    "oz_device",
+    "x_head",
+    "y_head",
+    "z_head",
+    "ox_head",
+    "oy_head",
+    "oz_head",
+];
+
+/// A table of channels, with a leading file column if they come from more than one file.
+///
+/// The columns BIDS defines come first, so that a single file's table can be used as its
+/// channels.tsv.  Missing values are n/a in TSV, as BIDS has them, and empty in CSV.
+pub fn to_table(files: &[Channels], format: ChannelsFormat) -> Result<String> {
+    let (delimiter, missing) = match format {
+        ChannelsFormat::Tsv => (b'\t', "n/a"),
+        ChannelsFormat::Csv => (b',', ""),
+    };
+    let with_file = files.len() > 1;
+
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(vec![]);
+
+    let mut header = HEADER.to_vec();
+    if with_file {
+        header.insert(0, "file");
+    }
+    wtr.write_record(header)?;
+
+    let show = |x: Option<String>| x.unwrap_or_else(|| missing.to_owned());
+    let floats = |x: Option<&[f32; 3]>| -> Vec<String> {
+        (0..3).map(|i| show(x.map(|x| x[i].to_string()))).collect()
+    };
+
+    for file in files {
+        for channel in &file.channels {
+            let mut row = vec![];
+            if with_file {
+                row.push(file.file.display().to_string());
+            }
+
+            row.extend([
+                channel.info.ch_name().to_owned(),
+                channel.bids_type().to_owned(),
+                show(channel.units()),
+                show(file.highpass.map(|x| x.to_string())),
+                show(file.lowpass.map(|x| x.to_string())),
+                show(file.sfreq.map(|x| x.to_string())),
+                String::from(if channel.bad { "bad" } else { "good" }),
+                channel.index.to_string(),
+                channel.info.ch_type().to_string(),
+                channel.info.coil().to_string(),
+                channel.info.cal.to_string(),
+                channel.info.range.to_string(),
+            ]);
+            for location in [&channel.device, &channel.head] {
+                row.extend(floats(location.as_ref().map(|x| &x.position)));
+                row.extend(floats(
+                    location.as_ref().and_then(|x| x.orientation.as_ref()),
+                ));
+            }
+
+            wtr.write_record(row)?;
+        }
+    }
+
+    Ok(String::from_utf8(wtr.into_inner()?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::values::Coil;
+
+    fn ch_info(name: &str, kind: ChType, coil: Coil, unit: Unit, scan_no: i32) -> ChInfo {
+        ChInfo {
+            scan_no,
+            log_no: scan_no,
+            kind: kind.to_code(),
+            range: 1.0,
+            cal: 2.0,
+            coil_type: coil.to_code(),
+            r0: [0.1, 0.0, 0.0],
+            ex: [1.0, 0.0, 0.0],
+            ey: [0.0, 1.0, 0.0],
+            ez: [0.0, 0.0, 1.0],
+            unit: unit.to_code(),
+            unit_mul: 0,
+            name: name.to_owned(),
+        }
+    }
+
+    // a measurement with a gradiometer, a magnetometer, an EEG and a trigger channel, and a
+    // device to head transformation which moves everything 1 cm up
+    fn tree(bads: Option<Data>) -> Tree<FiffNode> {
+        let channels = vec![
+            ch_info("MEG 0113", ChType::Meg, Coil::VvPlanarT1, Unit::TM, 1),
+            ch_info("MEG 0111", ChType::Meg, Coil::VvMagT1, Unit::T, 2),
+            ch_info("EEG 001", ChType::Eeg, Coil::Eeg, Unit::V, 3),
+            ch_info("STI 014", ChType::Stim, Coil::None, Unit::None, 4),
+        ];
+        let identity = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+        let trans = CoordTrans {
+            from: Coord::Device.to_code(),
+            to: Coord::Head.to_code(),
+            rot: identity,
+            translation: [0.0, 0.0, 0.01],
+            inv_rot: identity,
+            inv_translation: [0.0, 0.0, -0.01],
+        };
+
+        let mut tree = Tree::new();
+        let block = tree.add_child(FiffNode::Block {
+            kind: BlockKind::MeasInfo,
+        });
+        tree.move_to(block);
+
+        let mut tags = vec![
+            (DataTagKind::Sfreq, Data::Float(vec![1000.0])),
+            (DataTagKind::ChInfo, Data::ChInfoStruct(channels)),
+            (DataTagKind::CoordTrans, Data::CoordTransStruct(trans)),
+        ];
+        tags.extend(bads.map(|x| (DataTagKind::BadChs, x)));
+        for (kind, data) in tags {
+            tree.add_child(FiffNode::Tag { kind, data });
+        }
+
+        tree
+    }
+
+    fn names(channels: &Channels) -> Vec<&str> {
+        channels.channels.iter().map(|x| x.info.ch_name()).collect()
+    }
+
+    #[test]
+    fn can_read_channels() {
+        let channels = Channels::from_tree(Path::new("test.fif"), &tree(None));
+
+        let types: Vec<&str> = channels.channels.iter().map(|x| x.bids_type()).collect();
+        assert_eq!(types, ["MEGGRADPLANAR", "MEGMAG", "EEG", "TRIG"]);
+
+        let grad = &channels.channels[0];
+        assert_eq!(grad.units().as_deref(), Some("T/m"));
+        assert_eq!(channels.channels[3].units(), None);
+        assert_eq!(grad.device.unwrap().position, [0.1, 0.0, 0.0]);
+        assert_eq!(grad.head.unwrap().position, [0.1, 0.0, 0.01]);
+        assert_eq!(grad.head.unwrap().orientation, Some([0.0, 0.0, 1.0]));
+        assert!(!grad.bad);
+
+        // EEG positions are already in head coordinates, and have no orientation
+        let eeg = &channels.channels[2];
+        assert_eq!(eeg.device, None);
+        assert_eq!(
+            eeg.head,
+            Some(Location {
+                position: [0.1, 0.0, 0.0],
+                orientation: None
+            })
+        );
+
+        let stim = &channels.channels[3];
+        assert_eq!((stim.device, stim.head), (None, None));
+    }
+
+    #[test]
+    fn can_find_bad_channels() {
+        let bads = |data| {
+            let channels = Channels::from_tree(Path::new("test.fif"), &tree(Some(data)));
+            channels
+                .channels
+                .iter()
+                .filter(|x| x.bad)
+                .map(|x| x.info.ch_name().to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(bads(Data::Int32(vec![2, 3])), ["MEG 0111", "EEG 001"]);
+        assert_eq!(bads(Data::String("STI 014".into())), ["STI 014"]);
+    }
+
+    #[test]
+    fn can_pick_channels() {
+        let pick = |picks: &[&str]| {
+            let picks: Vec<Pick> = picks.iter().map(|x| Pick::new(x).unwrap()).collect();
+            Channels::from_tree(Path::new("test.fif"), &tree(None)).pick(&picks)
+        };
+
+        assert_eq!(names(&pick(&["meg"])), ["MEG 0113", "MEG 0111"]);
+        assert_eq!(names(&pick(&["megmag", "stim"])), ["MEG 0111", "STI 014"]);
+        assert_eq!(names(&pick(&["^EEG"])), ["EEG 001"]);
+        assert_eq!(names(&pick(&[])).len(), 4);
+        assert!(Pick::new("(").is_err());
+    }
+
+    #[test]
+    fn can_write_table() {
+        let channels = Channels::from_tree(Path::new("test.fif"), &tree(None));
+        let table = to_table(std::slice::from_ref(&channels), ChannelsFormat::Tsv).unwrap();
+        let lines: Vec<&str> = table.lines().collect();
+
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].starts_with("name\ttype\tunits\tlow_cutoff"));
+        assert!(
+            lines[3].starts_with("EEG 001\tEEG\tV\tn/a\tn/a\t1000\tgood\t2\tEEG\teeg\t"),
+            "{}",
+            lines[3]
+        );
+
+        // the EEG electrode only has a position in head coordinates
+        let eeg: Vec<&str> = lines[3].split('\t').skip(12).collect();
+        let device = ["n/a"; 6];
+        let head = ["0.1", "0", "0", "n/a", "n/a", "n/a"];
+        assert_eq!(eeg, [device, head].concat());
+
+        // several files get a file column
+        let table = to_table(&[channels.clone(), channels], ChannelsFormat::Csv).unwrap();
+        assert!(table.starts_with("file,name,type"));
+        assert!(table
+            .lines()
+            .nth(1)
+            .unwrap()
+            .starts_with("test.fif,MEG 0113,"));
+    }
+}
@@ -2,59 +2,484 @@
 
 use std::path::PathBuf;
 
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
 
-use crate::enums::DataTagKind;
-use crate::tag::{self, TagDef};
+use crate::anonymize::{Anonymizer, Rule};
+use crate::channels::{ChannelsFormat, Pick};
+use crate::dictionary::{self, Definition};
+use crate::diff::{DiffFormat, DiffOptions};
+use crate::dig::DigFormat;
+use crate::dump::DumpOptions;
+use crate::enums::{BlockTagKind, DataTagKind};
+use crate::expr::Expr;
+use crate::patch::Selector;
+use crate::query::Column;
+use crate::stats::{GroupKey, StatsFormat, StatsOptions};
+use crate::tag::{self, Data, TagDef};
+use crate::values::Coord;
+use crate::view::{TreeFormat, TreeOptions};
 
 use anyhow::Result;
 
+/// Configuration for each subcommand, see `crate::run`.
 #[derive(Debug)]
-pub struct Config {
+pub enum Config {
+    Query(QueryConfig),
+    Stats(StatsConfig),
+    Tree(TreeConfig),
+    Info(InfoConfig),
+    Channels(ChannelsConfig),
+    Dig(DigConfig),
+    Dump(DumpConfig),
+    Tags(TagsConfig),
+    Validate(ValidateConfig),
+    Diff(DiffConfig),
+    Index(IndexConfig),
+    Anonymize(AnonymizeConfig),
+    Set(SetConfig),
+}
+
+// a column and its description, for a tag or derived field given by name
+fn find_column(name: &str) -> Result<(Column, TagDef)> {
+    let column = Column::from_name(name)?;
+    let def = match &column {
+        Column::Derived(derived) => derived.tag_def(),
+        Column::Tag(kind) => TagDef::from(kind),
+    };
+
+    Ok((column, def))
+}
+
+// tag=value filters and a condition, all of which files have to match
+fn combine(filters: Vec<String>, condition: Option<String>) -> Result<Option<Expr>> {
+    let filters = filters.iter().map(|x| Expr::filter(x));
+    let condition = condition.map(|x| Expr::parse(&x));
+
+    Ok(Expr::all(filters.chain(condition).collect::<Result<_>>()?))
+}
+
+#[derive(Debug)]
+pub struct QueryConfig {
     pub files: Vec<PathBuf>,
-    pub query_codes: Vec<DataTagKind>,
-    pub show_tree: bool,
+    pub columns: Vec<Column>,
     pub describe_tags: Vec<TagDef>,
+    pub local_time: bool,
+    pub condition: Option<Expr>,
+    pub index: Option<PathBuf>,
 }
 
-impl Config {
+impl QueryConfig {
     pub fn new(
         files: Vec<PathBuf>,
-        show_tree: bool,
         query_tags: Vec<String>,
         describe: bool,
-    ) -> Result<Config> {
-        let string_to_tag = tag::read_tag_dict();
-        let query_codes: Result<Vec<&TagDef>> = query_tags
+        local_time: bool,
+        filters: Vec<String>,
+        condition: Option<String>,
+        index: Option<PathBuf>,
+    ) -> Result<QueryConfig> {
+        let mut columns = vec![];
+        let mut describe_tags = vec![];
+
+        for name in query_tags.iter() {
+            let (column, def) = find_column(name)?;
+            columns.push(column);
+            describe_tags.push(def);
+        }
+
+        if !describe {
+            describe_tags.clear();
+        }
+
+        Ok(QueryConfig {
+            files,
+            columns,
+            describe_tags,
+            local_time,
+            condition: combine(filters, condition)?,
+            index,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct StatsConfig {
+    pub files: Vec<PathBuf>,
+    pub options: StatsOptions,
+    pub format: StatsFormat,
+    pub condition: Option<Expr>,
+    pub index: Option<PathBuf>,
+}
+
+impl StatsConfig {
+    /// Tags to group by and work out statistics of are given by name or code, with a period
+    /// (e.g. meas_date:month) for dates to group by.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        files: Vec<PathBuf>,
+        group_by: Vec<String>,
+        summary: Vec<String>,
+        histogram: Vec<String>,
+        bins: Option<usize>,
+        frequency: Vec<String>,
+        top: Option<usize>,
+        format: StatsFormat,
+        filters: Vec<String>,
+        condition: Option<String>,
+        index: Option<PathBuf>,
+    ) -> Result<StatsConfig> {
+        let columns = |names: Vec<String>| -> Result<Vec<Column>> {
+            names.iter().map(|x| Column::from_name(x)).collect()
+        };
+
+        Ok(StatsConfig {
+            files,
+            options: StatsOptions {
+                group_by: group_by
+                    .iter()
+                    .map(|x| GroupKey::new(x))
+                    .collect::<Result<_>>()?,
+                summary: columns(summary)?,
+                histogram: columns(histogram)?,
+                bins,
+                frequency: columns(frequency)?,
+                top,
+            },
+            format,
+            condition: combine(filters, condition)?,
+            index,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct TreeConfig {
+    pub files: Vec<PathBuf>,
+    pub format: TreeFormat,
+    pub options: TreeOptions,
+}
+
+impl TreeConfig {
+    /// Tags to include or hide are given by name or code.
+    pub fn new(
+        files: Vec<PathBuf>,
+        format: TreeFormat,
+        max_depth: Option<usize>,
+        blocks_only: bool,
+        include: Vec<String>,
+        hide: Vec<String>,
+        expand: bool,
+    ) -> Result<TreeConfig> {
+        let find = |names: Vec<String>| -> Result<Vec<DataTagKind>> {
+            names.iter().map(|x| tag::find_tag(x)).collect()
+        };
+
+        Ok(TreeConfig {
+            files,
+            format,
+            options: TreeOptions {
+                max_depth,
+                blocks_only,
+                include: find(include)?,
+                hide: find(hide)?,
+                expand,
+            },
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct InfoConfig {
+    pub files: Vec<PathBuf>,
+}
+
+#[derive(Debug)]
+pub struct ChannelsConfig {
+    pub files: Vec<PathBuf>,
+    pub picks: Vec<Pick>,
+    pub format: ChannelsFormat,
+}
+
+impl ChannelsConfig {
+    /// Channels are picked by FIFF or BIDS channel type, or by a regex matching their names.
+    pub fn new(
+        files: Vec<PathBuf>,
+        picks: Vec<String>,
+        format: ChannelsFormat,
+    ) -> Result<ChannelsConfig> {
+        Ok(ChannelsConfig {
+            files,
+            picks: picks.iter().map(|x| Pick::new(x)).collect::<Result<_>>()?,
+            format,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct DigConfig {
+    pub file: PathBuf,
+    pub format: DigFormat,
+    pub frame: Option<Coord>,
+    pub trans: Option<PathBuf>,
+    pub output: Option<PathBuf>,
+}
+
+impl DigConfig {
+    /// The frame is given by name (e.g. mri) or code.  BIDS output is two files, so it needs an
+    /// output prefix.
+    pub fn new(
+        file: PathBuf,
+        format: DigFormat,
+        frame: Option<String>,
+        trans: Option<PathBuf>,
+        output: Option<PathBuf>,
+    ) -> Result<DigConfig> {
+        if format == DigFormat::Bids && output.is_none() {
+            return Err(anyhow!(
+                "BIDS output is written to files, give a prefix for them with --output, e.g. sub-01_ses-01"
+            ));
+        }
+
+        let frame = frame
+            .map(|x| match x.parse::<i32>() {
+                Ok(code) => Ok(Coord::from_code(code)),
+                Err(_) => x
+                    .parse()
+                    .map_err(|_| anyhow!("Unrecognized coordinate frame: {x:?}")),
+            })
+            .transpose()?;
+
+        Ok(DigConfig {
+            file,
+            format,
+            frame,
+            trans,
+            output,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct DumpConfig {
+    pub files: Vec<PathBuf>,
+    pub options: DumpOptions,
+}
+
+impl DumpConfig {
+    /// Tags to show can be given by name (including block_start and block_end) or code.
+    pub fn new(
+        files: Vec<PathBuf>,
+        from: Option<u64>,
+        to: Option<u64>,
+        codes: Vec<String>,
+        preview: usize,
+    ) -> Result<DumpConfig> {
+        let codes = codes
             .iter()
-            .map(|x| {
-                string_to_tag.get(x).ok_or(anyhow!(
-                    "Unrecognized tag: {:?}. See fiff/tags.tsv for a list of valid names.",
-                    x
-                ))
+            .map(|x| match x.as_str() {
+                "block_start" => Ok(BlockTagKind::BlockStart.to_code()),
+                "block_end" => Ok(BlockTagKind::BlockEnd.to_code()),
+                name => tag::find_tag(name).map(|x| x.to_code()),
             })
-            .collect();
+            .collect::<Result<_>>()?;
 
-        let query_codes = query_codes?;
+        Ok(DumpConfig {
+            files,
+            options: DumpOptions {
+                from,
+                to,
+                codes,
+                preview,
+            },
+        })
+    }
+}
 
-        let describe_tags: Vec<TagDef> = if describe {
-            query_codes.clone().into_iter().cloned().collect()
-        } else {
-            vec![]
-        };
+#[derive(Debug)]
+pub struct TagsConfig {
+    /// Definitions to show, every tag and derived field if nothing was asked for.
+    pub definitions: Vec<Definition>,
+}
 
-        let query_codes: Result<Vec<DataTagKind>> = query_codes
-            .into_iter()
-            .map(|x| DataTagKind::from_code(x.code))
-            .collect();
+impl TagsConfig {
+    /// Each search term gives everything in the dictionaries matching it, see
+    /// `dictionary::search`.  Blocks, types, or the tags using a type can be listed instead.
+    pub fn new(
+        terms: Vec<String>,
+        blocks: bool,
+        dtypes: bool,
+        dtype: Option<String>,
+    ) -> Result<TagsConfig> {
+        let all = Definition::all();
+        let mut definitions = vec![];
+
+        if let Some(dtype) = dtype {
+            let dtype = dictionary::find_dtype(&dtype)?;
+            definitions.extend(
+                dictionary::tags_with_dtype(&dtype)
+                    .into_iter()
+                    .map(Definition::Tag),
+            );
+        }
+        if blocks {
+            definitions.extend(
+                all.iter()
+                    .filter(|x| matches!(x, Definition::Block(_)))
+                    .cloned(),
+            );
+        }
+        if dtypes {
+            definitions.extend(
+                all.iter()
+                    .filter(|x| matches!(x, Definition::DType(_)))
+                    .cloned(),
+            );
+        }
+
+        for term in &terms {
+            let found = dictionary::search(term);
+            if found.is_empty() {
+                return Err(anyhow!("Nothing in the dictionaries matches {term:?}"));
+            }
+            definitions.extend(found);
+        }
+
+        if definitions.is_empty() {
+            definitions = all
+                .into_iter()
+                .filter(|x| matches!(x, Definition::Tag(_) | Definition::Derived(_)))
+                .collect();
+        }
+
+        Ok(TagsConfig { definitions })
+    }
+}
+
+#[derive(Debug)]
+pub struct ValidateConfig {
+    pub files: Vec<PathBuf>,
+}
+
+#[derive(Debug)]
+pub struct DiffConfig {
+    pub a: PathBuf,
+    pub b: PathBuf,
+    pub format: DiffFormat,
+    pub options: DiffOptions,
+}
 
-        let query_codes = query_codes?;
+impl DiffConfig {
+    /// Tags to ignore are given by name or code.
+    pub fn new(
+        a: PathBuf,
+        b: PathBuf,
+        format: DiffFormat,
+        tolerance: f32,
+        data: bool,
+        ignore: Vec<String>,
+    ) -> Result<DiffConfig> {
+        let ignore = ignore
+            .iter()
+            .map(|x| tag::find_tag(x))
+            .collect::<Result<_>>()?;
 
-        Ok(Config {
+        Ok(DiffConfig {
+            a,
+            b,
+            format,
+            options: DiffOptions {
+                tolerance,
+                data,
+                ignore,
+            },
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum IndexCommand {
+    /// Add these files, or bring them up to date
+    Update(Vec<PathBuf>),
+    Verify,
+    Rebuild,
+}
+
+#[derive(Debug)]
+pub struct IndexConfig {
+    pub index: PathBuf,
+    pub action: IndexCommand,
+}
+
+#[derive(Debug)]
+pub struct AnonymizeConfig {
+    pub files: Vec<PathBuf>,
+    pub output_dir: Option<PathBuf>,
+    pub anonymizer: Anonymizer,
+    pub report: Option<PathBuf>,
+}
+
+impl AnonymizeConfig {
+    /// Set up anonymization of files, either into output_dir or in place (if it's None).
+    ///
+    /// Rules for individual tags can be overridden by name, replacements are given as tag=value.
+    pub fn new(
+        files: Vec<PathBuf>,
+        output_dir: Option<PathBuf>,
+        shift_days: i64,
+        keep: Vec<String>,
+        drop: Vec<String>,
+        replace: Vec<String>,
+        report: Option<PathBuf>,
+    ) -> Result<AnonymizeConfig> {
+        let mut anonymizer = Anonymizer::new(shift_days);
+
+        for name in keep {
+            anonymizer = anonymizer.rule(tag::find_tag(&name)?, Rule::Keep);
+        }
+
+        for name in drop {
+            anonymizer = anonymizer.rule(tag::find_tag(&name)?, Rule::Drop);
+        }
+
+        for replacement in replace {
+            let (name, value) = replacement.split_once('=').ok_or(anyhow!(
+                "Replacements should look like tag=value, got {replacement:?}"
+            ))?;
+            let kind = tag::find_tag(name)?;
+            let data = Data::parse_for(&kind, value)
+                .with_context(|| format!("Invalid replacement {replacement:?}"))?;
+            anonymizer = anonymizer.rule(kind, Rule::Replace(data));
+        }
+
+        Ok(AnonymizeConfig {
             files,
-            show_tree,
-            query_codes,
-            describe_tags,
+            output_dir,
+            anonymizer,
+            report,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct SetConfig {
+    pub files: Vec<PathBuf>,
+    pub selector: Selector,
+    pub value: String,
+    pub dry_run: bool,
+}
+
+impl SetConfig {
+    pub fn new(
+        files: Vec<PathBuf>,
+        selector: String,
+        value: String,
+        dry_run: bool,
+    ) -> Result<SetConfig> {
+        Ok(SetConfig {
+            files,
+            selector: Selector::new(&selector)?,
+            value,
+            dry_run,
         })
     }
 }
@@ -0,0 +1,235 @@
+//! Dates and times as stored in .fif files.
+//!
+//! There are two encodings:
+//! - julian day numbers (dtype 6), used for subj_birth_day
+//! - unix time as seconds and microseconds, used for meas_date and the timestamps in ids
+//!
+//! Times are stored in UTC.  A file may also record the local offset at the time of measurement
+//! in utc_offset, as a string like +01:00.
+
+use std::fmt::Display;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, SecondsFormat, TimeZone, Utc};
+use log::warn;
+
+use crate::tag::display_vec;
+
+// julian day number of 31 December 1 BCE, i.e. day zero when counting days from the common era
+const JULIAN_DAY_CE: i32 = 1721425;
+
+/// Convert a julian day number into a calendar date.
+///
+/// Zero means the date was never set; it and anything outside chrono's range give None.
+pub fn decode_julian_date(day: i32) -> Option<NaiveDate> {
+    if day == 0 {
+        return None;
+    }
+
+    NaiveDate::from_num_days_from_ce_opt(day.checked_sub(JULIAN_DAY_CE)?)
+}
+
+/// Convert a calendar date into a julian day number, the inverse of `decode_julian_date`.
+pub fn encode_julian_date(date: NaiveDate) -> i32 {
+    date.num_days_from_ce() + JULIAN_DAY_CE
+}
+
+// values which can't be decoded are shown as the raw day number
+pub(crate) fn display_julian_dates(days: &[i32]) -> String {
+    let dates: Vec<String> = days
+        .iter()
+        .map(|&x| match decode_julian_date(x) {
+            Some(date) => date.to_string(),
+            None if x == 0 => String::from("unset"),
+            None => x.to_string(),
+        })
+        .collect();
+
+    display_vec(&dates)
+}
+
+// julian dates can be given either as YYYY-MM-DD or as day numbers
+pub(crate) fn parse_julian_dates(value: &str) -> Result<Vec<i32>> {
+    value
+        .split(|x: char| x == ',' || x.is_whitespace())
+        .filter(|x| !x.is_empty())
+        .map(|x| match NaiveDate::parse_from_str(x, "%Y-%m-%d") {
+            Ok(date) => Ok(encode_julian_date(date)),
+            Err(_) => x
+                .parse::<i32>()
+                .map_err(|e| anyhow!("{x:?} is not a date (YYYY-MM-DD) or julian day: {e}")),
+        })
+        .collect()
+}
+
+/// A point in time stored as seconds and microseconds since the unix epoch.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct MeasDate {
+    pub secs: i32,
+    pub usecs: i32,
+}
+
+/// The meas_date MNE-Python writes for a measurement without a date.
+pub const DATE_NONE: MeasDate = MeasDate {
+    secs: 0,
+    usecs: i32::MAX,
+};
+
+impl MeasDate {
+    /// Read the data of a meas_date tag: seconds, then microseconds (taken as 0 if missing).
+    pub fn from_ints(ints: &[i32]) -> Result<Self> {
+        match ints {
+            [secs] => Ok(MeasDate {
+                secs: *secs,
+                usecs: 0,
+            }),
+            [secs, usecs, ..] => Ok(MeasDate {
+                secs: *secs,
+                usecs: *usecs,
+            }),
+            [] => Err(anyhow!("meas_date has no data")),
+        }
+    }
+
+    pub fn is_unset(&self) -> bool {
+        *self == DATE_NONE
+    }
+
+    pub fn to_utc(self) -> Result<DateTime<Utc>> {
+        if self.is_unset() {
+            return Err(anyhow!("date is unset"));
+        }
+
+        if !(0..1_000_000).contains(&self.usecs) {
+            return Err(anyhow!(
+                "{} is not a valid number of microseconds",
+                self.usecs
+            ));
+        }
+
+        Utc.timestamp_opt(self.secs.into(), self.usecs as u32 * 1000)
+            .single()
+            .ok_or(anyhow!("{} seconds is out of range for a date", self.secs))
+    }
+
+    /// Format as RFC 3339, in UTC or in the given offset from it.
+    pub fn to_rfc3339(self, offset: Option<FixedOffset>) -> Result<String> {
+        let utc = self.to_utc()?;
+
+        Ok(match offset {
+            Some(offset) => utc
+                .with_timezone(&offset)
+                .to_rfc3339_opts(SecondsFormat::Micros, true),
+            None => utc.to_rfc3339_opts(SecondsFormat::Micros, true),
+        })
+    }
+
+    // dates which can't be decoded are shown as the raw numbers
+    pub(crate) fn display(self, offset: Option<FixedOffset>) -> String {
+        if self.is_unset() {
+            return String::from("unset");
+        }
+
+        self.to_rfc3339(offset).unwrap_or_else(|e| {
+            warn!("could not decode date: {e}");
+            format!("{} {}", self.secs, self.usecs)
+        })
+    }
+}
+
+impl Display for MeasDate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.display(None))
+    }
+}
+
+/// Parse the contents of a utc_offset tag, e.g. +01:00 or -05:30.
+pub fn parse_utc_offset(offset: &str) -> Result<FixedOffset> {
+    let invalid = || anyhow!("UTC offset should look like +HH:MM, got {offset:?}");
+
+    let trimmed = offset.trim_end_matches('\0').trim();
+    let (sign, rest) = if let Some(rest) = trimmed.strip_prefix('+') {
+        (1, rest)
+    } else if let Some(rest) = trimmed.strip_prefix('-') {
+        (-1, rest)
+    } else {
+        return Err(invalid());
+    };
+
+    let (hours, minutes) = rest.split_once(':').ok_or_else(invalid)?;
+    let hours: i32 = hours.parse().map_err(|_| invalid())?;
+    let minutes: i32 = minutes.parse().map_err(|_| invalid())?;
+
+    if !(0..60).contains(&minutes) {
+        return Err(invalid());
+    }
+
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60)).ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_decode_julian_date() {
+        assert_eq!(
+            decode_julian_date(2440588),
+            NaiveDate::from_ymd_opt(1970, 1, 1)
+        );
+        assert_eq!(
+            decode_julian_date(2451545),
+            NaiveDate::from_ymd_opt(2000, 1, 1)
+        );
+        assert_eq!(decode_julian_date(0), None);
+        assert_eq!(decode_julian_date(i32::MIN), None);
+        assert_eq!(decode_julian_date(i32::MAX), None);
+
+        let date = NaiveDate::from_ymd_opt(1987, 6, 5).unwrap();
+        assert_eq!(decode_julian_date(encode_julian_date(date)), Some(date));
+    }
+
+    #[test]
+    fn can_format_meas_date() {
+        let date = MeasDate::from_ints(&[1646226731, 138511]).unwrap();
+        assert_eq!(date.to_string(), "2022-03-02T13:12:11.138511Z");
+
+        let offset = parse_utc_offset("+01:00").unwrap();
+        assert_eq!(
+            date.to_rfc3339(Some(offset)).unwrap(),
+            "2022-03-02T14:12:11.138511+01:00"
+        );
+
+        let date = MeasDate::from_ints(&[1646226731]).unwrap();
+        assert_eq!(date.to_string(), "2022-03-02T13:12:11.000000Z");
+    }
+
+    #[test]
+    fn reports_bad_meas_dates() {
+        assert!(DATE_NONE.to_utc().is_err());
+        assert_eq!(DATE_NONE.to_string(), "unset");
+
+        let date = MeasDate::from_ints(&[1646226731, 2_000_000]).unwrap();
+        assert!(date.to_utc().is_err());
+        assert_eq!(date.to_string(), "1646226731 2000000");
+
+        assert!(MeasDate::from_ints(&[]).is_err());
+    }
+
+    #[test]
+    fn can_parse_utc_offset() {
+        assert_eq!(
+            parse_utc_offset("-05:30").unwrap(),
+            FixedOffset::west_opt(5 * 3600 + 1800).unwrap()
+        );
+        assert_eq!(
+            parse_utc_offset("+00:00\0").unwrap(),
+            FixedOffset::east_opt(0).unwrap()
+        );
+
+        assert!(parse_utc_offset("01:00").is_err());
+        assert!(parse_utc_offset("+1").is_err());
+        assert!(parse_utc_offset("+01:75").is_err());
+        assert!(parse_utc_offset("+99:00").is_err());
+    }
+}
@@ -0,0 +1,619 @@
+//! Fields which aren't stored in a .fif file, but can be worked out from tags that are.
+//!
+//! These can be queried by name alongside ordinary tags.  Each field implements `DerivedField`,
+//! and fields of your own can be added with `register`:
+//!
+//! ```
+//! use std::path::Path;
+//!
+//! use fiff::derived::{register, DerivedField};
+//! use fiff::enums::DataTagKind;
+//! use fiff::query::ResultSet;
+//! use fiff::tag::{Data, TagDef};
+//!
+//! struct Site;
+//!
+//! impl DerivedField for Site {
+//!     fn def(&self) -> TagDef {
+//!         TagDef::new("site", "string", "-", "Where the measurement was made")
+//!     }
+//!
+//!     fn requires(&self) -> Vec<DataTagKind> {
+//!         vec![DataTagKind::DeviceSerial]
+//!     }
+//!
+//!     fn compute(&self, _file: &Path, results: &ResultSet) -> Option<Data> {
+//!         match results.get(&DataTagKind::DeviceSerial)?.first()? {
+//!             Data::String(x) if x.trim_end_matches('\0') == "1234" => Some(Data::String("lab".into())),
+//!             _ => None,
+//!         }
+//!     }
+//! }
+//!
+//! register(Site).unwrap();
+//! ```
+
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+
+use crate::date::{decode_julian_date, MeasDate};
+use crate::enums::DataTagKind;
+use crate::info::sample_size;
+use crate::parser::FifParser;
+use crate::query::{ResultSet, Search};
+use crate::tag::{self, Data, TagDef};
+use crate::values::{ChType, Role};
+
+/// A field worked out from the tags of a file.
+pub trait DerivedField: Send + Sync {
+    /// Name, type, unit and description, for --describe.  The name is what's given to -t.
+    fn def(&self) -> TagDef;
+
+    /// Tags that have to be read to work out this field.
+    fn requires(&self) -> Vec<DataTagKind>;
+
+    /// Work out the field from the tags found in a file, None if any are missing or unset.
+    fn compute(&self, file: &Path, results: &ResultSet) -> Option<Data>;
+}
+
+/// A field from the registry, compared by name.
+#[derive(Clone)]
+pub struct Derived(Arc<dyn DerivedField>);
+
+// fields added by register, after the built in ones
+static REGISTERED: RwLock<Vec<Derived>> = RwLock::new(Vec::new());
+
+/// Add a field, which can then be queried by name.  Names can't be reused, or be those of tags.
+pub fn register(field: impl DerivedField + 'static) -> Result<()> {
+    let field = Derived(Arc::new(field));
+    let name = field.name();
+
+    if tag::find_tag(&name).is_ok() || Derived::from_name(&name).is_some() {
+        return Err(anyhow!(
+            "There is already a tag or derived field called {name}"
+        ));
+    }
+
+    REGISTERED.write().unwrap().push(field);
+    Ok(())
+}
+
+/// Every field, built in ones first.
+pub fn all() -> Vec<Derived> {
+    let builtin: [Arc<dyn DerivedField>; 9] = [
+        Arc::new(SubjAge),
+        Arc::new(Duration),
+        Arc::new(FileSize),
+        Arc::new(Channels("n_meg", ChType::Meg)),
+        Arc::new(Channels("n_eeg", ChType::Eeg)),
+        Arc::new(Channels("n_stim", ChType::Stim)),
+        Arc::new(SssApplied),
+        Arc::new(Splits),
+        Arc::new(Chpi),
+    ];
+
+    let mut fields: Vec<Derived> = builtin.into_iter().map(Derived).collect();
+    fields.extend(REGISTERED.read().unwrap().iter().cloned());
+    fields
+}
+
+impl Derived {
+    pub fn from_name(name: &str) -> Option<Self> {
+        all().into_iter().find(|x| x.name() == name)
+    }
+
+    pub fn name(&self) -> String {
+        self.0.def().name
+    }
+
+    /// Description in the same format as the tag dictionary, for --describe.
+    pub fn tag_def(&self) -> TagDef {
+        self.0.def()
+    }
+
+    /// Tags that have to be read to work out this field.
+    pub fn requires(&self) -> Vec<DataTagKind> {
+        self.0.requires()
+    }
+
+    /// Work out the field from the tags found in a file, None if any are missing or unset.
+    pub fn compute(&self, file: &Path, results: &ResultSet) -> Option<Data> {
+        self.0.compute(file, results)
+    }
+}
+
+impl Debug for Derived {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Derived({})", self.name())
+    }
+}
+
+impl PartialEq for Derived {
+    fn eq(&self, other: &Self) -> bool {
+        self.name() == other.name()
+    }
+}
+
+impl Eq for Derived {}
+
+impl Hash for Derived {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.name().hash(state);
+    }
+}
+
+impl Serialize for Derived {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.name())
+    }
+}
+
+fn first(results: &ResultSet, kind: DataTagKind) -> Option<&Data> {
+    results.get(&kind).and_then(|x| x.first())
+}
+
+fn first_int(results: &ResultSet, kind: DataTagKind) -> Option<i32> {
+    match first(results, kind)? {
+        Data::Int32(x) => x.first().copied(),
+        _ => None,
+    }
+}
+
+// 1 or 0, so that flags can be counted and averaged
+fn flag(set: bool) -> Data {
+    Data::Int32(vec![set.into()])
+}
+
+struct SubjAge;
+
+impl DerivedField for SubjAge {
+    fn def(&self) -> TagDef {
+        TagDef::new(
+            "subj_age",
+            "int32",
+            "years",
+            "Age of the subject at the start of the measurement (derived from meas_date and subj_birth_day)",
+        )
+    }
+
+    fn requires(&self) -> Vec<DataTagKind> {
+        vec![DataTagKind::MeasDate, DataTagKind::SubjBirthDay]
+    }
+
+    fn compute(&self, _file: &Path, results: &ResultSet) -> Option<Data> {
+        let measured = match first(results, DataTagKind::MeasDate)? {
+            Data::Int32(x) => MeasDate::from_ints(x).ok()?.to_utc().ok()?.date_naive(),
+            _ => return None,
+        };
+
+        let birthday = match first(results, DataTagKind::SubjBirthDay)? {
+            Data::JulianDate(x) | Data::Int32(x) => decode_julian_date(*x.first()?)?,
+            _ => return None,
+        };
+
+        let age = measured.years_since(birthday)?;
+        Some(Data::Int32(vec![age.try_into().ok()?]))
+    }
+}
+
+struct Duration;
+
+impl Duration {
+    // samples in the data buffers of raw data, including skipped ones.  Buffers are all the same
+    // length, so skips counted in buffers are as long as any of them.
+    fn raw_samples(results: &ResultSet) -> Option<u64> {
+        let nchan = first_int(results, DataTagKind::Nchan)?.max(1) as u64;
+
+        let buffers: Vec<u64> = results
+            .get(&DataTagKind::DataBuffer)?
+            .iter()
+            .map(|x| sample_size(x.dtype()).map_or(0, |size| x.size() / (size * nchan)))
+            .collect();
+
+        let count = |kind| -> u64 {
+            let skips = results.get(&kind).into_iter().flatten();
+            skips
+                .filter_map(|x| match x {
+                    Data::Int32(x) => x.first().map(|x| (*x).max(0) as u64),
+                    _ => None,
+                })
+                .sum()
+        };
+
+        let buffer = buffers.first().copied().unwrap_or(0);
+        Some(
+            buffers.iter().sum::<u64>()
+                + buffer * count(DataTagKind::DataSkip)
+                + count(DataTagKind::DataSkipSamp),
+        )
+    }
+}
+
+impl DerivedField for Duration {
+    fn def(&self) -> TagDef {
+        TagDef::new(
+            "duration",
+            "float",
+            "s",
+            "Length of the recording, from first_sample and last_sample or the size of the raw data, and sfreq",
+        )
+    }
+
+    fn requires(&self) -> Vec<DataTagKind> {
+        vec![
+            DataTagKind::Sfreq,
+            DataTagKind::FirstSample,
+            DataTagKind::LastSample,
+            DataTagKind::Nchan,
+            DataTagKind::DataBuffer,
+            DataTagKind::DataSkip,
+            DataTagKind::DataSkipSamp,
+        ]
+    }
+
+    fn compute(&self, _file: &Path, results: &ResultSet) -> Option<Data> {
+        let sfreq = match first(results, DataTagKind::Sfreq)? {
+            Data::Float(x) => x.first().copied().filter(|x| *x > 0.0)?,
+            _ => return None,
+        };
+
+        let last = first_int(results, DataTagKind::LastSample);
+        let samples = match last {
+            Some(last) => {
+                let first = first_int(results, DataTagKind::FirstSample)?;
+                (i64::from(last) - i64::from(first) + 1).max(0) as u64
+            }
+            None => Self::raw_samples(results)?,
+        };
+
+        Some(Data::Float(vec![
+            (samples as f64 / f64::from(sfreq)) as f32,
+        ]))
+    }
+}
+
+struct FileSize;
+
+impl DerivedField for FileSize {
+    fn def(&self) -> TagDef {
+        TagDef::new("file_size", "int32", "bytes", "Size of the file")
+    }
+
+    fn requires(&self) -> Vec<DataTagKind> {
+        vec![]
+    }
+
+    fn compute(&self, file: &Path, _results: &ResultSet) -> Option<Data> {
+        let size = file.metadata().ok()?.len();
+        Some(Data::Int32(vec![size.try_into().ok()?]))
+    }
+}
+
+// number of channels of a type
+struct Channels(&'static str, ChType);
+
+impl DerivedField for Channels {
+    fn def(&self) -> TagDef {
+        let description = format!("Number of {} channels (derived from ch_info)", self.1);
+        TagDef::new(self.0, "int32", "card", &description)
+    }
+
+    fn requires(&self) -> Vec<DataTagKind> {
+        vec![DataTagKind::ChInfo]
+    }
+
+    fn compute(&self, _file: &Path, results: &ResultSet) -> Option<Data> {
+        let channels = results.get(&DataTagKind::ChInfo)?;
+
+        let count = channels
+            .iter()
+            .flat_map(|x| match x {
+                Data::ChInfoStruct(x) => x.iter().collect(),
+                _ => vec![],
+            })
+            .filter(|x| x.ch_type() == self.1)
+            .count();
+
+        Some(Data::Int32(vec![count.try_into().ok()?]))
+    }
+}
+
+// tags which are only written in SSS info blocks
+const SSS_TAGS: [DataTagKind; 3] = [
+    DataTagKind::SssJob,
+    DataTagKind::SssFrame,
+    DataTagKind::SssOrigin,
+];
+
+struct SssApplied;
+
+impl DerivedField for SssApplied {
+    fn def(&self) -> TagDef {
+        TagDef::new(
+            "sss_applied",
+            "int32",
+            "-",
+            "1 if MaxFilter (SSS) has been applied to the data, otherwise 0",
+        )
+    }
+
+    fn requires(&self) -> Vec<DataTagKind> {
+        SSS_TAGS.to_vec()
+    }
+
+    fn compute(&self, _file: &Path, results: &ResultSet) -> Option<Data> {
+        Some(flag(SSS_TAGS.iter().any(|x| results.contains_key(x))))
+    }
+}
+
+struct Chpi;
+
+impl DerivedField for Chpi {
+    fn def(&self) -> TagDef {
+        TagDef::new(
+            "chpi",
+            "int32",
+            "-",
+            "1 if HPI coil frequencies are recorded, so head position can be tracked, otherwise 0",
+        )
+    }
+
+    fn requires(&self) -> Vec<DataTagKind> {
+        vec![DataTagKind::HpiCoilFreq]
+    }
+
+    fn compute(&self, _file: &Path, results: &ResultSet) -> Option<Data> {
+        Some(flag(results.contains_key(&DataTagKind::HpiCoilFreq)))
+    }
+}
+
+struct Splits;
+
+impl Splits {
+    // the file referred to with a role, relative to the file referring to it
+    fn referenced(file: &Path, results: &ResultSet, role: &Role) -> Option<PathBuf> {
+        let roles = results.get(&DataTagKind::RefRole)?;
+        let names = results.get(&DataTagKind::RefFileName)?;
+
+        // each reference block has a role and a file name
+        roles
+            .iter()
+            .zip(names)
+            .find_map(|(x, name)| match (x, name) {
+                (Data::Int32(x), Data::String(name)) if x.first() == Some(&role.to_code()) => {
+                    Some(file.with_file_name(tag::trim_string(name)))
+                }
+                _ => None,
+            })
+    }
+
+    // parts found by following references with a role from a file, not counting the file
+    fn follow(file: &Path, results: &ResultSet, role: &Role) -> usize {
+        let query = HashSet::from([DataTagKind::RefRole, DataTagKind::RefFileName]);
+        let mut seen = HashSet::from([file.to_owned()]);
+        let mut next = Self::referenced(file, results, role);
+
+        while let Some(file) = next.filter(|x| x.is_file() && seen.insert(x.clone())) {
+            let results = match FifParser::read_tags(file.clone()) {
+                Ok(tags) => Search::collect_tags(tags, &query),
+                Err(_) => break,
+            };
+            next = Self::referenced(&file, &results, role);
+        }
+
+        seen.len() - 1
+    }
+}
+
+impl DerivedField for Splits {
+    fn def(&self) -> TagDef {
+        TagDef::new(
+            "n_splits",
+            "int32",
+            "card",
+            "Number of parts of a recording split across files, following references to the other parts",
+        )
+    }
+
+    fn requires(&self) -> Vec<DataTagKind> {
+        vec![DataTagKind::RefRole, DataTagKind::RefFileName]
+    }
+
+    fn compute(&self, file: &Path, results: &ResultSet) -> Option<Data> {
+        let parts = 1
+            + Self::follow(file, results, &Role::PrevFile)
+            + Self::follow(file, results, &Role::NextFile);
+        Some(Data::Int32(vec![parts.try_into().ok()?]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::date::encode_julian_date;
+    use crate::tag::{ChInfo, Tag, FIFFV_NEXT_SEQ};
+    use crate::writer::FifWriter;
+    use chrono::NaiveDate;
+
+    fn compute(name: &str, results: &ResultSet) -> Option<Data> {
+        Derived::from_name(name)
+            .unwrap()
+            .compute(Path::new("test.fif"), results)
+    }
+
+    #[test]
+    fn can_compute_subj_age() {
+        // 2022-03-02
+        let meas_date = Data::Int32(vec![1646226731, 138511]);
+
+        let age = |birthday: NaiveDate| {
+            let results = ResultSet::from([
+                (DataTagKind::MeasDate, vec![meas_date.clone()]),
+                (
+                    DataTagKind::SubjBirthDay,
+                    vec![Data::JulianDate(vec![encode_julian_date(birthday)])],
+                ),
+            ]);
+            compute("subj_age", &results)
+        };
+
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+
+        assert_eq!(age(date(1990, 3, 2)), Some(Data::Int32(vec![32])));
+        assert_eq!(age(date(1990, 3, 3)), Some(Data::Int32(vec![31])));
+        assert_eq!(age(date(2023, 1, 1)), None);
+    }
+
+    #[test]
+    fn needs_both_tags_for_subj_age() {
+        let results = ResultSet::from([(
+            DataTagKind::MeasDate,
+            vec![Data::Int32(vec![1646226731, 138511])],
+        )]);
+        assert_eq!(compute("subj_age", &results), None);
+
+        let results = ResultSet::from([
+            (DataTagKind::MeasDate, vec![Data::Int32(vec![1646226731])]),
+            (DataTagKind::SubjBirthDay, vec![Data::JulianDate(vec![0])]),
+        ]);
+        assert_eq!(compute("subj_age", &results), None);
+    }
+
+    #[test]
+    fn can_compute_duration() {
+        let mut results = ResultSet::from([
+            (DataTagKind::Sfreq, vec![Data::Float(vec![1000.0])]),
+            (DataTagKind::Nchan, vec![Data::Int32(vec![2])]),
+            (
+                DataTagKind::DataBuffer,
+                vec![
+                    Data::InFile {
+                        start: 0,
+                        size: 8000,
+                        dtype: 4,
+                    };
+                    3
+                ],
+            ),
+            (DataTagKind::DataSkip, vec![Data::Int32(vec![1])]),
+            (DataTagKind::DataSkipSamp, vec![Data::Int32(vec![500])]),
+        ]);
+        // three buffers and a skipped one of 1000 samples, and 500 skipped samples
+        assert_eq!(compute("duration", &results), Some(Data::Float(vec![4.5])));
+
+        results.insert(DataTagKind::FirstSample, vec![Data::Int32(vec![-100])]);
+        results.insert(DataTagKind::LastSample, vec![Data::Int32(vec![899])]);
+        assert_eq!(compute("duration", &results), Some(Data::Float(vec![1.0])));
+    }
+
+    #[test]
+    fn can_count_channels_and_flags() {
+        let channel = |kind| {
+            Data::ChInfoStruct(vec![ChInfo {
+                scan_no: 1,
+                log_no: 1,
+                kind,
+                range: 1.0,
+                cal: 1.0,
+                coil_type: 0,
+                r0: [0.0; 3],
+                ex: [0.0; 3],
+                ey: [0.0; 3],
+                ez: [0.0; 3],
+                unit: 0,
+                unit_mul: 0,
+                name: String::from("test"),
+            }])
+        };
+        let results = ResultSet::from([
+            (
+                DataTagKind::ChInfo,
+                vec![channel(1), channel(1), channel(2)],
+            ),
+            (DataTagKind::SssJob, vec![Data::Int32(vec![2])]),
+        ]);
+
+        assert_eq!(compute("n_meg", &results), Some(Data::Int32(vec![2])));
+        assert_eq!(compute("n_stim", &results), Some(Data::Int32(vec![0])));
+        assert_eq!(compute("sss_applied", &results), Some(Data::Int32(vec![1])));
+        assert_eq!(compute("chpi", &results), Some(Data::Int32(vec![0])));
+        assert_eq!(compute("n_splits", &results), Some(Data::Int32(vec![1])));
+        assert_eq!(compute("n_eeg", &ResultSet::new()), None);
+    }
+
+    #[test]
+    fn can_follow_split_files() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let reference = |role: Role, name: &str| {
+            ResultSet::from([
+                (
+                    DataTagKind::RefRole,
+                    vec![Data::Int32(vec![role.to_code()])],
+                ),
+                (DataTagKind::RefFileName, vec![Data::String(name.into())]),
+            ])
+        };
+
+        // the second part refers back to the first, which isn't counted twice
+        let mut writer = FifWriter::create(dir.path().join("a-raw-1.fif")).unwrap();
+        let tags: Vec<Tag> = reference(Role::PrevFile, "a-raw.fif")
+            .into_iter()
+            .map(|(kind, mut data)| Tag::Data {
+                kind,
+                data: data.remove(0),
+                next: FIFFV_NEXT_SEQ,
+            })
+            .collect();
+        writer.write_tags(&tags).unwrap();
+        writer.finish().unwrap();
+
+        let first = dir.path().join("a-raw.fif");
+        let results = reference(Role::NextFile, "a-raw-1.fif");
+        assert_eq!(
+            Derived::from_name("n_splits")
+                .unwrap()
+                .compute(&first, &results),
+            Some(Data::Int32(vec![2]))
+        );
+    }
+
+    #[test]
+    fn can_register_fields() {
+        struct Answer;
+
+        impl DerivedField for Answer {
+            fn def(&self) -> TagDef {
+                TagDef::new("test_answer", "int32", "-", "The answer")
+            }
+
+            fn requires(&self) -> Vec<DataTagKind> {
+                vec![DataTagKind::Sfreq]
+            }
+
+            fn compute(&self, _file: &Path, _results: &ResultSet) -> Option<Data> {
+                Some(Data::Int32(vec![42]))
+            }
+        }
+
+        register(Answer).unwrap();
+        assert_eq!(
+            compute("test_answer", &ResultSet::new()),
+            Some(Data::Int32(vec![42]))
+        );
+        assert_eq!(
+            Derived::from_name("test_answer").unwrap().requires(),
+            vec![DataTagKind::Sfreq]
+        );
+
+        // names are taken by tags and fields already there
+        assert!(register(Answer).is_err());
+        assert!(all().iter().any(|x| x.name() == "n_eeg"));
+    }
+}
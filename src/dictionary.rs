@@ -0,0 +1,336 @@
+//! Looking things up in the dictionaries of tags, blocks and primitive types (fiff/*.tsv), and
+//! the derived fields, for `meginfo tags`.
+//!
+//! Searches give whatever has the name or code searched for, or else everything whose name or
+//! description contains it, and names a few typos away from it.
+
+use std::fmt::Display;
+
+use anyhow::anyhow;
+
+use crate::derived::{self, Derived};
+use crate::enums::{BlockKind, DType, DataTagKind};
+use crate::tag::TagDef;
+
+/// An entry from one of the dictionaries.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Definition {
+    Tag(DataTagKind),
+    Derived(Derived),
+    Block(BlockKind),
+    DType(DType),
+}
+
+impl Definition {
+    /// Every tag, derived field, block and type, in that order.
+    pub fn all() -> Vec<Definition> {
+        let tags = DataTagKind::ALL.iter().cloned().map(Definition::Tag);
+        let fields = derived::all().into_iter().map(Definition::Derived);
+        let blocks = BlockKind::ALL.iter().cloned().map(Definition::Block);
+        let dtypes = DType::ALL.iter().cloned().map(Definition::DType);
+
+        tags.chain(fields).chain(blocks).chain(dtypes).collect()
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Definition::Tag(x) => x.to_string(),
+            Definition::Derived(x) => x.name(),
+            Definition::Block(x) => x.to_string(),
+            Definition::DType(x) => x.to_string(),
+        }
+    }
+
+    fn code(&self) -> Option<i32> {
+        match self {
+            Definition::Tag(x) => Some(x.to_code()),
+            Definition::Derived(_) => None,
+            Definition::Block(x) => Some(x.to_code()),
+            Definition::DType(x) => Some(x.to_code()),
+        }
+    }
+
+    fn description(&self) -> String {
+        match self {
+            Definition::Tag(x) => x.description().to_owned(),
+            Definition::Derived(x) => x.tag_def().description().to_owned(),
+            Definition::Block(x) => x.description().to_owned(),
+            Definition::DType(x) => x.description().to_owned(),
+        }
+    }
+}
+
+fn names<T: Display>(items: impl IntoIterator<Item = T>) -> String {
+    let names: Vec<String> = items.into_iter().map(|x| x.to_string()).collect();
+    names.join(", ")
+}
+
+impl Display for Definition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Definition::Tag(kind) => {
+                write!(f, "{}", TagDef::from(kind))?;
+                if !kind.blocks().is_empty() {
+                    write!(f, "\n  in blocks: {}", names(kind.blocks()))?;
+                }
+                Ok(())
+            }
+            Definition::Derived(derived) => write!(
+                f,
+                "{}\n  derived from: {}",
+                derived.tag_def(),
+                names(derived.requires())
+            ),
+            Definition::Block(kind) => {
+                write!(
+                    f,
+                    "{kind} (block {}): {}",
+                    kind.to_code(),
+                    kind.description()
+                )?;
+
+                let tags = DataTagKind::ALL
+                    .iter()
+                    .filter(|x| x.blocks().contains(kind));
+                let tags = names(tags);
+                if !tags.is_empty() {
+                    write!(f, "\n  tags: {tags}")?;
+                }
+                Ok(())
+            }
+            Definition::DType(dtype) => {
+                write!(
+                    f,
+                    "{dtype} (dtype {}): {}",
+                    dtype.to_code(),
+                    dtype.description()
+                )?;
+
+                let tags = names(tags_with_dtype(dtype));
+                if !tags.is_empty() {
+                    write!(f, "\n  used by: {tags}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// the primitive type of a dtype as written in tags.tsv, e.g. float for float*(3) and int32 for
+// enum(sex)
+fn primitive(dtype: &str) -> &str {
+    let base = dtype.split(['*', '(', '[']).next().unwrap_or(dtype);
+
+    match base {
+        "int" | "enum" | "bitmask" | "bool" => "int32",
+        "id1.1" => "id_struct",
+        "ch_info_rec" => "ch_info_struct",
+        "ch_pos_rec" => "ch_pos_struct",
+        "coor_trans_rec" => "coord_trans_struct",
+        "dig_point" => "dig_point_struct",
+        "dig_string" => "dig_string_struct",
+        base => base,
+    }
+}
+
+/// A primitive type by name or code, or as written in tags.tsv (e.g. enum or id1.1).
+pub fn find_dtype(name: &str) -> anyhow::Result<DType> {
+    if let Ok(code) = name.parse::<i32>() {
+        return Ok(DType::from_code(code));
+    }
+
+    primitive(name).parse().map_err(|_| {
+        anyhow!(
+            "Unrecognized dtype: {name:?}.{} See `meginfo tags --dtypes` for a list.",
+            did_you_mean(name, DType::ALL.iter().map(|x| x.to_string()))
+        )
+    })
+}
+
+/// Tags whose data is of a primitive type.
+pub fn tags_with_dtype(dtype: &DType) -> Vec<DataTagKind> {
+    let name = dtype.to_string();
+
+    DataTagKind::ALL
+        .iter()
+        .filter(|x| primitive(x.dtype()) == name)
+        .cloned()
+        .collect()
+}
+
+// number of single character insertions, deletions, changes or swaps of neighbours to turn one
+// string into another
+fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    // d[i][j] is the distance between the first i characters of a and first j of b
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    d[0] = (0..=b.len()).collect();
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let change = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + change);
+
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+
+    d[a.len()][b.len()]
+}
+
+// how far a name can be from the one given and still be a likely typo of it
+fn is_close(name: &str, given: &str) -> bool {
+    distance(name, given) <= (given.chars().count() / 3).max(1)
+}
+
+/// Everything matching a name, code or part of a description, ignoring case.
+pub fn search(term: &str) -> Vec<Definition> {
+    let all = Definition::all();
+    let term = term.trim().to_lowercase();
+    let code = term.strip_prefix("code:").unwrap_or(&term).parse::<i32>();
+
+    let exact: Vec<Definition> = all
+        .iter()
+        .filter(|x| x.name() == term || code.as_ref().is_ok_and(|y| x.code() == Some(*y)))
+        .cloned()
+        .collect();
+    if !exact.is_empty() || code.is_ok() {
+        return exact;
+    }
+
+    // names containing the term first, then descriptions, then near misses
+    let mut found: Vec<(usize, Definition)> = all
+        .into_iter()
+        .filter_map(|x| {
+            let rank = if x.name().contains(&term) {
+                0
+            } else if x.description().to_lowercase().contains(&term) {
+                1
+            } else if is_close(&x.name(), &term) {
+                2
+            } else {
+                return None;
+            };
+            Some((rank, x))
+        })
+        .collect();
+    found.sort_by_key(|(rank, _)| *rank);
+
+    found.into_iter().map(|(_, x)| x).collect()
+}
+
+/// " Did you mean ...?" for the names closest to one that wasn't found, or nothing if none are
+/// close.
+pub fn did_you_mean(given: &str, names: impl IntoIterator<Item = String>) -> String {
+    let mut close: Vec<(usize, String)> = names
+        .into_iter()
+        .filter(|x| is_close(x, given))
+        .map(|x| (distance(&x, given), x))
+        .collect();
+    close.sort();
+
+    let close: Vec<String> = close.into_iter().take(3).map(|(_, x)| x).collect();
+    match close.as_slice() {
+        [] => String::new(),
+        [name] => format!(" Did you mean {name}?"),
+        [names @ .., last] => format!(" Did you mean {} or {last}?", names.join(", ")),
+    }
+}
+
+/// Error for a tag (or derived field, if they're allowed) that isn't in the dictionary.
+pub fn unrecognized_tag(name: &str, derived: bool) -> anyhow::Error {
+    let tags = DataTagKind::ALL.iter().map(|x| x.to_string());
+    let fields = derived::all().into_iter().map(|x| x.name());
+    let suggestion = match derived {
+        true => did_you_mean(name, tags.chain(fields)),
+        false => did_you_mean(name, tags),
+    };
+
+    anyhow!(
+        "Unrecognized tag: {name:?}.{suggestion} See `meginfo tags` for a list of valid names, or give a code."
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn found(term: &str) -> Vec<String> {
+        search(term).iter().map(Definition::name).collect()
+    }
+
+    #[test]
+    fn can_search_dictionaries() {
+        assert_eq!(found("sfreq"), vec!["sfreq"]);
+        assert_eq!(found("subj_age"), vec!["subj_age"]);
+        // the tag, block and primitive type with that code
+        assert_eq!(found("101"), vec!["dir_pointer", "meas_info"]);
+
+        let helium = found("HELIUM");
+        assert_eq!(helium[..3], ["helium_level", "helium_info", "he_level_raw"]);
+
+        // typos
+        assert!(found("sfrq").contains(&String::from("sfreq")));
+        assert!(found("no such thing at all").is_empty());
+    }
+
+    #[test]
+    fn can_describe_definitions() {
+        let sfreq = Definition::Tag(DataTagKind::Sfreq).to_string();
+        assert_eq!(
+            sfreq.lines().last(),
+            Some("  in blocks: meas_info"),
+            "{sfreq}"
+        );
+
+        let block = Definition::Block(BlockKind::HeliumInfo).to_string();
+        assert!(
+            block.ends_with("tags: he_level_raw, helium_level"),
+            "{block}"
+        );
+
+        let age = Definition::Derived(Derived::from_name("subj_age").unwrap()).to_string();
+        assert!(
+            age.ends_with("derived from: meas_date, subj_birth_day"),
+            "{age}"
+        );
+    }
+
+    #[test]
+    fn can_find_tags_by_dtype() {
+        let julian = find_dtype("julian").unwrap();
+        assert_eq!(tags_with_dtype(&julian), vec![DataTagKind::SubjBirthDay]);
+
+        let int32 = tags_with_dtype(&find_dtype("enum").unwrap());
+        assert!(int32.contains(&DataTagKind::SubjSex));
+        assert!(int32.contains(&DataTagKind::Nchan));
+
+        assert_eq!(find_dtype("4").unwrap(), DType::Float);
+        assert!(find_dtype("flaot")
+            .unwrap_err()
+            .to_string()
+            .contains("Did you mean float?"));
+    }
+
+    #[test]
+    fn suggests_names() {
+        let names = || ["sfreq", "nchan", "lowpass"].map(String::from);
+
+        assert_eq!(did_you_mean("sfrequ", names()), " Did you mean sfreq?");
+        assert_eq!(did_you_mean("xyz", names()), "");
+        assert_eq!(distance("kitten", "sitting"), 3);
+        assert_eq!(distance("sfreq", "sfrqe"), 1);
+
+        let error = unrecognized_tag("subj_ag", true).to_string();
+        assert!(error.contains("Did you mean subj_age"), "{error}");
+    }
+}
@@ -0,0 +1,695 @@
+//! Compare two files, block by block and tag by tag.
+//!
+//! Blocks are matched by their path from the root and tags by kind, in file order, so the second
+//! `bad_chs` of a block is compared with the second `bad_chs` of the same block in the other file.
+//! Channels are matched by name instead, so that reordering them shows up as moves rather than as
+//! every channel changing.
+
+use std::cell::RefCell;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use petgraph::stable_graph::NodeIndex;
+use serde::Serialize;
+
+use crate::enums::DataTagKind;
+use crate::graph::Tree;
+use crate::parser::FifParser;
+use crate::tag::{display_vec, ChInfo, Data, FiffNode, LabelledData};
+
+/// Relative difference below which floats are taken to be the same.
+pub const DEFAULT_TOLERANCE: f32 = 1e-6;
+
+// tags which say where things are in the file, rather than anything about the measurement
+const BOOKKEEPING: [DataTagKind; 3] = [DataTagKind::Dir, DataTagKind::DirPointer, DataTagKind::Nop];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DiffFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for DiffFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "text" => DiffFormat::Text,
+            "json" => DiffFormat::Json,
+            _ => bail!("Unknown diff format {s}, expected text or json"),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DiffOptions {
+    /// Relative difference below which floats are taken to be the same
+    pub tolerance: f32,
+    /// Compare the contents of data buffers, not just how many there are
+    pub data: bool,
+    /// Don't compare the values of tags of these kinds
+    pub ignore: Vec<DataTagKind>,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        DiffOptions {
+            tolerance: DEFAULT_TOLERANCE,
+            data: false,
+            ignore: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    Added,
+    Removed,
+    Changed,
+    Moved,
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Change::Added => "added",
+            Change::Removed => "removed",
+            Change::Changed => "changed",
+            Change::Moved => "moved",
+        };
+
+        // padded, so that differences line up
+        f.pad(name)
+    }
+}
+
+/// A block, tag or channel which differs, with its values in each file (positions, for moved
+/// channels) where there's something to show.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Difference {
+    pub path: String,
+    pub change: Change,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:<8} {}", self.change, self.path)?;
+
+        match (&self.old, &self.new) {
+            (Some(old), Some(new)) => write!(f, ": {old} -> {new}"),
+            (Some(value), None) | (None, Some(value)) => write!(f, " = {value}"),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Diff {
+    pub a: PathBuf,
+    pub b: PathBuf,
+    pub differences: Vec<Difference>,
+}
+
+impl Diff {
+    pub fn read(a: &Path, b: &Path, options: &DiffOptions) -> Result<Self> {
+        let tree_a = FifParser::parse(a.to_owned())?;
+        let tree_b = FifParser::parse(b.to_owned())?;
+
+        let differences = compare(
+            Side::new(&tree_a, Some(a))?,
+            Side::new(&tree_b, Some(b))?,
+            options,
+        )?;
+
+        Ok(Diff {
+            a: a.to_owned(),
+            b: b.to_owned(),
+            differences,
+        })
+    }
+}
+
+impl Display for Diff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "--- {}", self.a.display())?;
+        writeln!(f, "+++ {}", self.b.display())?;
+
+        for difference in &self.differences {
+            writeln!(f, "{difference}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Differences between two trees, whose large payloads can't be read (so are compared by size).
+pub fn diff_trees(
+    a: &Tree<FiffNode>,
+    b: &Tree<FiffNode>,
+    options: &DiffOptions,
+) -> Result<Vec<Difference>> {
+    compare(Side::new(a, None)?, Side::new(b, None)?, options)
+}
+
+fn compare(a: Side, b: Side, options: &DiffOptions) -> Result<Vec<Difference>> {
+    let (root_a, root_b) = (a.tree.root, b.tree.root);
+    let mut differ = Differ {
+        a,
+        b,
+        options,
+        differences: vec![],
+    };
+    differ.blocks("", root_a, root_b)?;
+
+    Ok(differ.differences)
+}
+
+// a tree, along with the file that its large payloads can be read from
+struct Side<'a> {
+    tree: &'a Tree<FiffNode>,
+    file: Option<RefCell<BufReader<File>>>,
+}
+
+impl<'a> Side<'a> {
+    fn new(tree: &'a Tree<FiffNode>, file: Option<&Path>) -> Result<Self> {
+        let file = match file {
+            Some(file) => {
+                let fh = File::open(file).with_context(|| format!("Can't open {file:?}"))?;
+                Some(RefCell::new(BufReader::new(fh)))
+            }
+            None => None,
+        };
+
+        Ok(Side { tree, file })
+    }
+
+    fn load(&self, data: &Data) -> Result<Data> {
+        match (data, &self.file) {
+            (Data::InFile { start, size, dtype }, Some(file)) => {
+                let mut file = file.borrow_mut();
+                let mut bytes = vec![0u8; *size as usize];
+                file.seek(SeekFrom::Start(*start))?;
+                file.read_exact(&mut bytes)?;
+                Ok(Data::from_slice(bytes, *dtype))
+            }
+            _ => Ok(data.clone()),
+        }
+    }
+}
+
+// a value to compare, floats within the tolerance of each other are the same
+#[derive(Debug, PartialEq)]
+enum Value {
+    Floats(Vec<f32>),
+    Text(String),
+}
+
+impl Value {
+    fn of(kind: &DataTagKind, data: Data) -> Self {
+        match data {
+            Data::Float(x) => Value::Floats(x),
+            Data::InFile { size, dtype, .. } => {
+                Value::Text(format!("{size} bytes of dtype {dtype}"))
+            }
+            data => Value::Text(LabelledData::new(kind.clone(), data).to_string()),
+        }
+    }
+
+    fn close_to(&self, other: &Value, tolerance: f32) -> bool {
+        match (self, other) {
+            (Value::Floats(a), Value::Floats(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(x, y)| close(*x, *y, tolerance))
+            }
+            (a, b) => a == b,
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Floats(x) => write!(f, "{}", display_vec(x)),
+            Value::Text(x) => write!(f, "{x}"),
+        }
+    }
+}
+
+fn close(a: f32, b: f32, tolerance: f32) -> bool {
+    a == b || (a.is_nan() && b.is_nan()) || (a - b).abs() <= tolerance * a.abs().max(b.abs())
+}
+
+fn channel_fields(ch: &ChInfo) -> Vec<(&'static str, Value)> {
+    let text = |x: &dyn Display| Value::Text(x.to_string());
+
+    vec![
+        ("scan_no", text(&ch.scan_no)),
+        ("log_no", text(&ch.log_no)),
+        ("kind", text(&ch.ch_type())),
+        ("range", Value::Floats(vec![ch.range])),
+        ("cal", Value::Floats(vec![ch.cal])),
+        ("coil_type", text(&ch.coil())),
+        ("r0", Value::Floats(ch.r0.to_vec())),
+        ("ex", Value::Floats(ch.ex.to_vec())),
+        ("ey", Value::Floats(ch.ey.to_vec())),
+        ("ez", Value::Floats(ch.ez.to_vec())),
+        ("unit", text(&ch.unit())),
+        ("unit_mul", text(&ch.unit_mul)),
+    ]
+}
+
+// items grouped by key, keys in order of first appearance
+fn group<K: PartialEq>(items: impl Iterator<Item = (K, NodeIndex)>) -> Vec<(K, Vec<NodeIndex>)> {
+    let mut groups: Vec<(K, Vec<NodeIndex>)> = vec![];
+
+    for (key, item) in items {
+        match groups.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, group)) => group.push(item),
+            None => groups.push((key, vec![item])),
+        }
+    }
+
+    groups
+}
+
+// keys of both, those of a first, with the items each has under them
+fn pair<K: PartialEq + Clone>(
+    a: Vec<(K, Vec<NodeIndex>)>,
+    b: Vec<(K, Vec<NodeIndex>)>,
+) -> Vec<(K, Vec<NodeIndex>, Vec<NodeIndex>)> {
+    let mut pairs: Vec<(K, Vec<NodeIndex>, Vec<NodeIndex>)> =
+        a.into_iter().map(|(k, x)| (k, x, vec![])).collect();
+
+    for (key, items) in b {
+        match pairs.iter_mut().find(|(k, _, _)| *k == key) {
+            Some((_, _, group)) => *group = items,
+            None => pairs.push((key, vec![], items)),
+        }
+    }
+
+    pairs
+}
+
+// a path segment, numbered when either file has more than one of its kind
+fn segment(path: &str, name: impl Display, i: usize, numbered: bool) -> String {
+    let name = match numbered {
+        true => format!("{name}[{i}]"),
+        false => name.to_string(),
+    };
+
+    match path {
+        "" => name,
+        _ => format!("{path}/{name}"),
+    }
+}
+
+// positions of the longest sequence of items which appear in the same order in both
+fn common_order(a: &[&str], b: &[&str]) -> Vec<bool> {
+    let mut lengths = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lengths[i][j] = match a[i] == b[j] {
+                true => lengths[i + 1][j + 1] + 1,
+                false => lengths[i + 1][j].max(lengths[i][j + 1]),
+            };
+        }
+    }
+
+    let mut in_order = vec![false; a.len()];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            in_order[i] = true;
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+
+    in_order
+}
+
+struct Differ<'a> {
+    a: Side<'a>,
+    b: Side<'a>,
+    options: &'a DiffOptions,
+    differences: Vec<Difference>,
+}
+
+impl Differ<'_> {
+    fn push(&mut self, path: String, change: Change, old: Option<String>, new: Option<String>) {
+        self.differences.push(Difference {
+            path,
+            change,
+            old,
+            new,
+        });
+    }
+
+    fn compares_values(&self, kind: &DataTagKind) -> bool {
+        !self.options.ignore.contains(kind)
+            && (self.options.data || *kind != DataTagKind::DataBuffer)
+    }
+
+    // value to show for a tag which is only in one file
+    fn shown_value(&self, side: &Side, node: NodeIndex) -> Result<Option<String>> {
+        let FiffNode::Tag { kind, data } = &side.tree[node] else {
+            return Ok(None);
+        };
+
+        Ok(match data {
+            Data::InFile { .. } => None,
+            data => Some(Value::of(kind, side.load(data)?).to_string()),
+        })
+    }
+
+    fn blocks(&mut self, path: &str, a: NodeIndex, b: NodeIndex) -> Result<()> {
+        let tags = |side: &Side, block| {
+            group(
+                side.tree
+                    .children(block)
+                    .into_iter()
+                    .filter_map(|x| match &side.tree[x] {
+                        FiffNode::Tag { kind, .. } if !BOOKKEEPING.contains(kind) => {
+                            Some((kind.clone(), x))
+                        }
+                        _ => None,
+                    }),
+            )
+        };
+
+        for (kind, xs, ys) in pair(tags(&self.a, a), tags(&self.b, b)) {
+            if kind == DataTagKind::ChInfo {
+                self.channels(path, &xs, &ys)?;
+                continue;
+            }
+
+            let numbered = xs.len().max(ys.len()) > 1;
+            for i in 0..xs.len().max(ys.len()) {
+                let path = segment(path, &kind, i, numbered);
+
+                match (xs.get(i), ys.get(i)) {
+                    (Some(x), Some(y)) => self.tags(path, &kind, *x, *y)?,
+                    (Some(x), None) => {
+                        let old = self.shown_value(&self.a, *x)?;
+                        self.push(path, Change::Removed, old, None);
+                    }
+                    (None, Some(y)) => {
+                        let new = self.shown_value(&self.b, *y)?;
+                        self.push(path, Change::Added, None, new);
+                    }
+                    (None, None) => {}
+                }
+            }
+        }
+
+        let blocks = |side: &Side, block| {
+            group(
+                side.tree
+                    .children(block)
+                    .into_iter()
+                    .filter_map(|x| match &side.tree[x] {
+                        FiffNode::Block { kind } => Some((kind.clone(), x)),
+                        FiffNode::Tag { .. } => None,
+                    }),
+            )
+        };
+
+        for (kind, xs, ys) in pair(blocks(&self.a, a), blocks(&self.b, b)) {
+            let numbered = xs.len().max(ys.len()) > 1;
+            for i in 0..xs.len().max(ys.len()) {
+                let path = segment(path, &kind, i, numbered);
+
+                match (xs.get(i), ys.get(i)) {
+                    (Some(x), Some(y)) => self.blocks(&path, *x, *y)?,
+                    (Some(_), None) => self.push(path, Change::Removed, None, None),
+                    (None, Some(_)) => self.push(path, Change::Added, None, None),
+                    (None, None) => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn tags(&mut self, path: String, kind: &DataTagKind, a: NodeIndex, b: NodeIndex) -> Result<()> {
+        if !self.compares_values(kind) {
+            return Ok(());
+        }
+
+        let (FiffNode::Tag { data: x, .. }, FiffNode::Tag { data: y, .. }) =
+            (&self.a.tree[a], &self.b.tree[b])
+        else {
+            return Ok(());
+        };
+
+        let x = Value::of(kind, self.a.load(x)?);
+        let y = Value::of(kind, self.b.load(y)?);
+
+        if !x.close_to(&y, self.options.tolerance) {
+            // data buffers are too long to show
+            match kind {
+                DataTagKind::DataBuffer => self.push(path, Change::Changed, None, None),
+                _ => self.push(
+                    path,
+                    Change::Changed,
+                    Some(x.to_string()),
+                    Some(y.to_string()),
+                ),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn channels(&mut self, path: &str, a: &[NodeIndex], b: &[NodeIndex]) -> Result<()> {
+        let channels = |side: &Side, tags: &[NodeIndex]| -> Result<Vec<ChInfo>> {
+            let mut channels = vec![];
+            for tag in tags {
+                if let FiffNode::Tag { data, .. } = &side.tree[*tag] {
+                    if let Data::ChInfoStruct(x) = side.load(data)? {
+                        channels.extend(x);
+                    }
+                }
+            }
+            Ok(channels)
+        };
+
+        let xs = channels(&self.a, a)?;
+        let ys = channels(&self.b, b)?;
+        let compare = self.compares_values(&DataTagKind::ChInfo);
+
+        let names_a: Vec<&str> = xs.iter().map(|x| x.ch_name()).collect();
+        let names_b: Vec<&str> = ys.iter().map(|x| x.ch_name()).collect();
+        let path_of = |name: &str| segment(path, format!("ch_info[{name}]"), 0, false);
+
+        for (i, x) in xs.iter().enumerate() {
+            let Some(j) = names_b.iter().position(|y| *y == names_a[i]) else {
+                self.push(path_of(names_a[i]), Change::Removed, None, None);
+                continue;
+            };
+
+            if !compare {
+                continue;
+            }
+
+            for ((field, old), (_, new)) in
+                channel_fields(x).into_iter().zip(channel_fields(&ys[j]))
+            {
+                if !old.close_to(&new, self.options.tolerance) {
+                    self.push(
+                        format!("{}.{field}", path_of(names_a[i])),
+                        Change::Changed,
+                        Some(old.to_string()),
+                        Some(new.to_string()),
+                    );
+                }
+            }
+        }
+
+        for name in names_b.iter().filter(|x| !names_a.contains(x)) {
+            self.push(path_of(name), Change::Added, None, None);
+        }
+
+        // channels in both, in the order of each file
+        let common_a: Vec<&str> = names_a
+            .iter()
+            .copied()
+            .filter(|x| names_b.contains(x))
+            .collect();
+        let common_b: Vec<&str> = names_b
+            .iter()
+            .copied()
+            .filter(|x| names_a.contains(x))
+            .collect();
+
+        for (name, in_order) in common_a.iter().zip(common_order(&common_a, &common_b)) {
+            if !in_order {
+                let from = names_a.iter().position(|x| x == name).unwrap_or_default();
+                let to = names_b.iter().position(|x| x == name).unwrap_or_default();
+                self.push(
+                    path_of(name),
+                    Change::Moved,
+                    Some(from.to_string()),
+                    Some(to.to_string()),
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::BlockKind;
+
+    fn channel(name: &str, cal: f32) -> Data {
+        Data::ChInfoStruct(vec![ChInfo {
+            scan_no: 1,
+            log_no: 1,
+            kind: 1,
+            range: 1.0,
+            cal,
+            coil_type: 3012,
+            r0: [0.0; 3],
+            ex: [1.0, 0.0, 0.0],
+            ey: [0.0, 1.0, 0.0],
+            ez: [0.0, 0.0, 1.0],
+            unit: 112,
+            unit_mul: 0,
+            name: name.to_owned(),
+        }])
+    }
+
+    fn make_tree(sfreq: f32, channels: &[(&str, f32)], projectors: usize) -> Tree<FiffNode> {
+        let mut tree = Tree::new();
+        let meas = tree.add_child(FiffNode::Block {
+            kind: BlockKind::Meas,
+        });
+        tree.move_to(meas);
+
+        let info = tree.add_child(FiffNode::Block {
+            kind: BlockKind::MeasInfo,
+        });
+        tree.move_to(info);
+        tree.add_child(FiffNode::Tag {
+            kind: DataTagKind::Sfreq,
+            data: Data::Float(vec![sfreq]),
+        });
+        for (name, cal) in channels {
+            tree.add_child(FiffNode::Tag {
+                kind: DataTagKind::ChInfo,
+                data: channel(name, *cal),
+            });
+        }
+        for _ in 0..projectors {
+            tree.add_child(FiffNode::Block {
+                kind: BlockKind::SspItem,
+            });
+        }
+
+        tree.move_to(meas);
+        let raw = tree.add_child(FiffNode::Block {
+            kind: BlockKind::RawData,
+        });
+        tree.move_to(raw);
+        tree.add_child(FiffNode::Tag {
+            kind: DataTagKind::DataBuffer,
+            data: Data::Float(vec![sfreq]),
+        });
+
+        tree
+    }
+
+    fn show(differences: Vec<Difference>) -> Vec<String> {
+        differences.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn same_files_have_no_differences() {
+        let a = make_tree(1000.0, &[("MEG0113", 1.0)], 1);
+        let b = make_tree(1000.0 * (1.0 + 1e-7), &[("MEG0113", 1.0)], 1);
+
+        assert_eq!(diff_trees(&a, &b, &DiffOptions::default()).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn can_find_changes() {
+        let a = make_tree(1000.0, &[("MEG0113", 1.0)], 1);
+        let b = make_tree(500.0, &[("MEG0113", 2.0)], 2);
+
+        assert_eq!(
+            show(diff_trees(&a, &b, &DiffOptions::default()).unwrap()),
+            vec![
+                "changed  meas/meas_info/sfreq: 1000 -> 500",
+                "changed  meas/meas_info/ch_info[MEG0113].cal: 1 -> 2",
+                "added    meas/meas_info/ssp_item[1]",
+            ]
+        );
+
+        let options = DiffOptions {
+            data: true,
+            ignore: vec![DataTagKind::Sfreq, DataTagKind::ChInfo],
+            ..Default::default()
+        };
+        assert_eq!(
+            show(diff_trees(&b, &a, &options).unwrap()),
+            vec![
+                "removed  meas/meas_info/ssp_item[1]",
+                "changed  meas/raw_data/data_buffer",
+            ]
+        );
+
+        let options = DiffOptions {
+            tolerance: 1.0,
+            ..Default::default()
+        };
+        assert_eq!(diff_trees(&a, &b, &options).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn can_find_reordered_channels() {
+        let a = make_tree(
+            1000.0,
+            &[
+                ("MEG0113", 1.0),
+                ("MEG0112", 1.0),
+                ("MEG0111", 1.0),
+                ("EEG001", 1.0),
+            ],
+            0,
+        );
+        let b = make_tree(
+            1000.0,
+            &[
+                ("MEG0112", 1.0),
+                ("MEG0111", 1.0),
+                ("MEG0113", 1.0),
+                ("EEG002", 1.0),
+            ],
+            0,
+        );
+
+        assert_eq!(
+            show(diff_trees(&a, &b, &DiffOptions::default()).unwrap()),
+            vec![
+                "removed  meas/meas_info/ch_info[EEG001]",
+                "added    meas/meas_info/ch_info[EEG002]",
+                "moved    meas/meas_info/ch_info[MEG0113]: 0 -> 2",
+            ]
+        );
+    }
+}
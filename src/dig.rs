@@ -0,0 +1,506 @@
+//! Digitized points of a measurement: fiducials, HPI coils, EEG electrodes and head shape points
+//! from the Isotrak block, for `meginfo dig`.
+//!
+//! Points can be moved into another coordinate frame with the transformations stored in the file,
+//! or in another file such as the -trans.fif from co-registration, and written as BIDS
+//! electrodes.tsv and coordsystem.json, a .pos head shape file, or CSV.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Result};
+use serde_json::{json, Map, Value};
+
+use crate::enums::{BlockKind, DataTagKind};
+use crate::graph::Tree;
+use crate::parser::FifParser;
+use crate::tag::{ChInfo, CoordTrans, Data, DigPoint, FiffNode};
+use crate::values::{Cardinal, ChType, Coord, Point};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DigFormat {
+    #[default]
+    Csv,
+    Pos,
+    Bids,
+}
+
+impl FromStr for DigFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "csv" => DigFormat::Csv,
+            "pos" => DigFormat::Pos,
+            "bids" => DigFormat::Bids,
+            _ => bail!("Unknown dig format {s}, expected csv, pos or bids"),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Dig {
+    pub file: PathBuf,
+    /// Frame the points are in, head unless the Isotrak block says otherwise
+    pub frame: Coord,
+    pub points: Vec<DigPoint>,
+    /// Transformations between frames which the points can be moved with
+    pub transforms: Vec<CoordTrans>,
+    // EEG channels, to name electrodes after
+    eeg: Vec<ChInfo>,
+}
+
+impl Dig {
+    pub fn read(file: &Path) -> Result<Self> {
+        let tree = FifParser::parse(file.to_owned())?;
+        Ok(Self::from_tree(file, &tree))
+    }
+
+    pub fn from_tree(file: &Path, tree: &Tree<FiffNode>) -> Self {
+        let isotrak = tree.blocks(&BlockKind::Isotrak).first().copied();
+
+        let frame = isotrak
+            .and_then(|x| tree.tag_in(x, &DataTagKind::MneCoordFrame))
+            .and_then(|x| match x {
+                Data::Int32(x) => x.first().map(|x| Coord::from_code(*x)),
+                _ => None,
+            })
+            .unwrap_or(Coord::Head);
+
+        let points = isotrak
+            .map(|x| tree.tags_in(x, &DataTagKind::DigPoint))
+            .unwrap_or_default()
+            .into_iter()
+            .flat_map(|x| match x {
+                Data::DigPointStruct(x) => x.clone(),
+                _ => vec![],
+            })
+            .collect();
+
+        let eeg = tree
+            .blocks(&BlockKind::MeasInfo)
+            .first()
+            .map(|x| tree.tags_in(*x, &DataTagKind::ChInfo))
+            .unwrap_or_default()
+            .into_iter()
+            .flat_map(|x| match x {
+                Data::ChInfoStruct(x) => x.clone(),
+                _ => vec![],
+            })
+            .filter(|x| x.ch_type() == ChType::Eeg)
+            .collect();
+
+        Dig {
+            file: file.to_owned(),
+            frame,
+            points,
+            transforms: transforms(tree),
+            eeg,
+        }
+    }
+
+    /// The points in another frame, using the transformations in the file and any added with
+    /// `with_transforms`, one after the other if need be (e.g. device to head to MRI).
+    pub fn to_frame(mut self, frame: Coord) -> Result<Self> {
+        if frame == self.frame {
+            return Ok(self);
+        }
+
+        let trans = find_transform(&self.transforms, &self.frame, &frame).ok_or_else(|| {
+            anyhow!(
+                "No transformation from {} to {frame} coordinates in {}",
+                self.frame,
+                self.file.display()
+            )
+        })?;
+
+        for point in &mut self.points {
+            point.r = trans.apply(point.r);
+        }
+        self.frame = frame;
+
+        Ok(self)
+    }
+
+    pub fn with_transforms(mut self, mut transforms: Vec<CoordTrans>) -> Self {
+        self.transforms.append(&mut transforms);
+        self
+    }
+
+    /// Name of a point: the BIDS name of a fiducial (NAS, LPA, RPA), coil1 for HPI coil 1, the
+    /// name of the EEG channel with the electrode's number, or the kind and number of others.
+    pub fn name(&self, point: &DigPoint) -> String {
+        if let Some(cardinal) = point.cardinal() {
+            return cardinal.to_string();
+        }
+
+        match point.point() {
+            Point::Hpi => format!("coil{}", point.ident),
+            Point::Eeg => match self.eeg.iter().find(|x| x.log_no == point.ident) {
+                Some(channel) => channel.ch_name().to_owned(),
+                None => format!("EEG {:03}", point.ident),
+            },
+            kind => format!("{kind}{}", point.ident),
+        }
+    }
+
+    fn of_kind(&self, kind: Point) -> impl Iterator<Item = &DigPoint> {
+        self.points.iter().filter(move |x| x.point() == kind)
+    }
+
+    /// A row per point with its name, kind, number and position in metres.
+    pub fn to_csv(&self) -> Result<String> {
+        let mut wtr = csv::Writer::from_writer(vec![]);
+        wtr.write_record(["name", "kind", "ident", "x", "y", "z", "frame"])?;
+
+        for point in &self.points {
+            let mut row = vec![
+                self.name(point),
+                point.point().to_string(),
+                point.ident.to_string(),
+            ];
+            row.extend(point.r.iter().map(|x| x.to_string()));
+            row.push(self.frame.to_string());
+            wtr.write_record(row)?;
+        }
+
+        Ok(String::from_utf8(wtr.into_inner()?)?)
+    }
+
+    /// A head shape file as read by CTF and MNE: the number of points, then a line per point with
+    /// a label and position in centimetres.  Fiducials are labelled nasion, left and right, and
+    /// head shape points by number.
+    pub fn to_pos(&self) -> String {
+        let mut lines = vec![self.points.len().to_string()];
+
+        for point in &self.points {
+            let label = match point.cardinal() {
+                Some(Cardinal::Nasion) => String::from("nasion"),
+                Some(Cardinal::Lpa) => String::from("left"),
+                Some(Cardinal::Rpa) => String::from("right"),
+                _ if matches!(point.point(), Point::Extra | Point::Head) => point.ident.to_string(),
+                // labels are separated from positions by whitespace
+                _ => self.name(point).replace(' ', ""),
+            };
+            let r: Vec<String> = point.r.iter().map(|x| (x * 100.0).to_string()).collect();
+            lines.push(format!("{label}\t{}", r.join("\t")));
+        }
+
+        lines.join("\n") + "\n"
+    }
+
+    /// BIDS electrodes.tsv, with the position of each EEG electrode in metres.
+    pub fn to_electrodes_tsv(&self) -> Result<String> {
+        let mut wtr = csv::WriterBuilder::new()
+            .delimiter(b'\t')
+            .from_writer(vec![]);
+        wtr.write_record(["name", "x", "y", "z"])?;
+
+        for point in self.of_kind(Point::Eeg) {
+            let mut row = vec![self.name(point)];
+            row.extend(point.r.iter().map(|x| x.to_string()));
+            wtr.write_record(row)?;
+        }
+
+        Ok(String::from_utf8(wtr.into_inner()?)?)
+    }
+
+    /// BIDS coordsystem.json, describing the frame of the points and giving the positions of the
+    /// fiducials and HPI coils.
+    pub fn to_coordsystem_json(&self) -> Value {
+        // head coordinates are what BIDS calls ElektaNeuromag, other frames have no BIDS name
+        let (system, description) = match &self.frame {
+            Coord::Head => ("ElektaNeuromag", None),
+            frame => ("Other", Some(frame.description())),
+        };
+        let frame = |prefix: &str| {
+            let mut fields = vec![
+                (format!("{prefix}CoordinateSystem"), json!(system)),
+                (format!("{prefix}CoordinateUnits"), json!("m")),
+            ];
+            if let Some(description) = description {
+                let name = format!("{prefix}CoordinateSystemDescription");
+                fields.push((name, json!(description)));
+            }
+            fields
+        };
+        let positions = |kind| -> Map<String, Value> {
+            self.of_kind(kind)
+                .map(|x| (self.name(x), position(x.r)))
+                .collect()
+        };
+
+        let mut json = Map::new();
+        json.extend(frame("MEG"));
+        if self.of_kind(Point::Eeg).next().is_some() {
+            json.extend(frame("EEG"));
+        }
+
+        let coils = positions(Point::Hpi);
+        if !coils.is_empty() {
+            json.insert(String::from("HeadCoilCoordinates"), coils.into());
+            json.extend(frame("HeadCoil"));
+        }
+
+        let landmarks = positions(Point::Cardinal);
+        if !landmarks.is_empty() {
+            json.insert(
+                String::from("AnatomicalLandmarkCoordinates"),
+                landmarks.into(),
+            );
+            json.extend(frame("AnatomicalLandmark"));
+        }
+
+        let head_points = self.of_kind(Point::Extra).next().is_some();
+        json.insert(String::from("DigitizedHeadPoints"), json!(head_points));
+
+        json.into()
+    }
+
+    /// Write the points to a file, or the two files of the BIDS format, named with the given
+    /// prefix (e.g. sub-01_ses-01 for sub-01_ses-01_electrodes.tsv).  Returns the files written.
+    pub fn save(&self, format: DigFormat, path: &Path) -> Result<Vec<PathBuf>> {
+        match format {
+            DigFormat::Csv => std::fs::write(path, self.to_csv()?)?,
+            DigFormat::Pos => std::fs::write(path, self.to_pos())?,
+            DigFormat::Bids => {
+                let with_suffix = |suffix: &str| {
+                    let mut name = path.as_os_str().to_owned();
+                    name.push(suffix);
+                    PathBuf::from(name)
+                };
+
+                let mut written = vec![];
+                if self.of_kind(Point::Eeg).next().is_some() {
+                    let electrodes = with_suffix("_electrodes.tsv");
+                    std::fs::write(&electrodes, self.to_electrodes_tsv()?)?;
+                    written.push(electrodes);
+                }
+
+                let coordsystem = with_suffix("_coordsystem.json");
+                let json = serde_json::to_string_pretty(&self.to_coordsystem_json())?;
+                std::fs::write(&coordsystem, json + "\n")?;
+                written.push(coordsystem);
+
+                return Ok(written);
+            }
+        }
+
+        Ok(vec![path.to_owned()])
+    }
+}
+
+// a position as JSON, with each f32 written as its shortest decimal (0.1 rather than the
+// 0.10000000149011612 it would be widened to)
+fn position(r: [f32; 3]) -> Value {
+    json!(r.map(|x| x.to_string().parse::<f64>().ok()))
+}
+
+/// Every transformation between coordinate frames in a file, e.g. device to head in a raw file, or
+/// head to MRI in a -trans.fif.
+pub fn transforms(tree: &Tree<FiffNode>) -> Vec<CoordTrans> {
+    tree.depth_first(tree.root)
+        .into_iter()
+        .filter_map(|x| match &tree[x] {
+            FiffNode::Tag {
+                kind: DataTagKind::CoordTrans,
+                data: Data::CoordTransStruct(x),
+            } => Some(x.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+// the transformation between two frames made of the fewest transformations (or their inverses)
+fn find_transform(transforms: &[CoordTrans], from: &Coord, to: &Coord) -> Option<CoordTrans> {
+    let steps: Vec<CoordTrans> = transforms
+        .iter()
+        .flat_map(|x| [x.clone(), x.inverse()])
+        .collect();
+
+    let mut seen = HashSet::from([from.to_code()]);
+    let mut paths: Vec<CoordTrans> = steps
+        .iter()
+        .filter(|x| x.from_frame() == *from)
+        .cloned()
+        .collect();
+
+    while !paths.is_empty() {
+        if let Some(found) = paths.iter().find(|x| x.to_frame() == *to) {
+            return Some(found.clone());
+        }
+
+        seen.extend(paths.iter().map(|x| x.to));
+        paths = paths
+            .iter()
+            .flat_map(|path| {
+                steps
+                    .iter()
+                    .filter(|x| x.from == path.to && !seen.contains(&x.to))
+                    .map(|x| path.then(x))
+            })
+            .collect();
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDENTITY: [[f32; 3]; 3] = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+    // moves points by a translation
+    fn shift(from: Coord, to: Coord, by: [f32; 3]) -> CoordTrans {
+        CoordTrans {
+            from: from.to_code(),
+            to: to.to_code(),
+            rot: IDENTITY,
+            translation: by,
+            inv_rot: IDENTITY,
+            inv_translation: by.map(|x| -x),
+        }
+    }
+
+    fn point(kind: Point, ident: i32, r: [f32; 3]) -> DigPoint {
+        DigPoint {
+            kind: kind.to_code(),
+            ident,
+            r,
+        }
+    }
+
+    // fiducials, an HPI coil, an EEG electrode and a head shape point, with an EEG channel and
+    // the device to head transformation in meas_info
+    fn dig() -> Dig {
+        let mut tree = Tree::new();
+        let root = tree.current();
+
+        let meas_info = tree.add_child(FiffNode::Block {
+            kind: BlockKind::MeasInfo,
+        });
+        tree.move_to(meas_info);
+        let eeg = ChInfo {
+            scan_no: 1,
+            log_no: 1,
+            kind: ChType::Eeg.to_code(),
+            range: 1.0,
+            cal: 1.0,
+            coil_type: 1,
+            r0: [0.0; 3],
+            ex: [0.0; 3],
+            ey: [0.0; 3],
+            ez: [0.0; 3],
+            unit: 107,
+            unit_mul: 0,
+            name: String::from("EEG 001"),
+        };
+        tree.add_child(FiffNode::Tag {
+            kind: DataTagKind::ChInfo,
+            data: Data::ChInfoStruct(vec![eeg]),
+        });
+        tree.add_child(FiffNode::Tag {
+            kind: DataTagKind::CoordTrans,
+            data: Data::CoordTransStruct(shift(Coord::Device, Coord::Head, [0.0, 0.0, 0.01])),
+        });
+        tree.move_to(root);
+
+        let isotrak = tree.add_child(FiffNode::Block {
+            kind: BlockKind::Isotrak,
+        });
+        tree.move_to(isotrak);
+        let points = vec![
+            point(Point::Cardinal, 1, [-0.07, 0.0, 0.0]),
+            point(Point::Cardinal, 2, [0.0, 0.1, 0.0]),
+            point(Point::Cardinal, 3, [0.07, 0.0, 0.0]),
+            point(Point::Hpi, 1, [0.03, 0.09, 0.05]),
+            point(Point::Eeg, 1, [0.0, 0.0, 0.1]),
+            point(Point::Extra, 1, [0.01, 0.02, 0.03]),
+        ];
+        for point in points {
+            tree.add_child(FiffNode::Tag {
+                kind: DataTagKind::DigPoint,
+                data: Data::DigPointStruct(vec![point]),
+            });
+        }
+
+        Dig::from_tree(Path::new("test.fif"), &tree)
+    }
+
+    #[test]
+    fn can_read_points() {
+        let dig = dig();
+        let names: Vec<String> = dig.points.iter().map(|x| dig.name(x)).collect();
+
+        assert_eq!(dig.frame, Coord::Head);
+        assert_eq!(names, ["LPA", "NAS", "RPA", "coil1", "EEG 001", "extra1"]);
+        assert_eq!(dig.transforms.len(), 1);
+    }
+
+    #[test]
+    fn can_change_frame() {
+        let mri = shift(Coord::Head, Coord::Mri, [0.0, 0.25, 0.0]);
+        let dig = dig().with_transforms(vec![mri]);
+
+        // through the inverse of device to head
+        let device = dig.clone().to_frame(Coord::Device).unwrap();
+        assert_eq!(device.frame, Coord::Device);
+        assert_eq!(device.points[1].r, [0.0, 0.1, -0.01]);
+
+        // device to head to mri
+        let mri = device.to_frame(Coord::Mri).unwrap();
+        assert_eq!(mri.points[1].r, [0.0, 0.1 + 0.25, 0.0]);
+
+        let error = dig.to_frame(Coord::MneMniTal).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "No transformation from head to mne_mni_tal coordinates in test.fif"
+        );
+    }
+
+    #[test]
+    fn can_write_csv_and_pos() {
+        let dig = dig();
+
+        let csv = dig.to_csv().unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "name,kind,ident,x,y,z,frame");
+        assert_eq!(lines[5], "EEG 001,eeg,1,0,0,0.1,head");
+
+        let pos = dig.to_pos();
+        let lines: Vec<&str> = pos.lines().collect();
+        assert_eq!(lines[0], "6");
+        assert_eq!(lines[2], "nasion\t0\t10\t0");
+        assert_eq!(lines[5], "EEG001\t0\t0\t10");
+        assert_eq!(lines[6], "1\t1\t2\t3");
+    }
+
+    #[test]
+    fn can_write_bids() {
+        let dig = dig();
+
+        assert_eq!(
+            dig.to_electrodes_tsv().unwrap(),
+            "name\tx\ty\tz\nEEG 001\t0\t0\t0.1\n"
+        );
+
+        let json = dig.to_coordsystem_json();
+        assert_eq!(json["MEGCoordinateSystem"], "ElektaNeuromag");
+        assert_eq!(json["EEGCoordinateUnits"], "m");
+        assert_eq!(
+            json["AnatomicalLandmarkCoordinates"]["NAS"],
+            json!([0.0, 0.1, 0.0])
+        );
+        assert!(json["HeadCoilCoordinates"]["coil1"].is_array());
+        assert_eq!(json["DigitizedHeadPoints"], true);
+
+        let device = dig.to_frame(Coord::Device).unwrap().to_coordsystem_json();
+        assert_eq!(device["MEGCoordinateSystem"], "Other");
+        assert_eq!(
+            device["MEGCoordinateSystemDescription"],
+            "Device coordinates"
+        );
+    }
+}
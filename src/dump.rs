@@ -0,0 +1,251 @@
+//! Low-level listing of the tags in a file, as they're read.
+//!
+//! Unlike the tree, this shows every tag header (including block_start and block_end) with its
+//! position, so that broken files can be compared against what the reader makes of them.
+
+use std::fmt::Display;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::enums::{BlockKind, BlockTagKind, DType};
+use crate::parser::{FifParser, RawTag};
+use crate::tag::{Data, Tag};
+
+/// Which tags to list, and how much of each payload to show.
+#[derive(Debug, Clone, Default)]
+pub struct DumpOptions {
+    /// Only tags with headers at or after this byte
+    pub from: Option<u64>,
+    /// Only tags with headers before this byte
+    pub to: Option<u64>,
+    /// Only tags with these codes, all tags if empty
+    pub codes: Vec<i32>,
+    /// Number of payload bytes to show
+    pub preview: usize,
+}
+
+#[derive(Debug)]
+pub struct Entry {
+    /// Number of blocks the tag is inside
+    pub depth: usize,
+    pub tag: RawTag,
+}
+
+#[derive(Debug)]
+pub struct Dump {
+    pub file: PathBuf,
+    pub entries: Vec<Entry>,
+}
+
+impl Dump {
+    pub fn read(file: &Path, options: &DumpOptions) -> Result<Self> {
+        let reader = BufReader::new(File::open(file)?);
+        let tags = FifParser::read_raw_tags_from(reader, options.preview)?;
+
+        Ok(Self::from_tags(file, tags, options))
+    }
+
+    pub fn from_tags(file: &Path, tags: Vec<RawTag>, options: &DumpOptions) -> Self {
+        let mut depth: usize = 0;
+        let mut entries = vec![];
+
+        for tag in tags {
+            // block_start is shown at the depth of the block it opens, block_end likewise
+            let tag_depth = match &tag.tag {
+                Tag::Block {
+                    kind: BlockTagKind::BlockStart,
+                    ..
+                } => {
+                    depth += 1;
+                    depth - 1
+                }
+                Tag::Block {
+                    kind: BlockTagKind::BlockEnd,
+                    ..
+                } => {
+                    depth = depth.saturating_sub(1);
+                    depth
+                }
+                Tag::Data { .. } => depth,
+            };
+
+            let selected = options.from.is_none_or(|x| tag.position >= x)
+                && options.to.is_none_or(|x| tag.position < x)
+                && (options.codes.is_empty() || options.codes.contains(&tag.header.code));
+
+            if selected {
+                entries.push(Entry {
+                    depth: tag_depth,
+                    tag,
+                });
+            }
+        }
+
+        Dump {
+            file: file.to_owned(),
+            entries,
+        }
+    }
+}
+
+// name of a tag, blocks along with the kind of block they start or end
+fn tag_name(tag: &Tag) -> String {
+    match tag {
+        Tag::Data { kind, .. } => kind.to_string(),
+        Tag::Block { kind, data, .. } => {
+            let name = match kind {
+                BlockTagKind::BlockStart => "block_start",
+                BlockTagKind::BlockEnd => "block_end",
+            };
+
+            match data {
+                Data::Int32(x) if x.len() == 1 => format!("{name} {}", BlockKind::from_code(x[0])),
+                _ => name.to_owned(),
+            }
+        }
+    }
+}
+
+// bytes as hex, followed by the printable ones as ASCII
+fn preview(bytes: &[u8]) -> String {
+    if bytes.is_empty() {
+        return String::new();
+    }
+
+    let hex: Vec<String> = bytes.iter().map(|x| format!("{x:02x}")).collect();
+    let ascii: String = bytes
+        .iter()
+        .map(|&x| {
+            if x.is_ascii_graphic() || x == b' ' {
+                x as char
+            } else {
+                '.'
+            }
+        })
+        .collect();
+
+    format!("{}  |{ascii}|", hex.join(" "))
+}
+
+impl Display for Dump {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", self.file.display())?;
+        writeln!(
+            f,
+            "{:>10}  {:>5}  {:<36}  {:<18}  {:>10}  {:>10}  data",
+            "offset", "code", "name", "dtype", "size", "next"
+        )?;
+
+        for Entry { depth, tag } in &self.entries {
+            let name = format!("{}{}", "  ".repeat(*depth), tag_name(&tag.tag));
+            let dtype = DType::from_code(tag.header.dtype).to_string();
+
+            writeln!(
+                f,
+                "{:>10}  {:>5}  {name:<36}  {dtype:<18}  {:>10}  {:>10}  {}",
+                tag.position,
+                tag.header.code,
+                tag.header.size,
+                tag.header.next,
+                preview(&tag.preview)
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::DataTagKind;
+    use crate::tag::{Header, FIFFV_NEXT_SEQ};
+
+    fn raw(position: u64, code: i32, dtype: i32, tag: Tag, preview: &[u8]) -> RawTag {
+        RawTag {
+            position,
+            header: Header {
+                code,
+                dtype,
+                size: preview.len() as i32,
+                next: FIFFV_NEXT_SEQ,
+            },
+            preview: preview.to_vec(),
+            tag,
+        }
+    }
+
+    fn tags() -> Vec<RawTag> {
+        let block = |kind, block: BlockKind| Tag::Block {
+            kind,
+            data: Data::Int32(vec![block.to_code()]),
+            next: FIFFV_NEXT_SEQ,
+        };
+        let nchan = Tag::Data {
+            kind: DataTagKind::Nchan,
+            data: Data::Int32(vec![3]),
+            next: FIFFV_NEXT_SEQ,
+        };
+
+        vec![
+            raw(
+                0,
+                104,
+                3,
+                block(BlockTagKind::BlockStart, BlockKind::Meas),
+                &[0, 0, 0, 100],
+            ),
+            raw(20, 200, 3, nchan, &[0, 0, 0, 3]),
+            raw(
+                40,
+                105,
+                3,
+                block(BlockTagKind::BlockEnd, BlockKind::Meas),
+                &[0, 0, 0, 100],
+            ),
+        ]
+    }
+
+    #[test]
+    fn can_dump_tags() {
+        let dump = Dump::from_tags(Path::new("raw.fif"), tags(), &DumpOptions::default());
+        let depths: Vec<usize> = dump.entries.iter().map(|x| x.depth).collect();
+        assert_eq!(depths, vec![0, 1, 0]);
+
+        let lines: Vec<String> = dump.to_string().lines().map(|x| x.to_owned()).collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[2].contains("block_start meas"));
+        assert!(lines[3].contains("  nchan"));
+        assert!(lines[3].contains("int32"));
+        assert!(lines[3].ends_with("00 00 00 03  |....|"));
+    }
+
+    #[test]
+    fn can_select_tags() {
+        let options = DumpOptions {
+            from: Some(10),
+            to: Some(40),
+            ..Default::default()
+        };
+        let dump = Dump::from_tags(Path::new("raw.fif"), tags(), &options);
+        assert_eq!(dump.entries.len(), 1);
+        assert_eq!(dump.entries[0].depth, 1);
+
+        let options = DumpOptions {
+            codes: vec![104, 105],
+            ..Default::default()
+        };
+        let dump = Dump::from_tags(Path::new("raw.fif"), tags(), &options);
+        let positions: Vec<u64> = dump.entries.iter().map(|x| x.tag.position).collect();
+        assert_eq!(positions, vec![0, 40]);
+    }
+
+    #[test]
+    fn can_preview_bytes() {
+        assert_eq!(preview(b"MEG\x00"), "4d 45 47 00  |MEG.|");
+        assert_eq!(preview(&[]), "");
+    }
+}
@@ -0,0 +1,538 @@
+//! Conditions on the tags of a file, for `--where` and `--filter`.
+//!
+//! A condition compares a tag (or derived field) with a value, e.g. `sfreq >= 1000`,
+//! `bad_chs contains "MEG 2443"`, `subj_his_id ~ '^P0[0-9]+'` or `meas_date > 2023-01-01`, or
+//! checks whether it's there at all (`hpi_fit_accept exists`, `subj_birth_day missing`).
+//! Conditions combine with `&&` (or `and`), `||` (or `or`), `!` (or `not`) and parentheses.
+//!
+//! Values are compared by type: numbers as numbers, dates as dates, enumerated values by name or
+//! code, and everything else as it's displayed.  `contains` matches whole channel names in lists
+//! such as `bad_chs`, and any part of the text elsewhere.  A tag which occurs more than once
+//! matches if any occurrence does, except for `!=`, which needs all of them to differ.
+
+use std::cmp::Ordering;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use regex::Regex;
+
+use crate::date::{decode_julian_date, MeasDate};
+use crate::enums::DataTagKind;
+use crate::query::{Column, ResultSet};
+use crate::tag::{trim_string, Data, LabelledData};
+
+#[derive(Debug, Clone)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+    Matches(Regex),
+}
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Exists(Column),
+    Compare(Column, Op, String),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Token {
+    Word(String),
+    // quoted, so never taken as a keyword
+    Quoted(String),
+    Symbol(&'static str),
+}
+
+impl Token {
+    fn is(&self, symbol: &str, keyword: &str) -> bool {
+        match self {
+            Token::Symbol(x) => *x == symbol,
+            Token::Word(x) => x.eq_ignore_ascii_case(keyword),
+            Token::Quoted(_) => false,
+        }
+    }
+}
+
+// a token as found in the input, for errors
+fn found(token: Option<Token>) -> String {
+    match token {
+        Some(Token::Word(x) | Token::Quoted(x)) => format!("{x:?}"),
+        Some(Token::Symbol(x)) => format!("{x:?}"),
+        None => String::from("the end"),
+    }
+}
+
+// longest first, so that >= isn't read as > followed by =
+const SYMBOLS: [&str; 13] = [
+    "&&", "||", ">=", "<=", "==", "!=", "=", ">", "<", "~", "!", "(", ")",
+];
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut rest = input.trim_start();
+
+    while !rest.is_empty() {
+        if let Some(symbol) = SYMBOLS.iter().find(|x| rest.starts_with(**x)) {
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        } else if let Some(quote) = rest.chars().next().filter(|x| *x == '"' || *x == '\'') {
+            let end = rest[1..]
+                .find(quote)
+                .ok_or(anyhow!("Unclosed quote in {rest}"))?;
+            tokens.push(Token::Quoted(rest[1..end + 1].to_owned()));
+            rest = &rest[end + 2..];
+        } else {
+            let end = rest
+                .find(|x: char| x.is_whitespace() || "()!<>=~&|\"'".contains(x))
+                .unwrap_or(rest.len());
+            // a delimiter which isn't a symbol on its own, e.g. a single & or |
+            if end == 0 {
+                let symbol = rest.chars().next().unwrap_or_default();
+                return Err(anyhow!(
+                    "Unrecognized symbol {symbol:?} in {input:?}, use && or || to combine conditions"
+                ));
+            }
+            tokens.push(Token::Word(rest[..end].to_owned()));
+            rest = &rest[end..];
+        }
+
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+// recursive descent over the tokens, lowest precedence (||) first
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn eat(&mut self, symbol: &str, keyword: &str) -> bool {
+        let found = self.peek().is_some_and(|x| x.is(symbol, keyword));
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut expr = self.and()?;
+        while self.eat("||", "or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut expr = self.unary()?;
+        while self.eat("&&", "and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        if self.eat("!", "not") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+
+        if self.eat("(", "(") {
+            let expr = self.or()?;
+            if !self.eat(")", ")") {
+                return Err(anyhow!("Expected ), found {}", found(self.next())));
+            }
+            return Ok(expr);
+        }
+
+        self.condition()
+    }
+
+    fn condition(&mut self) -> Result<Expr> {
+        let name = match self.next() {
+            Some(Token::Word(x)) => x,
+            x => return Err(anyhow!("Expected a tag name, found {}", found(x))),
+        };
+        let column = Column::from_name(&name)?;
+
+        if self.eat("", "exists") {
+            return Ok(Expr::Exists(column));
+        }
+        if self.eat("", "missing") {
+            return Ok(Expr::Not(Box::new(Expr::Exists(column))));
+        }
+
+        let op = match self.next() {
+            Some(Token::Symbol(x)) if x != "(" && x != ")" && x != "!" => x.to_owned(),
+            Some(Token::Word(x))
+                if x.eq_ignore_ascii_case("contains") || x.eq_ignore_ascii_case("matches") =>
+            {
+                x.to_lowercase()
+            }
+            x => {
+                return Err(anyhow!(
+                    "Expected a comparison after {name}, found {}",
+                    found(x)
+                ))
+            }
+        };
+
+        let value = match self.next() {
+            Some(Token::Word(x) | Token::Quoted(x)) => x,
+            x => {
+                return Err(anyhow!(
+                    "Expected a value to compare {name} with, found {}",
+                    found(x)
+                ))
+            }
+        };
+
+        let op = match op.as_str() {
+            "=" | "==" => Op::Eq,
+            "!=" => Op::Ne,
+            "<" => Op::Lt,
+            "<=" => Op::Le,
+            ">" => Op::Gt,
+            ">=" => Op::Ge,
+            "contains" => Op::Contains,
+            "~" | "matches" => {
+                Op::Matches(Regex::new(&value).with_context(|| format!("Invalid regex {value:?}"))?)
+            }
+            x => return Err(anyhow!("Expected a comparison after {name}, found {x:?}")),
+        };
+
+        check_value(&column, &op, &value)?;
+        Ok(Expr::Compare(column, op, value))
+    }
+}
+
+// catch misspelt names and dates here, rather than silently matching nothing
+fn check_value(column: &Column, op: &Op, value: &str) -> Result<()> {
+    let Column::Tag(kind) = column else {
+        return Ok(());
+    };
+
+    if matches!(op, Op::Matches(_)) {
+        return Ok(());
+    }
+
+    if let Some(code) = kind.parse_value(value) {
+        code?;
+    }
+
+    if is_date(kind) && parse_time(value).is_none() {
+        return Err(anyhow!(
+            "{value:?} is not a date (YYYY-MM-DD) or time (RFC 3339) to compare {kind} with"
+        ));
+    }
+
+    Ok(())
+}
+
+pub(crate) fn is_date(kind: &DataTagKind) -> bool {
+    matches!(kind, DataTagKind::MeasDate | DataTagKind::SubjBirthDay)
+}
+
+// dates are taken as midnight UTC
+fn parse_time(value: &str) -> Option<DateTime<Utc>> {
+    match DateTime::parse_from_rfc3339(value) {
+        Ok(time) => Some(time.with_timezone(&Utc)),
+        Err(_) => NaiveDate::parse_from_str(value, "%Y-%m-%d")
+            .ok()
+            .map(|x| x.and_time(Default::default()).and_utc()),
+    }
+}
+
+// times held by a meas_date or birthday, or other data holding julian dates
+pub(crate) fn times(kind: Option<&DataTagKind>, data: &Data) -> Option<Vec<DateTime<Utc>>> {
+    let day = |x: &i32| decode_julian_date(*x).map(|x| x.and_time(Default::default()).and_utc());
+
+    match (kind, data) {
+        (Some(DataTagKind::MeasDate), Data::Int32(x)) => Some(
+            MeasDate::from_ints(x)
+                .ok()?
+                .to_utc()
+                .ok()
+                .into_iter()
+                .collect(),
+        ),
+        (Some(DataTagKind::SubjBirthDay), Data::Int32(x)) | (_, Data::JulianDate(x)) => {
+            Some(x.iter().filter_map(day).collect())
+        }
+        _ => None,
+    }
+}
+
+fn ordered(ordering: Option<Ordering>, op: &Op) -> bool {
+    let Some(ordering) = ordering else {
+        return false;
+    };
+
+    match op {
+        Op::Eq | Op::Contains => ordering == Ordering::Equal,
+        Op::Ne => ordering != Ordering::Equal,
+        Op::Lt => ordering == Ordering::Less,
+        Op::Le => ordering != Ordering::Greater,
+        Op::Gt => ordering == Ordering::Greater,
+        Op::Ge => ordering != Ordering::Less,
+        Op::Matches(_) => false,
+    }
+}
+
+// compare one occurrence of a tag (or derived field) with a value
+fn compare(kind: Option<&DataTagKind>, data: &Data, op: &Op, value: &str) -> bool {
+    let text = match kind {
+        Some(kind) => LabelledData::new(kind.clone(), data.clone()).to_string(),
+        None => data.to_string(),
+    };
+
+    if let Op::Matches(regex) = op {
+        return regex.is_match(&text);
+    }
+
+    if let Some(times) = times(kind, data) {
+        let time = parse_time(value);
+        return times.iter().any(|x| ordered(time.map(|y| x.cmp(&y)), op));
+    }
+
+    let code = kind.and_then(|x| x.parse_value(value)).and_then(|x| x.ok());
+    if let (Data::Int32(x), Some(code)) = (data, code) {
+        return x.iter().any(|x| ordered(Some(x.cmp(&code)), op));
+    }
+    if let (Data::Int32(x), Ok(value)) = (data, value.parse::<f64>()) {
+        return x
+            .iter()
+            .any(|x| ordered(f64::from(*x).partial_cmp(&value), op));
+    }
+    if let (Data::Float(x), Ok(value)) = (data, value.parse::<f32>()) {
+        return x.iter().any(|x| ordered(x.partial_cmp(&value), op));
+    }
+
+    if let (Op::Contains, Some(kind), Data::String(x)) = (op, kind, data) {
+        if is_list(kind) {
+            return trim_string(x).split(':').any(|x| x.trim() == value);
+        }
+    }
+
+    match op {
+        Op::Contains => text.contains(value),
+        op => ordered(Some(text.as_str().cmp(value)), op),
+    }
+}
+
+// tags holding colon-separated lists of channel names, whose items are matched whole
+fn is_list(kind: &DataTagKind) -> bool {
+    matches!(
+        kind,
+        DataTagKind::BadChs
+            | DataTagKind::HpiBadChs
+            | DataTagKind::ProjItemChNameList
+            | DataTagKind::MneChNameList
+    )
+}
+
+impl Expr {
+    pub fn parse(input: &str) -> Result<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(input)?,
+            position: 0,
+        };
+
+        let expr = parser
+            .or()
+            .with_context(|| format!("Invalid condition {input:?}"))?;
+
+        match parser.peek() {
+            None => Ok(expr),
+            x => Err(anyhow!(
+                "Invalid condition {input:?}: unexpected {}",
+                found(x.cloned())
+            )),
+        }
+    }
+
+    /// Condition for a `--filter`, given as tag=value.  This is the same as `tag = value`, but
+    /// the value needn't be quoted.
+    pub fn filter(filter: &str) -> Result<Self> {
+        let (name, value) = filter.split_once('=').ok_or(anyhow!(
+            "Filters should look like tag=value, got {filter:?}"
+        ))?;
+        let column = Column::from_name(name.trim())?;
+        let value = value.trim().to_owned();
+
+        check_value(&column, &Op::Eq, &value)?;
+        Ok(Expr::Compare(column, Op::Eq, value))
+    }
+
+    /// Condition which all of the conditions have to match, if there are any.
+    pub fn all(conditions: Vec<Expr>) -> Option<Self> {
+        conditions
+            .into_iter()
+            .reduce(|a, b| Expr::And(Box::new(a), Box::new(b)))
+    }
+
+    /// Tags and derived fields the condition looks at.
+    pub fn columns(&self) -> Vec<Column> {
+        match self {
+            Expr::And(a, b) | Expr::Or(a, b) => [a.columns(), b.columns()].concat(),
+            Expr::Not(a) => a.columns(),
+            Expr::Exists(column) | Expr::Compare(column, _, _) => vec![column.clone()],
+        }
+    }
+
+    pub(crate) fn matches(&self, file: &Path, results: &ResultSet) -> bool {
+        match self {
+            Expr::And(a, b) => a.matches(file, results) && b.matches(file, results),
+            Expr::Or(a, b) => a.matches(file, results) || b.matches(file, results),
+            Expr::Not(a) => !a.matches(file, results),
+            Expr::Exists(column) => !column.values(file, results).is_empty(),
+            Expr::Compare(column, op, value) => {
+                let kind = column.kind();
+                let values = column.values(file, results);
+
+                match op {
+                    Op::Ne => {
+                        !values.is_empty()
+                            && !values.iter().any(|x| compare(kind, x, &Op::Eq, value))
+                    }
+                    op => values.iter().any(|x| compare(kind, x, op, value)),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn results() -> ResultSet {
+        ResultSet::from([
+            (DataTagKind::Sfreq, vec![Data::Float(vec![1000.0])]),
+            (
+                DataTagKind::MeasDate,
+                vec![Data::Int32(vec![1646226731, 138511])],
+            ),
+            (
+                DataTagKind::SubjBirthDay,
+                vec![Data::JulianDate(vec![2451545])],
+            ),
+            (DataTagKind::SubjSex, vec![Data::Int32(vec![2])]),
+            (
+                DataTagKind::BadChs,
+                vec![Data::String("MEG 2443:EEG 053".into())],
+            ),
+            (DataTagKind::Nchan, vec![Data::Int32(vec![306])]),
+            (
+                DataTagKind::Experimenter,
+                vec![Data::String("Jane Doe".into())],
+            ),
+        ])
+    }
+
+    fn check(condition: &str) -> bool {
+        Expr::parse(condition)
+            .unwrap()
+            .matches(Path::new("test.fif"), &results())
+    }
+
+    #[test]
+    fn can_compare_values() {
+        assert!(check("sfreq >= 1000"));
+        assert!(check("sfreq == 1000.0"));
+        assert!(!check("sfreq > 1000"));
+        assert!(check("nchan < 400 && nchan != 204"));
+        assert!(check("bad_chs contains \"MEG 2443\""));
+        assert!(!check("bad_chs contains 'MEG 2444'"));
+        assert!(!check("bad_chs contains 'MEG 244'"));
+        assert!(check("experimenter contains Doe"));
+        assert!(check("bad_chs ~ '^MEG [0-9]+'"));
+        assert!(check("subj_sex = female"));
+        assert!(check("subj_sex == 2"));
+        assert!(!check("subj_sex = male"));
+    }
+
+    #[test]
+    fn can_compare_dates() {
+        assert!(check("meas_date > 2022-03-02"));
+        assert!(check("meas_date < 2022-03-02T14:00:00Z"));
+        assert!(!check("meas_date >= 2023-01-01"));
+        assert!(check("subj_birth_day = 2000-01-01"));
+        assert!(check("subj_age >= 22"));
+    }
+
+    #[test]
+    fn can_combine_conditions() {
+        assert!(check(
+            "sfreq >= 1000 && (subj_sex = male || bad_chs contains 'EEG 053')"
+        ));
+        assert!(check("not subj_sex = male and sfreq exists"));
+        assert!(!check("!(sfreq = 1000)"));
+        assert!(check("hpi_fit_accept missing or line_freq = 50"));
+        assert!(!check("line_freq exists"));
+        assert!(!check("line_freq != 50"));
+
+        let columns = Expr::parse("sfreq > 1 || subj_age missing")
+            .unwrap()
+            .columns();
+        assert_eq!(columns.len(), 2);
+    }
+
+    #[test]
+    fn can_filter_by_value() {
+        let filter = |x| {
+            Expr::filter(x)
+                .unwrap()
+                .matches(Path::new("test.fif"), &results())
+        };
+
+        assert!(filter("sfreq=1000"));
+        assert!(!filter("sfreq=600"));
+        assert!(filter("subj_sex=female"));
+        assert!(filter("subj_sex = 2"));
+        assert!(filter("bad_chs=MEG 2443:EEG 053"));
+        assert!(!filter("line_freq=50"));
+
+        assert!(Expr::filter("subj_sex=sometimes").is_err());
+        assert!(Expr::filter("subj_sex").is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_conditions() {
+        for condition in [
+            "",
+            "sfreq >",
+            "sfreq 1000",
+            "(sfreq > 1",
+            "sfreq > 1 sfreq",
+            "not_a_tag > 1",
+            "subj_sex = sometimes",
+            "meas_date > yesterday",
+            "bad_chs ~ '('",
+            "bad_chs = 'MEG",
+            "sfreq > 1 & nchan > 2",
+            "sfreq > 1 | nchan > 2",
+        ] {
+            assert!(Expr::parse(condition).is_err(), "{condition}");
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! Find the files to read from the paths given on the command line.
+//!
+//! Directories are searched recursively for .fif files, or files matching the given globs.  Files
+//! given by name are always read (unless excluded), even if they're hidden or symlinks.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use log::warn;
+use walkdir::{DirEntry, WalkDir};
+
+// files looked for in directories, unless other globs are given
+const DEFAULT_GLOB: &str = "*.fif";
+
+#[derive(Debug, Clone, Default)]
+pub struct FileOptions {
+    /// Only read files matching these globs, .fif files in directories if empty
+    pub globs: Vec<String>,
+    /// Leave out files matching these globs
+    pub exclude: Vec<String>,
+    /// Follow symlinks when searching directories
+    pub follow_links: bool,
+    /// Search hidden files and directories
+    pub hidden: bool,
+    /// Only search this many directories deep
+    pub max_depth: Option<usize>,
+}
+
+fn glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+
+    for pattern in patterns {
+        let glob = Glob::new(pattern).with_context(|| format!("Invalid glob {pattern:?}"))?;
+        builder.add(glob);
+    }
+
+    Ok(builder.build()?)
+}
+
+// globs match either the file name or the whole path
+fn matches(globs: &GlobSet, path: &Path) -> bool {
+    globs.is_match(path) || path.file_name().is_some_and(|x| globs.is_match(x))
+}
+
+fn is_hidden(entry: &DirEntry) -> bool {
+    // the directory being searched is never hidden, even if it's e.g. `.`
+    entry.depth() > 0 && entry.file_name().to_string_lossy().starts_with('.')
+}
+
+/// Files to read from a list of files and directories, in the order given, with directories
+/// sorted by name.  Paths which don't exist are reported and skipped.
+pub fn find_files(paths: &[PathBuf], options: &FileOptions) -> Result<Vec<PathBuf>> {
+    let explicit = glob_set(&options.globs)?;
+    let searched = match options.globs.is_empty() {
+        true => glob_set(&[DEFAULT_GLOB.to_owned()])?,
+        false => explicit.clone(),
+    };
+    let exclude = glob_set(&options.exclude)?;
+
+    let mut files = vec![];
+    let mut seen = HashSet::new();
+    let mut add = |file: &Path| match file.canonicalize() {
+        Ok(file) if seen.insert(file.clone()) => files.push(file),
+        Ok(_) => {}
+        Err(e) => warn!("Skipping {}: {e}", file.display()),
+    };
+
+    for path in paths {
+        if path.is_file() {
+            if (options.globs.is_empty() || matches(&explicit, path)) && !matches(&exclude, path) {
+                add(path);
+            }
+            continue;
+        }
+
+        if !path.is_dir() {
+            warn!("Skipping {}: no such file or directory", path.display());
+            continue;
+        }
+
+        let mut walk = WalkDir::new(path)
+            .follow_links(options.follow_links)
+            .sort_by_file_name();
+        if let Some(depth) = options.max_depth {
+            walk = walk.max_depth(depth);
+        }
+
+        let entries = walk
+            .into_iter()
+            .filter_entry(|x| options.hidden || !is_hidden(x));
+
+        for entry in entries {
+            match entry {
+                Ok(entry) if entry.file_type().is_file() => {
+                    let file = entry.path();
+                    if matches(&searched, file) && !matches(&exclude, file) {
+                        add(file);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Skipping {e}"),
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // a directory of files to search, made fresh for each test
+    fn make_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("meginfo-files-{name}"));
+        let _ = fs::remove_dir_all(&dir);
+
+        for file in [
+            "a-raw.fif",
+            "a-raw-1.fif",
+            "notes.txt",
+            ".hidden.fif",
+            "sub/b-raw.fif",
+            "sub/deeper/c-raw.fif",
+            ".cache/d-raw.fif",
+        ] {
+            let file = dir.join(file);
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(file, b"").unwrap();
+        }
+
+        dir
+    }
+
+    fn names(dir: &Path, files: Vec<PathBuf>) -> Vec<String> {
+        let dir = dir.canonicalize().unwrap();
+        files
+            .iter()
+            .map(|x| x.strip_prefix(&dir).unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn can_search_directories() {
+        let dir = make_dir("search");
+        let files = find_files(std::slice::from_ref(&dir), &FileOptions::default()).unwrap();
+
+        assert_eq!(
+            names(&dir, files),
+            vec![
+                "a-raw-1.fif",
+                "a-raw.fif",
+                "sub/b-raw.fif",
+                "sub/deeper/c-raw.fif"
+            ]
+        );
+
+        let options = FileOptions {
+            hidden: true,
+            max_depth: Some(1),
+            ..Default::default()
+        };
+        let files = find_files(std::slice::from_ref(&dir), &options).unwrap();
+        assert_eq!(
+            names(&dir, files),
+            vec![".hidden.fif", "a-raw-1.fif", "a-raw.fif"]
+        );
+    }
+
+    #[test]
+    fn can_pick_files_with_globs() {
+        let dir = make_dir("globs");
+        let options = FileOptions {
+            globs: vec![String::from("*-raw*.fif")],
+            exclude: vec![String::from("*-1.fif"), String::from("**/deeper/*")],
+            ..Default::default()
+        };
+        let files = find_files(std::slice::from_ref(&dir), &options).unwrap();
+        assert_eq!(names(&dir, files), vec!["a-raw.fif", "sub/b-raw.fif"]);
+
+        // files given by name are read whatever they're called, and only once
+        let paths = vec![
+            dir.join("notes.txt"),
+            dir.join(".hidden.fif"),
+            dir.join("notes.txt"),
+        ];
+        let files = find_files(&paths, &FileOptions::default()).unwrap();
+        assert_eq!(names(&dir, files), vec!["notes.txt", ".hidden.fif"]);
+
+        assert!(find_files(&paths, &options).unwrap().is_empty());
+    }
+
+    #[test]
+    fn skips_missing_paths() {
+        let dir = make_dir("missing");
+        let paths = vec![dir.join("nothing.fif"), dir.join("a-raw.fif")];
+
+        let files = find_files(&paths, &FileOptions::default()).unwrap();
+        assert_eq!(names(&dir, files), vec!["a-raw.fif"]);
+
+        let options = FileOptions {
+            globs: vec![String::from("[")],
+            ..Default::default()
+        };
+        assert!(find_files(&paths, &options).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follows_links_if_asked() {
+        let dir = make_dir("links");
+        std::os::unix::fs::symlink(dir.join("sub"), dir.join("linked")).unwrap();
+
+        let files = find_files(std::slice::from_ref(&dir), &FileOptions::default()).unwrap();
+        assert_eq!(files.len(), 4);
+
+        let options = FileOptions {
+            follow_links: true,
+            ..Default::default()
+        };
+        // files reached through the link are the same files, so are only read once
+        let files = find_files(&[dir.join("linked"), dir.clone()], &options).unwrap();
+        assert_eq!(
+            names(&dir, files),
+            vec![
+                "sub/b-raw.fif",
+                "sub/deeper/c-raw.fif",
+                "a-raw-1.fif",
+                "a-raw.fif"
+            ]
+        );
+    }
+}
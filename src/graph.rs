@@ -3,8 +3,7 @@
 use petgraph::stable_graph::{NodeIndex, StableGraph};
 use petgraph::Directed;
 use std::fmt::Display;
-
-use termtree;
+use std::ops::{Index, IndexMut};
 
 #[derive(Debug, Clone)]
 pub struct Tree<T: Display> {
@@ -22,24 +21,60 @@ where
         let root = graph.add_node(T::default());
 
         Tree {
-            graph: graph,
+            graph,
             current: root,
-            root: root,
+            root,
         }
     }
 
     fn create_view(&self, node: NodeIndex) -> termtree::Tree<String> {
         let mut tree = termtree::Tree::new(self.graph[node].to_string());
 
-        let children: Vec<NodeIndex> = self.graph.neighbors(node).collect();
-
-        for child in children.iter().rev() {
-            tree.push(self.create_view(*child));
+        for child in self.children(node) {
+            tree.push(self.create_view(child));
         }
 
         tree
     }
 
+    /// Children of a node, in the order they were added.
+    pub fn children(&self, node: NodeIndex) -> Vec<NodeIndex> {
+        // petgraph iterates over edges most recent first
+        let mut children: Vec<NodeIndex> = self.graph.neighbors(node).collect();
+        children.reverse();
+        children
+    }
+
+    /// All nodes below (and including) a node, depth first in file order.
+    pub fn depth_first(&self, node: NodeIndex) -> Vec<NodeIndex> {
+        let mut nodes = vec![node];
+
+        for child in self.children(node) {
+            nodes.append(&mut self.depth_first(child));
+        }
+
+        nodes
+    }
+
+    /// Remove a node along with everything below it.
+    pub fn remove(&mut self, node: NodeIndex) {
+        for child in self.depth_first(node) {
+            self.graph.remove_node(child);
+        }
+    }
+
+    fn same_subtree(&self, node: NodeIndex, other: &Self, other_node: NodeIndex) -> bool {
+        let children = self.children(node);
+        let other_children = other.children(other_node);
+
+        self[node] == other[other_node]
+            && children.len() == other_children.len()
+            && children
+                .iter()
+                .zip(other_children.iter())
+                .all(|(a, b)| self.same_subtree(*a, other, *b))
+    }
+
     pub fn add_child(&mut self, child: T) -> NodeIndex {
         // adds a child at the current node
         let n = self.graph.add_node(child);
@@ -52,6 +87,11 @@ where
         self.current = ind;
     }
 
+    pub fn current(&self) -> NodeIndex {
+        // the node new children are added to
+        self.current
+    }
+
     pub fn node_count(&self) -> usize {
         self.graph.node_count()
     }
@@ -61,6 +101,33 @@ where
     }
 }
 
+impl<T: Default + PartialEq + Display> Default for Tree<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Display> Index<NodeIndex> for Tree<T> {
+    type Output = T;
+
+    fn index(&self, index: NodeIndex) -> &Self::Output {
+        &self.graph[index]
+    }
+}
+
+impl<T: Display> IndexMut<NodeIndex> for Tree<T> {
+    fn index_mut(&mut self, index: NodeIndex) -> &mut Self::Output {
+        &mut self.graph[index]
+    }
+}
+
+// two trees are equal if they hold the same values in the same shape, regardless of node indices
+impl<T: Default + PartialEq + Display> PartialEq for Tree<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.same_subtree(self.root, other, other.root)
+    }
+}
+
 impl<T: Default + PartialEq + Display> Display for Tree<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.create_view(self.root))
@@ -78,7 +145,38 @@ mod tests {
 
         assert_eq!(tree.node_count(), 8);
         assert_eq!(tree.edge_count(), 7);
-        assert_eq!(tree.graph.node_weights().fold(0, |acc, x| acc + x), 28);
+        assert_eq!(tree.graph.node_weights().sum::<i32>(), 28);
+    }
+
+    #[test]
+    fn children_keep_insertion_order() {
+        let tree = make_dummy_tree();
+
+        let children: Vec<i32> = tree
+            .children(tree.root)
+            .into_iter()
+            .map(|x| tree[x])
+            .collect();
+
+        assert_eq!(children, vec![1, 2, 3]);
+        assert_eq!(tree, make_dummy_tree());
+
+        let mut other = make_dummy_tree();
+        other.add_child(8);
+        assert_ne!(tree, other);
+    }
+
+    #[test]
+    fn can_remove_subtree() {
+        let mut tree = make_dummy_tree();
+        let c = tree.children(tree.root)[2];
+
+        let below: Vec<i32> = tree.depth_first(c).into_iter().map(|x| tree[x]).collect();
+        assert_eq!(below, vec![3, 4, 6, 7, 5]);
+
+        tree.remove(c);
+        assert_eq!(tree.node_count(), 3);
+        assert_eq!(tree.edge_count(), 2);
     }
 
     #[test]
@@ -0,0 +1,463 @@
+//! On-disk index of the tags in many files, so that repeated queries don't have to re-read them.
+//!
+//! Entries are keyed by path, and kept for as long as the file's size, modification time and
+//! file_id are unchanged, so a file replaced by another with the same size and time is still read
+//! again.  Tags are stored as their encoded payloads, so reading them back gives exactly what
+//! reading the file would.  Channel info, tag directories and data buffers are left out to keep
+//! the index small; queries for them, including derived fields such as n_meg or duration, read
+//! the files instead.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::enums::DataTagKind;
+use crate::parser::FifParser;
+use crate::tag::{tag_header, Data, Tag, FIFFV_NEXT_SEQ};
+use crate::writer::encode_data;
+
+// start of every index file, followed by the format version
+const MAGIC: &[u8; 8] = b"MEGINDEX";
+const VERSION: u32 = 2;
+
+// tags which are bulky, or only say where things are in the file
+const UNINDEXED: [DataTagKind; 5] = [
+    DataTagKind::ChInfo,
+    DataTagKind::DataBuffer,
+    DataTagKind::Dir,
+    DataTagKind::DirPointer,
+    DataTagKind::Nop,
+];
+
+/// True if tags of this kind are kept in the index.
+pub fn is_indexed(kind: &DataTagKind) -> bool {
+    !UNINDEXED.contains(kind)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct StoredTag {
+    code: i32,
+    dtype: i32,
+    bytes: Vec<u8>,
+}
+
+impl StoredTag {
+    fn new(kind: &DataTagKind, data: &Data) -> Option<Self> {
+        Some(StoredTag {
+            code: kind.to_code(),
+            dtype: data.dtype(),
+            // data left in the file isn't indexed
+            bytes: encode_data(data).ok()?,
+        })
+    }
+
+    fn to_tag(&self) -> Tag {
+        Tag::Data {
+            kind: DataTagKind::from_code(self.code),
+            data: Data::from_slice(self.bytes.clone(), self.dtype),
+            next: FIFFV_NEXT_SEQ,
+        }
+    }
+}
+
+/// The indexed tags of a file, along with the size, modification time (in nanoseconds since the
+/// epoch) and leading file_id it had when they were read.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub size: u64,
+    pub mtime: u64,
+    file_id: Option<StoredTag>,
+    tags: Vec<StoredTag>,
+}
+
+// size and modification time of a file
+fn stat(file: &Path) -> Result<(u64, u64)> {
+    let metadata = fs::metadata(file)?;
+    let mtime = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_nanos();
+
+    Ok((metadata.len(), mtime as u64))
+}
+
+// file_id of a file, which is its first tag, without reading the rest of the file
+fn read_file_id(file: &Path) -> Result<Option<StoredTag>> {
+    let mut reader = File::open(file)?;
+    let mut header = [0u8; 16];
+    reader.read_exact(&mut header)?;
+
+    let (_, (size, header)) = tag_header(&header).map_err(|e| e.to_owned())?;
+    if header.code != DataTagKind::FileId.to_code() {
+        return Ok(None);
+    }
+
+    let mut bytes = vec![0; size.min(512) as usize];
+    reader.read_exact(&mut bytes)?;
+    Ok(Some(StoredTag {
+        code: header.code,
+        dtype: header.dtype,
+        bytes,
+    }))
+}
+
+impl Entry {
+    pub fn read(file: &Path) -> Result<Self> {
+        let tags = FifParser::read_tags(file.to_owned())?;
+        Self::for_file(file, &tags)
+    }
+
+    /// Entry for tags just read from a file.
+    pub fn for_file(file: &Path, tags: &[Tag]) -> Result<Self> {
+        let (size, mtime) = stat(file)?;
+        Ok(Self::from_tags(size, mtime, tags))
+    }
+
+    pub fn from_tags(size: u64, mtime: u64, tags: &[Tag]) -> Self {
+        let file_id = match tags.first() {
+            Some(Tag::Data {
+                kind: kind @ DataTagKind::FileId,
+                data,
+                ..
+            }) => StoredTag::new(kind, data),
+            _ => None,
+        };
+
+        let tags = tags
+            .iter()
+            .filter_map(|tag| match tag {
+                Tag::Data { kind, data, .. } if is_indexed(kind) => StoredTag::new(kind, data),
+                _ => None,
+            })
+            .collect();
+
+        Entry {
+            size,
+            mtime,
+            file_id,
+            tags,
+        }
+    }
+
+    /// True if the file hasn't changed since it was indexed.
+    pub fn is_fresh(&self, file: &Path) -> bool {
+        self.same_stat(file) && self.same_file_id(file)
+    }
+
+    fn same_stat(&self, file: &Path) -> bool {
+        stat(file).is_ok_and(|x| x == (self.size, self.mtime))
+    }
+
+    // compared as stored, without decoding either
+    fn same_file_id(&self, file: &Path) -> bool {
+        read_file_id(file).is_ok_and(|x| x == self.file_id)
+    }
+
+    pub fn tags(&self) -> Vec<Tag> {
+        self.tags.iter().map(StoredTag::to_tag).collect()
+    }
+
+    /// The file_id the file started with, if it did.
+    pub fn file_id(&self) -> Option<Data> {
+        self.file_id.as_ref().map(|x| x.to_tag().data().clone())
+    }
+}
+
+/// Numbers of files affected by updating an index.
+#[derive(Debug, Default, PartialEq)]
+pub struct Changes {
+    pub added: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    pub removed: usize,
+}
+
+impl Display for Changes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} added, {} updated, {} unchanged, {} removed",
+            self.added, self.updated, self.unchanged, self.removed
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Index {
+    path: PathBuf,
+    entries: BTreeMap<PathBuf, Entry>,
+}
+
+impl Index {
+    /// Open an index, which is empty if the file doesn't exist yet.
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut index = Index {
+            path: path.to_owned(),
+            entries: BTreeMap::new(),
+        };
+
+        if !path.exists() {
+            return Ok(index);
+        }
+
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 8];
+        let mut version = [0u8; 4];
+        reader
+            .read_exact(&mut magic)
+            .and_then(|_| reader.read_exact(&mut version))
+            .with_context(|| format!("{path:?} is not an index"))?;
+
+        if &magic != MAGIC {
+            return Err(anyhow!("{path:?} is not an index"));
+        }
+        if u32::from_be_bytes(version) != VERSION {
+            return Err(anyhow!(
+                "{path:?} was written by another version of meginfo, delete it to start again"
+            ));
+        }
+
+        index.entries = bincode::deserialize_from(reader)
+            .with_context(|| format!("Could not read index {path:?}"))?;
+
+        Ok(index)
+    }
+
+    /// Write the index out, replacing the old one only once the new one is complete.
+    pub fn save(&self) -> Result<()> {
+        let partial = self.path.with_extension("partial");
+
+        let mut writer = BufWriter::new(File::create(&partial)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_be_bytes())?;
+        bincode::serialize_into(&mut writer, &self.entries)?;
+        writer.flush()?;
+        drop(writer);
+
+        fs::rename(&partial, &self.path)?;
+        Ok(())
+    }
+
+    /// All indexed files, sorted by path.
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.entries.keys().cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The entry for a file, if the file hasn't changed since it was indexed.
+    pub fn fresh(&self, file: &Path) -> Option<&Entry> {
+        self.entries.get(file).filter(|x| x.is_fresh(file))
+    }
+
+    pub fn insert(&mut self, file: PathBuf, entry: Entry) {
+        self.entries.insert(file, entry);
+    }
+
+    /// Index files, reading only those which are new or have changed.  Files which can't be read
+    /// are reported and skipped.
+    pub fn update(&mut self, files: &[PathBuf]) -> Changes {
+        let mut changes = Changes::default();
+
+        for file in files {
+            if self.fresh(file).is_some() {
+                changes.unchanged += 1;
+                continue;
+            }
+
+            match Entry::read(file) {
+                Ok(entry) => match self.entries.insert(file.clone(), entry) {
+                    Some(_) => changes.updated += 1,
+                    None => changes.added += 1,
+                },
+                Err(e) => warn!("Skipping {}: {e:#}", file.display()),
+            }
+        }
+
+        changes
+    }
+
+    /// Read every indexed file again, dropping those which no longer exist.
+    pub fn rebuild(&mut self) -> Changes {
+        let mut changes = Changes::default();
+
+        for file in self.files() {
+            if !file.is_file() {
+                self.entries.remove(&file);
+                changes.removed += 1;
+                continue;
+            }
+
+            match Entry::read(&file) {
+                Ok(entry) if self.entries.get(&file) == Some(&entry) => changes.unchanged += 1,
+                Ok(entry) => {
+                    self.entries.insert(file, entry);
+                    changes.updated += 1;
+                }
+                Err(e) => warn!("Keeping old entry for {}: {e:#}", file.display()),
+            }
+        }
+
+        changes
+    }
+
+    /// Files whose entries no longer match them, and why.
+    pub fn verify(&self) -> Vec<(PathBuf, String)> {
+        let mut problems = vec![];
+
+        for (file, entry) in &self.entries {
+            let problem = if !file.is_file() {
+                String::from("file no longer exists")
+            } else if !entry.same_stat(file) {
+                String::from("file has changed since it was indexed")
+            } else if !entry.same_file_id(file) {
+                String::from("file_id differs from the file's")
+            } else {
+                match Entry::read(file) {
+                    Ok(read) if read == *entry => continue,
+                    Ok(_) => String::from("tags differ from the file's"),
+                    Err(e) => format!("file can't be read: {e:#}"),
+                }
+            };
+
+            problems.push((file.clone(), problem));
+        }
+
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tag::IdStruct;
+    use crate::writer::FifWriter;
+
+    fn tags(sfreq: f32) -> Vec<Tag> {
+        let tag = |kind, data| Tag::Data {
+            kind,
+            data,
+            next: FIFFV_NEXT_SEQ,
+        };
+
+        vec![
+            tag(DataTagKind::FileId, Data::IdStruct(IdStruct::generate())),
+            tag(DataTagKind::Sfreq, Data::Float(vec![sfreq])),
+            tag(DataTagKind::BadChs, Data::String("MEG0113".into())),
+            tag(DataTagKind::Nop, Data::Void),
+        ]
+    }
+
+    fn write_file(file: &Path, sfreq: f32) {
+        let mut writer = FifWriter::create(file).unwrap();
+        writer.write_tags(&tags(sfreq)).unwrap();
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn can_store_tags() {
+        let tags = tags(1000.0);
+        let entry = Entry::from_tags(10, 20, &tags);
+
+        // everything but the nop comes back as it was
+        assert_eq!(entry.tags(), tags[..3].to_vec());
+        assert_eq!(entry.file_id(), Some(tags[0].data().clone()));
+        assert!(!is_indexed(&DataTagKind::ChInfo));
+    }
+
+    #[test]
+    fn can_update_index() {
+        let dir = std::env::temp_dir().join("meginfo-index-test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let files = vec![dir.join("a.fif"), dir.join("b.fif")];
+        for file in &files {
+            write_file(file, 1000.0);
+        }
+
+        let mut index = Index::open(&dir.join("meginfo.idx")).unwrap();
+        assert!(index.is_empty());
+
+        let changes = index.update(&files);
+        assert_eq!(changes.added, 2);
+        index.save().unwrap();
+
+        let mut index = Index::open(&dir.join("meginfo.idx")).unwrap();
+        assert_eq!(index.files(), files);
+        assert!(index.fresh(&files[0]).is_some());
+        assert_eq!(index.verify(), vec![]);
+
+        // only changed files are read again
+        write_file(&files[1], 500.0);
+        assert!(index.fresh(&files[1]).is_none());
+        assert_eq!(
+            index.verify(),
+            vec![(
+                files[1].clone(),
+                String::from("file has changed since it was indexed")
+            )]
+        );
+        assert_eq!(
+            index.update(&files),
+            Changes {
+                updated: 1,
+                unchanged: 1,
+                ..Default::default()
+            }
+        );
+
+        fs::remove_file(&files[0]).unwrap();
+        assert_eq!(
+            index.rebuild().to_string(),
+            "0 added, 0 updated, 1 unchanged, 1 removed"
+        );
+        assert_eq!(index.len(), 1);
+    }
+
+    #[test]
+    fn notices_replaced_files() {
+        let dir = std::env::temp_dir().join("meginfo-index-replaced");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("a.fif");
+        write_file(&file, 1000.0);
+
+        let mut index = Index::open(&dir.join("meginfo.idx")).unwrap();
+        index.update(std::slice::from_ref(&file));
+        let mtime = fs::metadata(&file).unwrap().modified().unwrap();
+
+        // another recording with the same size and modification time
+        write_file(&file, 1000.0);
+        File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+
+        assert!(index.fresh(&file).is_none());
+        assert_eq!(
+            index.verify(),
+            vec![(file, String::from("file_id differs from the file's"))]
+        );
+    }
+
+    #[test]
+    fn needs_an_index_file() {
+        let file = std::env::temp_dir().join("meginfo-index-not-an-index");
+        fs::write(&file, b"file,sfreq\n").unwrap();
+
+        assert!(Index::open(&file).is_err());
+    }
+}
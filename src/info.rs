@@ -0,0 +1,592 @@
+//! Human-readable summary of a measurement.
+//!
+//! Each part of the summary is read from the block it's stored in and left out if the block or
+//! tag is missing, so raw, evoked and MaxFiltered files each get the parts that apply to them.
+
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use petgraph::stable_graph::NodeIndex;
+
+use crate::enums::{BlockKind, DataTagKind};
+use crate::graph::Tree;
+use crate::parser::FifParser;
+use crate::tag::{Bitmask, ChInfo, Data, FiffNode, LabelledData};
+use crate::values::{ChType, SssJob};
+
+#[derive(Debug)]
+pub struct Info {
+    pub file: PathBuf,
+    /// Name and value of each field, in the order they're shown.  A field can appear more than
+    /// once, e.g. one history entry per processing step.
+    pub fields: Vec<(String, String)>,
+}
+
+impl Info {
+    pub fn read(file: &Path) -> Result<Self> {
+        let tree = FifParser::parse(file.to_owned())?;
+        Ok(Self::from_tree(file, &tree))
+    }
+
+    pub fn from_tree(file: &Path, tree: &Tree<FiffNode>) -> Self {
+        let summary = Summary::new(tree);
+        let mut fields = vec![];
+
+        let mut add = |name: &str, value: Option<String>| {
+            if let Some(value) = value {
+                fields.push((name.to_owned(), value));
+            }
+        };
+
+        add("subject", summary.subject());
+        add("system", summary.system());
+        add("gantry", summary.gantry());
+        add("meas_date", summary.show(&DataTagKind::MeasDate));
+        add("data", summary.data());
+        add("duration", summary.duration());
+        add("channels", summary.channels());
+        add("filter", summary.filter());
+        add("line_freq", summary.show(&DataTagKind::LineFreq));
+        add("bads", summary.bads());
+        add("hpi", summary.hpi());
+        add("head movement", summary.movement());
+        add("projectors", summary.projectors());
+        add("sss", summary.sss());
+        for entry in summary.history() {
+            add("history", Some(entry));
+        }
+
+        Info {
+            file: file.to_owned(),
+            fields,
+        }
+    }
+}
+
+impl Display for Info {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", self.file.display())?;
+
+        let width = self.fields.iter().map(|(x, _)| x.len()).max().unwrap_or(0);
+        let mut previous = None;
+
+        // repeated fields are only labelled once
+        for (name, value) in &self.fields {
+            let label = if previous == Some(name) { "" } else { name };
+            writeln!(f, "  {label:width$}  {value}")?;
+            previous = Some(name);
+        }
+
+        Ok(())
+    }
+}
+
+// bytes per sample of the dtypes used for data buffers
+pub(crate) fn sample_size(dtype: i32) -> Option<u64> {
+    match dtype {
+        2 | 16 => Some(2),
+        3 | 4 => Some(4),
+        5 | 20 => Some(8),
+        21 => Some(16),
+        _ => None,
+    }
+}
+
+fn first_int(data: Option<&Data>) -> Option<i32> {
+    match data? {
+        Data::Int32(x) => x.first().copied(),
+        _ => None,
+    }
+}
+
+fn first_float(data: Option<&Data>) -> Option<f32> {
+    match data? {
+        Data::Float(x) => x.first().copied(),
+        _ => None,
+    }
+}
+
+fn string(data: Option<&Data>) -> Option<String> {
+    Some(data?.to_string()).filter(|x| !x.is_empty())
+}
+
+// the parts of a file the summary is read from
+struct Summary<'a> {
+    tree: &'a Tree<FiffNode>,
+    // the first meas_info block, or the whole file if there isn't one
+    meas_info: NodeIndex,
+    channels: Vec<&'a ChInfo>,
+}
+
+impl<'a> Summary<'a> {
+    fn new(tree: &'a Tree<FiffNode>) -> Self {
+        let meas_info = tree
+            .blocks(&BlockKind::MeasInfo)
+            .first()
+            .copied()
+            .unwrap_or(tree.root);
+
+        let channels = tree
+            .tags_in(meas_info, &DataTagKind::ChInfo)
+            .into_iter()
+            .flat_map(|x| match x {
+                Data::ChInfoStruct(x) => x.iter().collect(),
+                _ => vec![],
+            })
+            .collect();
+
+        Summary {
+            tree,
+            meas_info,
+            channels,
+        }
+    }
+
+    fn find(&self, kind: &DataTagKind) -> Option<&'a Data> {
+        self.tree.find_tag(self.meas_info, kind)
+    }
+
+    fn show(&self, kind: &DataTagKind) -> Option<String> {
+        let data = self.find(kind)?;
+        Some(LabelledData::new(kind.clone(), data.clone()).to_string())
+    }
+
+    fn sfreq(&self) -> Option<f32> {
+        first_float(self.find(&DataTagKind::Sfreq)).filter(|x| *x > 0.0)
+    }
+
+    fn subject(&self) -> Option<String> {
+        let subject = self.tree.find_tag(self.tree.root, &DataTagKind::SubjHisId);
+        string(subject).or_else(|| {
+            first_int(self.tree.find_tag(self.tree.root, &DataTagKind::SubjId))
+                .map(|x| x.to_string())
+        })
+    }
+
+    fn system(&self) -> Option<String> {
+        let find = |kind| string(self.tree.find_tag(self.tree.root, &kind));
+
+        let mut parts: Vec<String> = [DataTagKind::DeviceType, DataTagKind::DeviceModel]
+            .into_iter()
+            .filter_map(find)
+            .collect();
+        if let Some(serial) = find(DataTagKind::DeviceSerial) {
+            parts.push(format!("serial {serial}"));
+        }
+
+        Some(parts.join(" ")).filter(|x| !x.is_empty())
+    }
+
+    fn gantry(&self) -> Option<String> {
+        let find = |kind| self.tree.find_tag(self.tree.root, &kind);
+
+        let mut parts: Vec<String> = vec![];
+        if let Some(model) = string(find(DataTagKind::GantryModel)) {
+            parts.push(model);
+        }
+        if let Some(angle) = first_int(find(DataTagKind::GantryAngle)) {
+            parts.push(format!("at {angle}°"));
+        }
+
+        Some(parts.join(" ")).filter(|x| !x.is_empty())
+    }
+
+    fn raw_blocks(&self) -> Vec<NodeIndex> {
+        [BlockKind::RawData, BlockKind::ContinuousData]
+            .iter()
+            .flat_map(|x| self.tree.blocks(x))
+            .collect()
+    }
+
+    fn data(&self) -> Option<String> {
+        let raw = self.raw_blocks();
+        let evoked = self.tree.blocks(&BlockKind::Evoked);
+
+        match (raw.is_empty(), evoked.len()) {
+            (false, _) => Some(String::from("raw")),
+            (true, 0) => None,
+            (true, 1) => Some(String::from("evoked")),
+            (true, n) => Some(format!("evoked ({n} sets)")),
+        }
+    }
+
+    // samples in the data buffers of a raw data block, including skipped ones
+    fn raw_samples(&self, block: NodeIndex, nchan: u64) -> u64 {
+        let mut samples = 0;
+        let mut buffer = 0;
+
+        for node in self.tree.children(block) {
+            let FiffNode::Tag { kind, data } = &self.tree[node] else {
+                continue;
+            };
+
+            match kind {
+                DataTagKind::DataBuffer => {
+                    buffer =
+                        sample_size(data.dtype()).map_or(0, |x| data.size() / (x * nchan).max(1));
+                    samples += buffer;
+                }
+                DataTagKind::DataSkip => {
+                    samples += buffer * first_int(Some(data)).unwrap_or(0).max(0) as u64
+                }
+                DataTagKind::DataSkipSamp => {
+                    samples += first_int(Some(data)).unwrap_or(0).max(0) as u64
+                }
+                _ => {}
+            }
+        }
+
+        samples
+    }
+
+    fn duration(&self) -> Option<String> {
+        let sfreq = self.sfreq()?;
+
+        let samples = match self.raw_blocks().first() {
+            Some(&block) => {
+                let nchan = first_int(self.find(&DataTagKind::Nchan))?.max(1) as u64;
+                self.raw_samples(block, nchan)
+            }
+            None => {
+                let evoked = *self.tree.blocks(&BlockKind::Evoked).first()?;
+                let first = first_int(self.tree.tag_in(evoked, &DataTagKind::FirstSample))?;
+                let last = first_int(self.tree.tag_in(evoked, &DataTagKind::LastSample))?;
+                (i64::from(last) - i64::from(first) + 1).max(0) as u64
+            }
+        };
+
+        Some(format!(
+            "{:.3} s ({samples} samples at {sfreq} Hz)",
+            samples as f64 / f64::from(sfreq)
+        ))
+    }
+
+    fn channels(&self) -> Option<String> {
+        if self.channels.is_empty() {
+            return self.show(&DataTagKind::Nchan);
+        }
+
+        // counts in the order of the channel type list, unknown types last
+        let mut counts: Vec<(ChType, usize)> = vec![];
+        for channel in &self.channels {
+            let kind = channel.ch_type();
+            match counts.iter_mut().find(|(x, _)| *x == kind) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((kind, 1)),
+            }
+        }
+        counts.sort_by_key(|(x, _)| {
+            ChType::ALL
+                .iter()
+                .position(|y| y == x)
+                .unwrap_or(usize::MAX)
+        });
+
+        let counts: Vec<String> = counts.iter().map(|(x, n)| format!("{n} {x}")).collect();
+        Some(format!("{} ({})", self.channels.len(), counts.join(", ")))
+    }
+
+    fn filter(&self) -> Option<String> {
+        let highpass = first_float(self.find(&DataTagKind::Highpass));
+        let lowpass = first_float(self.find(&DataTagKind::Lowpass));
+
+        match (highpass, lowpass) {
+            (Some(high), Some(low)) => Some(format!("{high} - {low} Hz")),
+            (Some(high), None) => Some(format!("highpass {high} Hz")),
+            (None, Some(low)) => Some(format!("lowpass {low} Hz")),
+            (None, None) => None,
+        }
+    }
+
+    // names from MNE's bad channel list, or else the channels numbered in bad_chs
+    fn bads(&self) -> Option<String> {
+        let mne = self
+            .tree
+            .blocks(&BlockKind::BadChannels)
+            .into_iter()
+            .find_map(|x| self.tree.tag_in(x, &DataTagKind::MneChNameList));
+
+        if let Some(Data::String(names)) = mne {
+            let names = crate::tag::trim_string(names);
+            return Some(if names.is_empty() {
+                String::from("none")
+            } else {
+                names.split(':').collect::<Vec<_>>().join(", ")
+            });
+        }
+
+        self.show(&DataTagKind::BadChs)
+    }
+
+    fn hpi(&self) -> Option<String> {
+        let meas = self.tree.blocks(&BlockKind::HpiMeas).first().copied();
+        let result = self.tree.blocks(&BlockKind::HpiResult).first().copied();
+
+        let ncoil = meas.and_then(|x| {
+            first_int(self.tree.tag_in(x, &DataTagKind::HpiNcoil)).or_else(|| {
+                let coils = self.tree.children(x).into_iter().filter(|y| {
+                    matches!(
+                        &self.tree[*y],
+                        FiffNode::Block {
+                            kind: BlockKind::HpiCoil
+                        }
+                    )
+                });
+                Some(coils.count() as i32).filter(|x| *x > 0)
+            })
+        });
+
+        let accepted = result
+            .and_then(|x| first_int(self.tree.tag_in(x, &DataTagKind::HpiFitAccept)))
+            .map(|x| Bitmask::new(x, "coil", 1));
+
+        match (ncoil, accepted) {
+            (Some(n), Some(accepted)) => Some(format!("{n} coils, accepted {accepted}")),
+            (Some(n), None) => Some(format!("{n} coils")),
+            (None, Some(accepted)) => Some(format!("accepted {accepted}")),
+            (None, None) => None,
+        }
+    }
+
+    fn sss_jobs(&self) -> Vec<SssJob> {
+        self.tree
+            .blocks(&BlockKind::SssInfo)
+            .into_iter()
+            .filter_map(|x| first_int(self.tree.tag_in(x, &DataTagKind::SssJob)))
+            .map(SssJob::from_code)
+            .collect()
+    }
+
+    fn movement(&self) -> Option<String> {
+        let compensated = self
+            .sss_jobs()
+            .iter()
+            .any(|x| matches!(x, SssJob::MovecFit | SssJob::MovecQua));
+
+        if compensated {
+            Some(String::from("compensated"))
+        } else if !self.tree.blocks(&BlockKind::HpiResult).is_empty() {
+            Some(String::from("not compensated"))
+        } else {
+            None
+        }
+    }
+
+    fn projectors(&self) -> Option<String> {
+        let items = self.tree.blocks(&BlockKind::SspItem);
+        if items.is_empty() {
+            return None;
+        }
+
+        let mut active = 0;
+        let names: Vec<String> = items
+            .iter()
+            .map(|&x| {
+                if first_int(self.tree.tag_in(x, &DataTagKind::MneProjItemActive)) == Some(1) {
+                    active += 1;
+                }
+                string(self.tree.tag_in(x, &DataTagKind::Name))
+                    .or_else(|| string(self.tree.tag_in(x, &DataTagKind::Description)))
+                    .unwrap_or_else(|| String::from("unnamed"))
+            })
+            .collect();
+
+        Some(format!(
+            "{} ({active} active): {}",
+            names.len(),
+            names.join(", ")
+        ))
+    }
+
+    fn sss(&self) -> Option<String> {
+        let blocks = self.tree.blocks(&BlockKind::SssInfo);
+        if blocks.is_empty() {
+            // only worth saying for measurements, not e.g. forward solutions
+            return self.data().map(|_| String::from("not applied"));
+        }
+
+        let mut parts: Vec<String> = self.sss_jobs().iter().map(|x| x.to_string()).collect();
+        let st_corr = blocks
+            .iter()
+            .find_map(|&x| first_float(self.tree.tag_in(x, &DataTagKind::SssStCorr)));
+        if let Some(corr) = st_corr {
+            parts.push(format!("tSSS correlation limit {corr}"));
+        }
+
+        Some(if parts.is_empty() {
+            String::from("applied")
+        } else {
+            format!("applied ({})", parts.join(", "))
+        })
+    }
+
+    fn history(&self) -> Vec<String> {
+        self.tree
+            .blocks(&BlockKind::ProcessingRecord)
+            .into_iter()
+            .map(|x| {
+                let creator = string(self.tree.tag_in(x, &DataTagKind::Creator))
+                    .unwrap_or_else(|| String::from("unknown program"));
+                match self.tree.tag_in(x, &DataTagKind::MeasDate) {
+                    Some(date) => format!(
+                        "{creator} at {}",
+                        LabelledData::new(DataTagKind::MeasDate, date.clone())
+                    ),
+                    None => creator,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(tree: &mut Tree<FiffNode>, kind: DataTagKind, data: Data) {
+        tree.add_child(FiffNode::Tag { kind, data });
+    }
+
+    // adds a block and moves into it, returning the block it was added to
+    fn block(tree: &mut Tree<FiffNode>, kind: BlockKind) -> NodeIndex {
+        let parent = tree.current();
+        let block = tree.add_child(FiffNode::Block { kind });
+        tree.move_to(block);
+        parent
+    }
+
+    fn channel(kind: i32, name: &str) -> Data {
+        Data::ChInfoStruct(vec![ChInfo {
+            scan_no: 1,
+            log_no: 1,
+            kind,
+            range: 1.0,
+            cal: 1.0,
+            coil_type: 0,
+            r0: [0.0; 3],
+            ex: [0.0; 3],
+            ey: [0.0; 3],
+            ez: [0.0; 3],
+            unit: 107,
+            unit_mul: 0,
+            name: name.to_owned(),
+        }])
+    }
+
+    #[test]
+    fn can_summarize_raw_file() {
+        let mut tree = Tree::new();
+        let meas = block(&mut tree, BlockKind::Meas);
+        let info = block(&mut tree, BlockKind::MeasInfo);
+        tag(&mut tree, DataTagKind::Nchan, Data::Int32(vec![3]));
+        tag(&mut tree, DataTagKind::Sfreq, Data::Float(vec![1000.0]));
+        tag(&mut tree, DataTagKind::Highpass, Data::Float(vec![0.1]));
+        tag(&mut tree, DataTagKind::Lowpass, Data::Float(vec![330.0]));
+        tag(&mut tree, DataTagKind::ChInfo, channel(1, "MEG 0113"));
+        tag(&mut tree, DataTagKind::ChInfo, channel(2, "EEG 001"));
+        tag(&mut tree, DataTagKind::ChInfo, channel(1, "MEG 0112"));
+
+        let parent = block(&mut tree, BlockKind::BadChannels);
+        tag(
+            &mut tree,
+            DataTagKind::MneChNameList,
+            Data::String("MEG 0113:EEG 001".into()),
+        );
+        tree.move_to(parent);
+
+        let parent = block(&mut tree, BlockKind::HpiResult);
+        tag(
+            &mut tree,
+            DataTagKind::HpiFitAccept,
+            Data::Int32(vec![0b1011]),
+        );
+        tree.move_to(parent);
+
+        let parent = block(&mut tree, BlockKind::Ssp);
+        block(&mut tree, BlockKind::SspItem);
+        tag(&mut tree, DataTagKind::Name, Data::String("PCA-v1".into()));
+        tag(
+            &mut tree,
+            DataTagKind::MneProjItemActive,
+            Data::Int32(vec![1]),
+        );
+        tree.move_to(parent);
+
+        tree.move_to(info);
+        block(&mut tree, BlockKind::RawData);
+        for _ in 0..2 {
+            tag(
+                &mut tree,
+                DataTagKind::DataBuffer,
+                Data::Float(vec![0.0; 30]),
+            );
+        }
+        tag(&mut tree, DataTagKind::DataSkip, Data::Int32(vec![2]));
+        tree.move_to(meas);
+
+        let info = Info::from_tree(Path::new("raw.fif"), &tree);
+        let fields: Vec<(&str, &str)> = info
+            .fields
+            .iter()
+            .map(|(x, y)| (x.as_str(), y.as_str()))
+            .collect();
+
+        assert_eq!(
+            fields,
+            vec![
+                ("data", "raw"),
+                ("duration", "0.040 s (40 samples at 1000 Hz)"),
+                ("channels", "3 (2 MEG, 1 EEG)"),
+                ("filter", "0.1 - 330 Hz"),
+                ("bads", "MEG 0113, EEG 001"),
+                ("hpi", "accepted coil1|coil2|coil4"),
+                ("head movement", "not compensated"),
+                ("projectors", "1 (1 active): PCA-v1"),
+                ("sss", "not applied"),
+            ]
+        );
+    }
+
+    #[test]
+    fn can_summarize_processed_file() {
+        let mut tree = Tree::new();
+        block(&mut tree, BlockKind::Meas);
+        let info = block(&mut tree, BlockKind::MeasInfo);
+        tag(&mut tree, DataTagKind::Sfreq, Data::Float(vec![1000.0]));
+
+        block(&mut tree, BlockKind::ProcessingHistory);
+        block(&mut tree, BlockKind::ProcessingRecord);
+        tag(
+            &mut tree,
+            DataTagKind::Creator,
+            Data::String("maxfilter".into()),
+        );
+        block(&mut tree, BlockKind::SssInfo);
+        tag(&mut tree, DataTagKind::SssJob, Data::Int32(vec![5]));
+        tag(&mut tree, DataTagKind::SssStCorr, Data::Float(vec![0.98]));
+
+        tree.move_to(info);
+        block(&mut tree, BlockKind::Evoked);
+        tag(&mut tree, DataTagKind::FirstSample, Data::Int32(vec![-200]));
+        tag(&mut tree, DataTagKind::LastSample, Data::Int32(vec![499]));
+
+        let info = Info::from_tree(Path::new("ave.fif"), &tree).to_string();
+        assert_eq!(
+            info,
+            "ave.fif
+  data           evoked
+  duration       0.700 s (700 samples at 1000 Hz)
+  head movement  compensated
+  sss            applied (movec_fit, tSSS correlation limit 0.98)
+  history        maxfilter
+"
+        );
+    }
+
+    #[test]
+    fn can_summarize_empty_file() {
+        let info = Info::from_tree(Path::new("empty.fif"), &Tree::new());
+        assert_eq!(info.to_string(), "empty.fif\n");
+    }
+}
@@ -12,42 +12,376 @@
 //! parse the files and extract metadata without having to load the entire file into memory.
 //!
 
+pub mod anonymize;
+pub mod channels;
 pub mod config;
+pub mod date;
+pub mod derived;
+pub mod dictionary;
+pub mod diff;
+pub mod dig;
+pub mod dump;
 pub mod enums;
+pub mod expr;
+pub mod files;
 pub mod graph;
+pub mod index;
+pub mod info;
 pub mod parser;
+pub mod patch;
 pub mod query;
+pub mod stats;
 pub mod tag;
+pub mod validate;
+pub mod values;
+pub mod view;
+pub mod writer;
 
-use anyhow;
-use config::Config;
+use std::path::{Path, PathBuf};
+
+use anonymize::Report;
+use anyhow::anyhow;
+use channels::Channels;
+use config::{
+    AnonymizeConfig, ChannelsConfig, Config, DiffConfig, DigConfig, DumpConfig, IndexCommand,
+    IndexConfig, InfoConfig, QueryConfig, SetConfig, StatsConfig, TagsConfig, TreeConfig,
+    ValidateConfig,
+};
+use diff::{Diff, DiffFormat};
+use dig::{Dig, DigFormat};
+use dump::Dump;
+use index::Index;
+use info::Info;
 use parser::FifParser;
+use patch::Patch;
 use query::Search;
+use stats::{Stats, StatsFormat};
+use view::TreeFormat;
+
+/// Executes the subcommand given by the supplied Config.
+pub fn run(config: Config) -> anyhow::Result<()> {
+    match config {
+        Config::Query(config) => query(config),
+        Config::Stats(config) => stats(config),
+        Config::Tree(config) => tree(config),
+        Config::Info(config) => info(config),
+        Config::Channels(config) => channels(config),
+        Config::Dig(config) => dig(config),
+        Config::Dump(config) => dump(config),
+        Config::Tags(config) => tags(config),
+        Config::Validate(config) => validate(config),
+        Config::Diff(config) => diff(config),
+        Config::Index(config) => index(config),
+        Config::Anonymize(config) => anonymize(config),
+        Config::Set(config) => set(config),
+    }
+}
+
+/// Searches for the given tags in all supplied files, printing a row per file as CSV.
+pub fn query(config: QueryConfig) -> anyhow::Result<()> {
+    for tag in config.describe_tags {
+        println!("{tag}");
+    }
+
+    let (mut index, files) = open_index(config.index.as_deref(), config.files)?;
+
+    let mut search = Search::new(config.columns, files);
+    if let Some(condition) = config.condition {
+        search = search.with_condition(condition);
+    }
+    if config.local_time {
+        search = search.with_local_time();
+    }
+
+    execute(&mut search, index.as_mut())?;
+    println!("{search}");
+
+    Ok(())
+}
+
+/// Prints counts, summaries, histograms and frequencies of tags across files, grouped by tags.
+pub fn stats(config: StatsConfig) -> anyhow::Result<()> {
+    let (mut index, files) = open_index(config.index.as_deref(), config.files)?;
+
+    let mut search = Search::new(config.options.columns(), files);
+    if let Some(condition) = config.condition {
+        search = search.with_condition(condition);
+    }
+
+    execute(&mut search, index.as_mut())?;
+
+    let results = search.results().into_iter().map(|(x, y)| (x.as_path(), y));
+    let stats = Stats::new(config.options, results);
+    match config.format {
+        StatsFormat::Csv => print!("{}", stats.to_csv()?),
+        StatsFormat::Json => println!("{}", serde_json::to_string_pretty(&stats.to_json())?),
+    }
+
+    Ok(())
+}
+
+// an index, if one is given, and the files to search: all indexed files if none were given
+fn open_index(
+    path: Option<&Path>,
+    files: Vec<PathBuf>,
+) -> anyhow::Result<(Option<Index>, Vec<PathBuf>)> {
+    let index = path.map(Index::open).transpose()?;
+
+    let files = match &index {
+        Some(index) if files.is_empty() => index.files(),
+        _ => files,
+    };
+
+    Ok((index, files))
+}
+
+// answer from the index where there is one, saving what was read into it
+fn execute(search: &mut Search, index: Option<&mut Index>) -> anyhow::Result<()> {
+    match index {
+        Some(index) => {
+            search.execute_with_index(index);
+            index.save()
+        }
+        None => {
+            search.execute();
+            Ok(())
+        }
+    }
+}
+
+/// Updates, checks or rebuilds an index.
+pub fn index(config: IndexConfig) -> anyhow::Result<()> {
+    let mut index = Index::open(&config.index)?;
+
+    match config.action {
+        IndexCommand::Update(files) => {
+            println!("{}", index.update(&files));
+            index.save()
+        }
+        IndexCommand::Rebuild => {
+            println!("{}", index.rebuild());
+            index.save()
+        }
+        IndexCommand::Verify => {
+            let problems = index.verify();
+            for (file, problem) in &problems {
+                println!("{}: {problem}", file.display());
+            }
+
+            match problems.len() {
+                0 => {
+                    println!("{} files ok", index.len());
+                    Ok(())
+                }
+                n => Err(anyhow!(
+                    "{n} of {} indexed files are out of date, run `meginfo index rebuild`",
+                    index.len()
+                )),
+            }
+        }
+    }
+}
+
+/// Prints the tree of blocks and tags of each file.
+pub fn tree(config: TreeConfig) -> anyhow::Result<()> {
+    // files are written as a single JSON array
+    let mut json = vec![];
+
+    for file in config.files {
+        let (tree, positions) = FifParser::parse_with_positions(file.clone())?;
+        let name = file.display().to_string();
+
+        match config.format {
+            TreeFormat::Text => {
+                println!("{name}");
+                println!("{}", view::render_text(&tree, &config.options));
+            }
+            TreeFormat::Json => json.push(serde_json::json!({
+                "file": name,
+                "root": view::render_json(&tree, &positions, &config.options),
+            })),
+            TreeFormat::Dot => print!("{}", view::render_dot(&tree, &name, &config.options)),
+        }
+    }
+
+    if config.format == TreeFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&json)?);
+    }
+
+    Ok(())
+}
+
+/// Prints a summary of each measurement.
+pub fn info(config: InfoConfig) -> anyhow::Result<()> {
+    for file in config.files {
+        println!("{}", Info::read(&file)?);
+    }
+
+    Ok(())
+}
+
+/// Prints a table of the channels in all files, with a row per channel.
+pub fn channels(config: ChannelsConfig) -> anyhow::Result<()> {
+    let files = config
+        .files
+        .iter()
+        .map(|x| Ok(Channels::read(x)?.pick(&config.picks)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    print!("{}", channels::to_table(&files, config.format)?);
+
+    Ok(())
+}
 
-/// Executes the main program using the supplied Config.
+/// Exports the digitized points of a file, in another coordinate frame if one is given.
 ///
-/// Can fail if reading tags or creating the tree fails.
+/// Transformations are read from the file, and from a separate file (e.g. a -trans.fif) if one is
+/// given.
+pub fn dig(config: DigConfig) -> anyhow::Result<()> {
+    let mut dig = Dig::read(&config.file)?;
+
+    if let Some(trans) = &config.trans {
+        let tree = FifParser::parse(trans.clone())?;
+        dig = dig.with_transforms(dig::transforms(&tree));
+    }
+    if let Some(frame) = config.frame {
+        dig = dig.to_frame(frame)?;
+    }
+
+    match (&config.output, config.format) {
+        (Some(output), format) => {
+            for file in dig.save(format, output)? {
+                println!("{}", file.display());
+            }
+        }
+        (None, DigFormat::Csv) => print!("{}", dig.to_csv()?),
+        (None, DigFormat::Pos) => print!("{}", dig.to_pos()),
+        (None, DigFormat::Bids) => return Err(anyhow!("BIDS output needs an output prefix")),
+    }
+
+    Ok(())
+}
+
+/// Lists the tags in each file as they're read, with their headers and the start of their data.
+pub fn dump(config: DumpConfig) -> anyhow::Result<()> {
+    for file in config.files {
+        println!("{}", Dump::read(&file, &config.options)?);
+    }
+
+    Ok(())
+}
+
+/// Prints descriptions of tags from the dictionary.
+pub fn tags(config: TagsConfig) -> anyhow::Result<()> {
+    for definition in config.definitions {
+        println!("{definition}");
+    }
+
+    Ok(())
+}
+
+/// Checks the structure of each file, printing any problems found.
 ///
-/// If show_tree is true, will print a representation of the entire fif tree for all files.
+/// Fails if any file has problems, so that the exit code can be checked by scripts.
+pub fn validate(config: ValidateConfig) -> anyhow::Result<()> {
+    let mut invalid = 0;
+
+    for file in &config.files {
+        let problems = validate::validate_file(file);
+
+        if problems.is_empty() {
+            println!("{}: ok", file.display());
+        } else {
+            invalid += 1;
+            println!("{}: {} problem(s)", file.display(), problems.len());
+            for problem in problems {
+                println!("  {problem}");
+            }
+        }
+    }
+
+    match invalid {
+        0 => Ok(()),
+        n => Err(anyhow!("{n} of {} files are invalid", config.files.len())),
+    }
+}
+
+/// Compares two files, failing if they differ.
+pub fn diff(config: DiffConfig) -> anyhow::Result<()> {
+    let diff = Diff::read(&config.a, &config.b, &config.options)?;
+
+    match config.format {
+        DiffFormat::Text => print!("{diff}"),
+        DiffFormat::Json => println!("{}", serde_json::to_string_pretty(&diff)?),
+    }
+
+    match diff.differences.len() {
+        0 => Ok(()),
+        n => Err(anyhow!("{n} difference(s) between files")),
+    }
+}
+
+/// Anonymizes all files in the supplied AnonymizeConfig, then prints (or saves) the audit report.
 ///
-/// Otherwise, will search for the given tags in all supplied files.
+/// Without an output directory, files are modified in place.  Files keep their names, so that
+/// references between parts of a split recording stay valid, and so must all be named differently.
+pub fn anonymize(config: AnonymizeConfig) -> anyhow::Result<()> {
+    let mut report = Report::default();
+
+    if let Some(dir) = &config.output_dir {
+        // files found in different directories could have the same name
+        let mut names = std::collections::HashSet::new();
+        for file in &config.files {
+            if !names.insert(file.file_name()) {
+                return Err(anyhow!(
+                    "More than one file is named {:?}, they would overwrite each other in {dir:?}",
+                    file.file_name().unwrap_or_default()
+                ));
+            }
+        }
+
+        std::fs::create_dir_all(dir)?;
+    }
+
+    for file in config.files {
+        let mut changes = match &config.output_dir {
+            Some(dir) => {
+                let name = file.file_name().ok_or(anyhow!("{file:?} is not a file"))?;
+                config.anonymizer.anonymize_file(&file, &dir.join(name))?
+            }
+            None => config.anonymizer.anonymize_in_place(&file)?,
+        };
+
+        report.0.append(&mut changes);
+    }
+
+    match config.report {
+        Some(path) => std::fs::write(path, report.to_string())?,
+        None => print!("{report}"),
+    }
+
+    Ok(())
+}
+
+/// Sets the selected tag to a new value in all files in the supplied SetConfig.
 ///
-pub fn run(config: Config) -> anyhow::Result<()> {
-    for tag in config.describe_tags {
-        println!("{tag}");
+/// The changes to every file are planned (and printed) before any is written, so that if the
+/// tag can't be set in one file, none are changed.  With dry_run, nothing is written.
+pub fn set(config: SetConfig) -> anyhow::Result<()> {
+    let patches = config
+        .files
+        .iter()
+        .map(|x| Patch::plan(x, &config.selector, &config.value))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    for patch in &patches {
+        println!("{patch}");
     }
 
-    if config.show_tree {
-        for file in config.files {
-            println!("fif tree for {file:?}: \n");
-            let tags = FifParser::read_tags(file)?;
-            let tree = FifParser::make_fif_tree(tags)?;
-            println!("{tree}");
+    if !config.dry_run {
+        for patch in &patches {
+            patch.apply()?;
         }
-    } else {
-        let mut search = Search::new(config.query_codes, config.files);
-        search.execute();
-        println!("{search}");
     }
 
     Ok(())
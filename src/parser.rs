@@ -2,6 +2,8 @@
 
 use anyhow::{Context, Result};
 use log::{info, warn};
+use petgraph::stable_graph::NodeIndex;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{self, Read, Seek};
 use std::path::PathBuf;
@@ -10,12 +12,25 @@ use std::vec;
 use crate::enums::BlockTagKind;
 use crate::graph::Tree;
 
-use crate::tag::{tag_header, FiffNode, Tag};
+use crate::tag::{tag_header, FiffNode, Header, Tag};
 
 // contains main file reading and parsing loop
 
 pub struct FifParser;
 
+/// Position in the file of the header of each node in a tree.
+pub type Positions = HashMap<NodeIndex, u64>;
+
+/// A tag as found in a file: where its header starts, the header as read, and the first bytes of
+/// its payload, along with the decoded tag.
+#[derive(Debug, PartialEq, Clone)]
+pub struct RawTag {
+    pub position: u64,
+    pub header: Header,
+    pub preview: Vec<u8>,
+    pub tag: Tag,
+}
+
 impl FifParser {
     pub fn parse(file: PathBuf) -> Result<Tree<FiffNode>> {
         let tags = Self::read_tags(file)?;
@@ -26,77 +41,137 @@ impl FifParser {
     pub fn read_tags(file: PathBuf) -> Result<Vec<Tag>> {
         let fh = File::open(&file).with_context(|| format!("No file found at {:?}", &file))?;
 
-        let file_length = fh.metadata().unwrap().len();
+        let file_length = fh.metadata()?.len();
 
         const BUFFER_SIZE: usize = 8192;
+        let reader = io::BufReader::with_capacity(BUFFER_SIZE, fh);
+
+        let tags = Self::read_tags_from(reader)?;
+        info!("file is {} bytes long", file_length);
+
+        Ok(tags)
+    }
+
+    /// Read all tags from any seekable source, e.g. an in-memory buffer.
+    ///
+    /// Payloads larger than 512 bytes are not read, but recorded by position (`Data::InFile`).
+    pub fn read_tags_from<R: Read + Seek>(reader: R) -> Result<Vec<Tag>> {
+        let tags = Self::read_positioned_tags_from(reader)?;
+        Ok(tags.into_iter().map(|(_, tag)| tag).collect())
+    }
+
+    /// Read all tags along with the position of their header in the file.
+    pub fn read_positioned_tags_from<R: Read + Seek>(reader: R) -> Result<Vec<(u64, Tag)>> {
+        let tags = Self::read_raw_tags_from(reader, 0)?;
+        Ok(tags.into_iter().map(|x| (x.position, x.tag)).collect())
+    }
+
+    /// Read all tags along with their headers, keeping up to preview bytes of each payload.
+    pub fn read_raw_tags_from<R: Read + Seek>(
+        mut reader: R,
+        preview: usize,
+    ) -> Result<Vec<RawTag>> {
         const MAX_PARSE_SIZE: u64 = 512;
 
-        let mut reader = io::BufReader::with_capacity(BUFFER_SIZE, fh);
         let mut header_buf = [0u8; 16];
-        let mut tags: Vec<Tag> = vec![];
+        let mut tags: Vec<RawTag> = vec![];
 
-        let mut position = 0u64;
+        let mut position = reader.stream_position()?;
 
         while let Ok(()) = reader.read_exact(&mut header_buf) {
-            let (_, (size, tag_header)) = tag_header(&header_buf).unwrap();
+            let (_, (size, tag_header)) = tag_header(&header_buf)
+                .map_err(|e| e.to_owned())
+                .with_context(|| format!("Could not read tag header at {position}"))?;
+            let header_position = position;
+            let header = tag_header.clone();
             position += 16;
 
-            let tag = if size > MAX_PARSE_SIZE {
-                reader.seek_relative(size as i64).unwrap();
-                Tag::from_header_file_position(tag_header, position, size)
+            let (tag, preview) = if size > MAX_PARSE_SIZE {
+                // only the start of large payloads is read, for the preview
+                let mut preview = vec![0; preview.min(size as usize)];
+                reader
+                    .read_exact(&mut preview)
+                    .with_context(|| format!("Truncated tag at {position}"))?;
+                reader.seek_relative(size as i64 - preview.len() as i64)?;
+
+                let tag = Tag::from_header_file_position(tag_header, position, size);
+                (tag, preview)
             } else {
                 let mut data_buf = vec![0; size as usize];
-                reader.read_exact(&mut data_buf).unwrap();
-                Tag::from_header_slice(tag_header, data_buf)
+                reader
+                    .read_exact(&mut data_buf)
+                    .with_context(|| format!("Truncated tag at {position}"))?;
+
+                let preview = data_buf[..preview.min(data_buf.len())].to_vec();
+                (Tag::from_header_slice(tag_header, data_buf), preview)
             };
 
             position += size;
 
             match tag {
-                Ok(tag) => tags.push(tag),
+                Ok(tag) => tags.push(RawTag {
+                    position: header_position,
+                    header,
+                    preview,
+                    tag,
+                }),
                 Err(e) => warn!("{e}"),
             }
         }
 
         let cur_pos = reader
-            .seek(io::SeekFrom::Current(0))
+            .stream_position()
             .expect("should be able to seek to current position");
 
         info!(
-            "Finished reading, cursor at {} bytes (tracked {}), file is {} bytes long",
-            cur_pos, position, file_length
+            "Finished reading, cursor at {} bytes (tracked {})",
+            cur_pos, position
         );
 
         Ok(tags)
     }
 
+    /// Parse a file into a tree, along with the position in the file of each node's header (the
+    /// block_start tag, for blocks).
+    pub fn parse_with_positions(file: PathBuf) -> Result<(Tree<FiffNode>, Positions)> {
+        let fh = File::open(&file).with_context(|| format!("No file found at {:?}", &file))?;
+        let tags = Self::read_positioned_tags_from(io::BufReader::new(fh))?;
+        Ok(Self::make_positioned_tree(tags))
+    }
+
     pub fn make_fif_tree(tags: Vec<Tag>) -> Result<Tree<FiffNode>> {
+        let (tree, _) = Self::make_positioned_tree(tags.into_iter().map(|x| (0, x)).collect());
+        Ok(tree)
+    }
+
+    pub fn make_positioned_tree(tags: Vec<(u64, Tag)>) -> (Tree<FiffNode>, Positions) {
         let mut tree = Tree::new();
+        let mut positions = Positions::new();
         let mut stack = vec![];
         let mut curr = tree.root;
 
-        for tag in tags {
+        for (position, tag) in tags {
             match &tag {
                 Tag::Block { kind, .. } => match kind {
                     BlockTagKind::BlockStart => {
                         stack.push(curr);
                         let child = tree.add_child(FiffNode::from_tag(tag));
+                        positions.insert(child, position);
                         tree.move_to(child);
                         curr = child;
                     }
                     BlockTagKind::BlockEnd => {
-                        tree.move_to(stack.pop().unwrap());
-                    }
-                    _ => {
-                        info!("ignored tag: {:?}", &tag)
+                        curr = stack.pop().unwrap_or(tree.root);
+                        tree.move_to(curr);
                     }
                 },
                 Tag::Data { .. } => {
-                    tree.add_child(FiffNode::from_tag(tag));
+                    let child = tree.add_child(FiffNode::from_tag(tag));
+                    positions.insert(child, position);
                 }
             }
         }
 
-        Ok(tree)
+        (tree, positions)
     }
 }
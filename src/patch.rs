@@ -0,0 +1,594 @@
+//! Change the value of a single tag in an existing file, without rewriting the whole file.
+//!
+//! When the new value encodes to as many bytes as the old one, the payload is overwritten where
+//! it is.  Otherwise the old tag is turned into a nop and the new tag appended to the end of the
+//! file, along with a new tag directory listing the new tag in place of the old one, so that
+//! readers going by the directory still find it in its original block.  Files without a
+//! directory can only be changed in place, as an appended tag would be outside its block.
+
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::enums::DataTagKind;
+use crate::parser::FifParser;
+use crate::tag::{self, Data, LabelledData, Tag, FIFFV_NEXT_NONE, FIFFV_NEXT_SEQ};
+use crate::writer::{encode_data, encode_dir, encode_header};
+
+/// Which tag to change: a tag name, optionally followed by which occurrence (counting from 0)
+/// in brackets, e.g. `line_freq` or `bad_chs[1]`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Selector {
+    pub kind: DataTagKind,
+    pub index: Option<usize>,
+}
+
+impl Selector {
+    pub fn new(selector: &str) -> Result<Self> {
+        let (name, index) = match selector.strip_suffix(']').and_then(|x| x.split_once('[')) {
+            Some((name, index)) => (
+                name,
+                Some(
+                    index
+                        .parse::<usize>()
+                        .with_context(|| format!("Invalid index in selector {selector:?}"))?,
+                ),
+            ),
+            None => (selector, None),
+        };
+
+        Ok(Selector {
+            kind: tag::find_tag(name)?,
+            index,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Method {
+    InPlace,
+    Append,
+}
+
+/// A planned change to one tag in a file.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Patch {
+    pub file: PathBuf,
+    pub kind: DataTagKind,
+    pub position: u64,
+    pub before: Data,
+    pub after: Data,
+    pub method: Method,
+}
+
+impl Patch {
+    /// Work out how to set the selected tag to a new value, without changing the file.
+    pub fn plan(file: &Path, selector: &Selector, value: &str) -> Result<Self> {
+        let mut fh = File::open(file).with_context(|| format!("No file found at {file:?}"))?;
+        let tags = FifParser::read_positioned_tags_from(BufReader::new(fh.try_clone()?))?;
+
+        let matching: Vec<(u64, Data)> = tags
+            .iter()
+            .filter_map(|(position, tag)| match tag {
+                Tag::Data { kind, data, .. } if *kind == selector.kind => {
+                    Some((*position, data.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+
+        let (position, before) = match (selector.index, matching.len()) {
+            (_, 0) => return Err(anyhow!("No {:?} tag in {file:?}", selector.kind)),
+            (None, 1) => matching[0].clone(),
+            (None, n) => {
+                return Err(anyhow!(
+                    "{n} {:?} tags in {file:?}, select one with e.g. [0]",
+                    selector.kind
+                ))
+            }
+            (Some(index), n) => matching
+                .get(index)
+                .ok_or(anyhow!(
+                    "Only {n} {:?} tags in {file:?}, can't select [{index}]",
+                    selector.kind
+                ))?
+                .clone(),
+        };
+
+        if let Data::InFile { .. } = before {
+            return Err(anyhow!("{:?} is too large to set", selector.kind));
+        }
+
+        // enumerated values can be given by name, as for anonymize --replace, but the type of
+        // the tag in the file wins over the dictionary's
+        let after = match Data::parse_for(&selector.kind, value) {
+            Ok(after) if after.dtype() == before.dtype() => after,
+            _ => before.parse_as(value)?,
+        };
+
+        let method = if encode_data(&after)?.len() == encode_data(&before)?.len() {
+            Method::InPlace
+        } else {
+            if find_directory(&mut fh, &tags)?.is_none() {
+                return Err(no_directory(file));
+            }
+            Method::Append
+        };
+
+        Ok(Patch {
+            file: file.to_owned(),
+            kind: selector.kind.clone(),
+            position,
+            before,
+            after,
+            method,
+        })
+    }
+
+    /// Write the change to the file.
+    pub fn apply(&self) -> Result<()> {
+        let mut fh = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&self.file)
+            .with_context(|| format!("Could not open {:?} for writing", self.file))?;
+
+        match self.method {
+            Method::InPlace => {
+                fh.seek(SeekFrom::Start(self.position + 16))?;
+                fh.write_all(&encode_data(&self.after)?)?;
+            }
+            Method::Append => self.append(&mut fh)?,
+        }
+
+        fh.flush()?;
+        Ok(())
+    }
+
+    // everything that can fail is worked out before the first write, so that a failure leaves the
+    // file as it was
+    fn append(&self, fh: &mut File) -> Result<()> {
+        let tags = FifParser::read_positioned_tags_from(BufReader::new(fh.try_clone()?))?;
+
+        let Directory {
+            pointer,
+            position: dir_old_position,
+            size: dir_old_size,
+            mut entries,
+        } = find_directory(fh, &tags)?.ok_or_else(|| no_directory(&self.file))?;
+
+        let old_size = position_i32(encode_data(&self.before)?.len() as u64)?;
+        let old_next = tags
+            .iter()
+            .find(|(position, _)| *position == self.position)
+            .map_or(FIFFV_NEXT_SEQ, |(_, tag)| tag.next());
+        let end_marked: Vec<u64> = tags
+            .iter()
+            .filter(|(_, tag)| tag.next() == FIFFV_NEXT_NONE)
+            .map(|(position, _)| *position)
+            .collect();
+
+        let end = fh.seek(SeekFrom::End(0))?;
+        let payload = encode_data(&self.after)?;
+        let payload_size = position_i32(payload.len() as u64)?;
+        let new_position = position_i32(end)?;
+
+        let entry = tag::DirEntry {
+            code: self.kind.to_code(),
+            dtype: self.after.dtype(),
+            size: payload_size,
+            position: new_position,
+        };
+        match entries
+            .iter_mut()
+            .find(|x| x.position as u64 == self.position)
+        {
+            Some(old) => *old = entry,
+            None => entries.push(entry),
+        }
+
+        // some files list the directory itself, which is about to become a nop
+        if let Some(old_dir) = entries
+            .iter_mut()
+            .find(|x| x.position as u64 == dir_old_position)
+        {
+            old_dir.code = DataTagKind::Nop.to_code();
+            old_dir.dtype = 0;
+        }
+
+        let dir = encode_data(&encode_dir(&entries))?;
+        let dir_size = position_i32(dir.len() as u64)?;
+        let dir_position = position_i32(end + 16 + payload.len() as u64)?;
+        let dir_next = if end_marked.is_empty() {
+            FIFFV_NEXT_SEQ
+        } else {
+            FIFFV_NEXT_NONE
+        };
+
+        // the new tag and directory go at the end, before anything already in the file changes
+        fh.write_all(&encode_header(
+            self.kind.to_code(),
+            self.after.dtype(),
+            payload_size,
+            FIFFV_NEXT_SEQ,
+        ))?;
+        fh.write_all(&payload)?;
+        fh.write_all(&encode_header(
+            DataTagKind::Dir.to_code(),
+            tag::DIR_ENTRY_STRUCT,
+            dir_size,
+            dir_next,
+        ))?;
+        fh.write_all(&dir)?;
+
+        // whatever marked the end of the file is no longer last
+        for position in end_marked {
+            overwrite_next(fh, position, FIFFV_NEXT_SEQ)?;
+        }
+
+        // the old tag and directory become reserved space
+        overwrite_header(
+            fh,
+            self.position,
+            DataTagKind::Nop.to_code(),
+            0,
+            old_size,
+            old_next,
+        )?;
+        overwrite_header(
+            fh,
+            dir_old_position,
+            DataTagKind::Nop.to_code(),
+            0,
+            dir_old_size,
+            FIFFV_NEXT_SEQ,
+        )?;
+
+        fh.seek(SeekFrom::Start(pointer + 16))?;
+        fh.write_all(&dir_position.to_be_bytes())?;
+
+        Ok(())
+    }
+}
+
+// without a directory, a tag appended to the end of the file would be outside its block
+fn no_directory(file: &Path) -> anyhow::Error {
+    anyhow!(
+        "{} has no tag directory, so a value of a different size can't be written without moving the tag out of its block",
+        file.display()
+    )
+}
+
+impl Display for Patch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let before = LabelledData::new(self.kind.clone(), self.before.clone());
+        let after = LabelledData::new(self.kind.clone(), self.after.clone());
+
+        let method = match self.method {
+            Method::InPlace => format!("in place at byte {}", self.position),
+            Method::Append => format!("appended, old tag at byte {} becomes a nop", self.position),
+        };
+
+        write!(
+            f,
+            "{}: {:?} {} -> {} ({})",
+            self.file.display(),
+            self.kind,
+            before,
+            after,
+            method
+        )
+    }
+}
+
+// where the dir pointer and the dir it points to are, along with the dir contents
+struct Directory {
+    pointer: u64,
+    position: u64,
+    size: i32,
+    entries: Vec<tag::DirEntry>,
+}
+
+fn find_directory(fh: &mut File, tags: &[(u64, Tag)]) -> Result<Option<Directory>> {
+    let pointer = tags.iter().find_map(|(position, tag)| match tag {
+        Tag::Data {
+            kind: DataTagKind::DirPointer,
+            data: Data::Int32(x),
+            ..
+        } if !x.is_empty() && x[0] > 0 => Some((*position, x[0] as u64)),
+        _ => None,
+    });
+
+    let Some((pointer, dir_position)) = pointer else {
+        return Ok(None);
+    };
+
+    let dir = tags.iter().find(|(position, tag)| {
+        *position == dir_position
+            && matches!(
+                tag,
+                Tag::Data {
+                    kind: DataTagKind::Dir,
+                    ..
+                }
+            )
+    });
+
+    let Some((position, dir)) = dir else {
+        return Err(anyhow!(
+            "Directory pointer leads to {dir_position}, where there is no directory"
+        ));
+    };
+
+    let bytes = match dir.data() {
+        Data::InFile { start, size, .. } => {
+            let mut bytes = vec![0u8; *size as usize];
+            fh.seek(SeekFrom::Start(*start))?;
+            fh.read_exact(&mut bytes)?;
+            bytes
+        }
+        data => encode_data(data)?,
+    };
+
+    let (_, entries) = tag::dir_entries(&bytes).map_err(|e| anyhow!("Invalid directory: {e}"))?;
+
+    Ok(Some(Directory {
+        pointer,
+        position: *position,
+        size: bytes.len() as i32,
+        entries,
+    }))
+}
+
+fn overwrite_header(
+    fh: &mut File,
+    position: u64,
+    code: i32,
+    dtype: i32,
+    size: i32,
+    next: i32,
+) -> Result<()> {
+    fh.seek(SeekFrom::Start(position))?;
+    fh.write_all(&encode_header(code, dtype, size, next))?;
+    Ok(())
+}
+
+fn overwrite_next(fh: &mut File, position: u64, next: i32) -> Result<()> {
+    fh.seek(SeekFrom::Start(position + 12))?;
+    fh.write_all(&next.to_be_bytes())?;
+    Ok(())
+}
+
+// file positions are stored as int32
+fn position_i32(position: u64) -> Result<i32> {
+    i32::try_from(position).map_err(|_| anyhow!("File position {position} too large for FIFF"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::BlockKind;
+    use crate::graph::Tree;
+    use crate::tag::{FiffNode, IdStruct};
+    use crate::writer::FifWriter;
+    use std::fs;
+
+    #[test]
+    fn can_parse_selector() {
+        assert_eq!(
+            Selector::new("line_freq").unwrap(),
+            Selector {
+                kind: DataTagKind::LineFreq,
+                index: None
+            }
+        );
+        assert_eq!(Selector::new("bad_chs[2]").unwrap().index, Some(2));
+        assert!(Selector::new("line_frequency").is_err());
+        assert!(Selector::new("bad_chs[x]").is_err());
+    }
+
+    #[test]
+    fn can_patch_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = write_test_file(dir.path(), true);
+        let original = fs::read(&file).unwrap();
+
+        let selector = Selector::new("line_freq").unwrap();
+        let patch = Patch::plan(&file, &selector, "60").unwrap();
+        assert_eq!(patch.method, Method::InPlace);
+        assert_eq!(patch.after, Data::Float(vec![60.0]));
+
+        // planning is a dry run
+        assert_eq!(fs::read(&file).unwrap(), original);
+
+        patch.apply().unwrap();
+        let patched = fs::read(&file).unwrap();
+        assert_eq!(patched.len(), original.len());
+
+        let replanned = Patch::plan(&file, &selector, "60").unwrap();
+        assert_eq!(replanned.before, Data::Float(vec![60.0]));
+    }
+
+    #[test]
+    fn can_patch_by_appending() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = write_test_file(dir.path(), true);
+
+        let selector = Selector::new("experimenter").unwrap();
+        let patch = Patch::plan(&file, &selector, "someone with a longer name").unwrap();
+        assert_eq!(patch.method, Method::Append);
+        patch.apply().unwrap();
+
+        let fh = BufReader::new(File::open(&file).unwrap());
+        let tags = FifParser::read_positioned_tags_from(fh).unwrap();
+
+        // the old tag is gone, the new one is appended along with a new directory
+        let (position, old) = &tags
+            .iter()
+            .find(|(position, _)| *position == patch.position)
+            .unwrap();
+        assert!(matches!(
+            old,
+            Tag::Data {
+                kind: DataTagKind::Nop,
+                ..
+            }
+        ));
+
+        let n = tags.len();
+        assert_eq!(
+            tags[n - 2].1.data(),
+            &Data::String("someone with a longer name".into())
+        );
+
+        let dir = tags[n - 1].clone();
+        assert_eq!(tags[1].1.data(), &Data::Int32(vec![dir.0 as i32]));
+
+        let entries = tag::dir_entries(&encode_data(dir.1.data()).unwrap())
+            .unwrap()
+            .1;
+        let entry = entries
+            .iter()
+            .find(|x| x.code == DataTagKind::Experimenter.to_code())
+            .unwrap();
+        assert_eq!(entry.position as u64, tags[n - 2].0);
+        assert!(entries.iter().all(|x| x.position as u64 != *position));
+    }
+
+    #[test]
+    fn keeps_directory_entries_in_step_with_tags() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = write_test_file(dir.path(), true);
+        list_directory_in_itself(&file);
+
+        let selector = Selector::new("experimenter").unwrap();
+        let patch = Patch::plan(&file, &selector, "someone with a longer name").unwrap();
+        patch.apply().unwrap();
+
+        let mut fh = File::open(&file).unwrap();
+        let tags =
+            FifParser::read_positioned_tags_from(BufReader::new(fh.try_clone().unwrap())).unwrap();
+        let dir = find_directory(&mut fh, &tags).unwrap().unwrap();
+
+        for entry in &dir.entries {
+            let (_, tag) = tags
+                .iter()
+                .find(|(position, _)| *position == entry.position as u64)
+                .unwrap();
+            assert_eq!(tag.code(), entry.code, "entry at {}", entry.position);
+        }
+    }
+
+    #[test]
+    fn can_set_values_by_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = write_test_file(dir.path(), true);
+
+        let selector = Selector::new("mne_coord_frame").unwrap();
+        let patch = Patch::plan(&file, &selector, "head").unwrap();
+        assert_eq!(patch.after, Data::Int32(vec![4]));
+        assert_eq!(patch.method, Method::InPlace);
+
+        assert!(Patch::plan(&file, &selector, "nowhere").is_err());
+    }
+
+    #[test]
+    fn needs_index_for_repeated_tags() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = write_test_file(dir.path(), true);
+
+        let selector = Selector::new("bad_chs").unwrap();
+        assert!(Patch::plan(&file, &selector, "1").is_err());
+
+        let selector = Selector::new("bad_chs[1]").unwrap();
+        let patch = Patch::plan(&file, &selector, "4 5").unwrap();
+        assert_eq!(patch.before, Data::Int32(vec![2, 3]));
+        assert_eq!(patch.method, Method::InPlace);
+    }
+
+    #[test]
+    fn needs_directory_to_append() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = write_test_file(dir.path(), false);
+        let original = fs::read(&file).unwrap();
+
+        // the same size can still be written in place
+        let selector = Selector::new("line_freq").unwrap();
+        assert!(Patch::plan(&file, &selector, "60").is_ok());
+
+        let selector = Selector::new("experimenter").unwrap();
+        let error = Patch::plan(&file, &selector, "someone with a longer name").unwrap_err();
+        assert!(
+            error.to_string().contains("has no tag directory"),
+            "{error}"
+        );
+        assert_eq!(fs::read(&file).unwrap(), original);
+    }
+
+    // rewrite the directory (the last tag) with an entry for itself, as acquisition software does
+    fn list_directory_in_itself(file: &Path) {
+        let mut fh = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(file)
+            .unwrap();
+        let tags =
+            FifParser::read_positioned_tags_from(BufReader::new(fh.try_clone().unwrap())).unwrap();
+        let mut dir = find_directory(&mut fh, &tags).unwrap().unwrap();
+
+        dir.entries.push(tag::DirEntry {
+            code: DataTagKind::Dir.to_code(),
+            dtype: tag::DIR_ENTRY_STRUCT,
+            size: dir.size + 16,
+            position: dir.position as i32,
+        });
+        let bytes = encode_data(&encode_dir(&dir.entries)).unwrap();
+
+        fh.set_len(dir.position).unwrap();
+        fh.seek(SeekFrom::Start(dir.position)).unwrap();
+        fh.write_all(&encode_header(
+            DataTagKind::Dir.to_code(),
+            tag::DIR_ENTRY_STRUCT,
+            bytes.len() as i32,
+            FIFFV_NEXT_SEQ,
+        ))
+        .unwrap();
+        fh.write_all(&bytes).unwrap();
+    }
+
+    fn write_test_file(dir: &Path, directory: bool) -> PathBuf {
+        let file = dir.join("test.fif");
+
+        let mut tree = Tree::new();
+        tree.add_child(FiffNode::Tag {
+            kind: DataTagKind::FileId,
+            data: Data::IdStruct(IdStruct::generate()),
+        });
+        let block = tree.add_child(FiffNode::Block {
+            kind: BlockKind::MeasInfo,
+        });
+        tree.move_to(block);
+
+        for (kind, data) in [
+            (DataTagKind::LineFreq, Data::Float(vec![50.0])),
+            (DataTagKind::Experimenter, Data::String("someone".into())),
+            (DataTagKind::BadChs, Data::Int32(vec![1])),
+            (DataTagKind::BadChs, Data::Int32(vec![2, 3])),
+            (DataTagKind::MneCoordFrame, Data::Int32(vec![1])),
+        ] {
+            tree.add_child(FiffNode::Tag { kind, data });
+        }
+
+        let mut writer = FifWriter::create(&file).unwrap();
+        if directory {
+            writer = writer.with_directory();
+        }
+        writer.write_tree(&tree).unwrap();
+        writer.finish().unwrap();
+
+        file
+    }
+}
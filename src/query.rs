@@ -3,58 +3,210 @@
 use std::{
     collections::{HashMap, HashSet},
     fmt::Display,
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 use crate::FifParser;
 use anyhow::Result;
-use log::info;
+use chrono::FixedOffset;
+use log::{info, warn};
+use serde::Serialize;
 
 use crate::{
+    date::parse_utc_offset,
+    derived::Derived,
+    dictionary,
     enums::DataTagKind,
-    tag::{Data, LabelledData, Tag},
+    expr::Expr,
+    index::{self, Entry, Index},
+    tag::{self, Data, LabelledData, Tag},
 };
 
-type QuerySet = HashSet<DataTagKind>;
-type ResultSet = HashMap<DataTagKind, Vec<Data>>;
+pub(crate) type QuerySet = HashSet<DataTagKind>;
+/// Every occurrence of each tag searched for in a file.
+pub type ResultSet = HashMap<DataTagKind, Vec<Data>>;
+
+/// A column of search output: either a tag read from the file, or a field derived from tags.
+#[derive(Debug, PartialEq, Clone, Eq, Hash, Serialize)]
+#[serde(untagged)]
+pub enum Column {
+    Tag(DataTagKind),
+    Derived(Derived),
+}
+
+impl Column {
+    /// A derived field, or a tag by name or code.
+    pub fn from_name(name: &str) -> Result<Self> {
+        Ok(match Derived::from_name(name) {
+            Some(derived) => Column::Derived(derived),
+            None => Column::Tag(
+                tag::find_tag(name).map_err(|_| dictionary::unrecognized_tag(name, true))?,
+            ),
+        })
+    }
+
+    /// Tags that have to be read to show this column.
+    pub fn requires(&self) -> Vec<DataTagKind> {
+        match self {
+            Column::Tag(kind) => vec![kind.clone()],
+            Column::Derived(derived) => derived.requires(),
+        }
+    }
+
+    /// The tag shown, None for derived fields.
+    pub(crate) fn kind(&self) -> Option<&DataTagKind> {
+        match self {
+            Column::Tag(kind) => Some(kind),
+            Column::Derived(_) => None,
+        }
+    }
+
+    /// Every occurrence of the column in a file's results.
+    pub(crate) fn values(&self, file: &Path, results: &ResultSet) -> Vec<Data> {
+        match self {
+            Column::Tag(kind) => results.get(kind).cloned().unwrap_or_default(),
+            Column::Derived(derived) => derived.compute(file, results).into_iter().collect(),
+        }
+    }
+}
+
+impl Display for Column {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Column::Tag(kind) => write!(f, "{kind}"),
+            Column::Derived(derived) => write!(f, "{}", derived.name()),
+        }
+    }
+}
+
+impl From<DataTagKind> for Column {
+    fn from(kind: DataTagKind) -> Self {
+        Column::Tag(kind)
+    }
+}
+
+impl From<Derived> for Column {
+    fn from(derived: Derived) -> Self {
+        Column::Derived(derived)
+    }
+}
 
 #[derive(Debug)]
 pub struct Search {
-    orders: (Vec<PathBuf>, Vec<DataTagKind>),
+    orders: (Vec<PathBuf>, Vec<Column>),
     query: QuerySet,
     state: HashMap<PathBuf, SearchState>,
+    local_time: bool,
+    condition: Option<Expr>,
 }
 
 impl Search {
-    pub fn new(codes: Vec<DataTagKind>, files: Vec<PathBuf>) -> Self {
+    pub fn new<C: Into<Column>>(columns: Vec<C>, files: Vec<PathBuf>) -> Self {
         let mut state: HashMap<PathBuf, SearchState> = HashMap::new();
 
         for file in files.iter() {
             state.insert(file.clone(), SearchState::Pending);
         }
 
+        let columns: Vec<Column> = columns.into_iter().map(Into::into).collect();
+
+        // derived fields need the tags they're worked out from
+        let query = columns.iter().flat_map(Column::requires).collect();
+
         Search {
-            orders: (files.clone(), codes.clone()),
-            query: HashSet::from_iter(codes.into_iter()),
-            state: state,
+            orders: (files.clone(), columns),
+            query,
+            state,
+            local_time: false,
+            condition: None,
         }
     }
 
+    /// Only show files matching the condition.
+    pub fn with_condition(mut self, condition: Expr) -> Self {
+        self.query
+            .extend(condition.columns().iter().flat_map(Column::requires));
+        self.condition = Some(condition);
+        self
+    }
+
+    /// Show times in the offset from UTC recorded in each file (utc_offset), where there is one.
+    pub fn with_local_time(mut self) -> Self {
+        self.query.insert(DataTagKind::UtcOffset);
+        self.local_time = true;
+        self
+    }
+
+    /// Read every file.  Files which can't be read are reported and left out of the results.
     pub fn execute(&mut self) {
         let query = self.query.clone();
 
         for (file, state) in self.state.iter_mut() {
-            *state = SearchState::Complete(Self::search_tags(file.clone(), query.clone()).unwrap());
+            match Self::search_tags(file.clone(), query.clone()) {
+                Ok(results) => *state = SearchState::Complete(results),
+                Err(e) => warn!("Skipping {}: {e:#}", file.display()),
+            }
         }
     }
 
+    /// Answer from an index where it can, reading (and indexing) only files which are new or
+    /// have changed, or all files if the query needs tags which aren't indexed.  As when updating
+    /// the index, files which can't be read are reported and left out of the results.
+    pub fn execute_with_index(&mut self, index: &mut Index) {
+        let indexed = self.query.iter().all(index::is_indexed);
+
+        for (file, state) in self.state.iter_mut() {
+            let tags = match index.fresh(file) {
+                Some(entry) if indexed => entry.tags(),
+                _ => match Self::read_and_index(file, index) {
+                    Ok(tags) => tags,
+                    Err(e) => {
+                        warn!("Skipping {}: {e:#}", file.display());
+                        continue;
+                    }
+                },
+            };
+
+            *state = SearchState::Complete(Self::collect_tags(tags, &self.query));
+        }
+    }
+
+    fn read_and_index(file: &Path, index: &mut Index) -> Result<Vec<Tag>> {
+        let tags = FifParser::read_tags(file.to_owned())?;
+        index.insert(file.to_owned(), Entry::for_file(file, &tags)?);
+        Ok(tags)
+    }
+
+    /// Results of the files which match the condition, in the order given.
+    pub(crate) fn results(&self) -> Vec<(&PathBuf, &ResultSet)> {
+        self.orders
+            .0
+            .iter()
+            .filter_map(|file| match self.state.get(file) {
+                Some(SearchState::Complete(results))
+                    if self
+                        .condition
+                        .as_ref()
+                        .is_none_or(|x| x.matches(file, results)) =>
+                {
+                    Some((file, results))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
     fn search_tags(file: PathBuf, query: QuerySet) -> Result<ResultSet> {
         let tags = FifParser::read_tags(file)?;
 
+        Ok(Self::collect_tags(tags, &query))
+    }
+
+    pub(crate) fn collect_tags(tags: Vec<Tag>, query: &QuerySet) -> ResultSet {
         let mut results = ResultSet::new();
 
         for tag in tags {
-            if let Tag::Data { kind, data } = tag {
+            if let Tag::Data { kind, data, .. } = tag {
                 if query.contains(&kind) {
                     results
                         .entry(kind)
@@ -70,7 +222,7 @@ impl Search {
             }
         }
 
-        Ok(results)
+        results
     }
 }
 
@@ -84,31 +236,36 @@ impl Display for Search {
         wtr.serialize(("file", &self.orders.1)).unwrap();
 
         // write entries with file and queried tags in original order
-        for file in self.orders.0.iter() {
-            let result = self.state.get(file).expect("File should be in map");
-
-            if let SearchState::Complete(results) = result {
-                let mut output: Vec<String> = self
-                    .orders
-                    .1
-                    .iter()
-                    .map(|x| {
-                        let kind = x.clone();
-                        results
-                            .get(x)
-                            .unwrap_or(&vec![])
-                            .get(0)
-                            .map_or("Not found".to_owned(), |x| {
-                                LabelledData::new(kind, x.clone()).to_string()
-                            })
-                    })
-                    .collect();
-
-                let mut filename = vec![String::from(file.file_name().unwrap().to_str().unwrap())];
-                filename.append(&mut output);
-
-                wtr.serialize(filename).unwrap();
-            }
+        for (file, results) in self.results() {
+            let offset = if self.local_time {
+                utc_offset(file, results)
+            } else {
+                None
+            };
+
+            let mut output: Vec<String> = self
+                .orders
+                .1
+                .iter()
+                .map(|x| match x {
+                    Column::Tag(kind) => results.get(kind).unwrap_or(&vec![]).first().map_or(
+                        "Not found".to_owned(),
+                        |x| {
+                            LabelledData::new(kind.clone(), x.clone())
+                                .with_utc_offset(offset)
+                                .to_string()
+                        },
+                    ),
+                    Column::Derived(derived) => derived
+                        .compute(file, results)
+                        .map_or("Not found".to_owned(), |x| x.to_string()),
+                })
+                .collect();
+
+            let mut filename = vec![String::from(file.file_name().unwrap().to_str().unwrap())];
+            filename.append(&mut output);
+
+            wtr.serialize(filename).unwrap();
         }
 
         let data = String::from_utf8(wtr.into_inner().unwrap()).unwrap();
@@ -116,6 +273,16 @@ impl Display for Search {
     }
 }
 
+// files without a (readable) utc_offset fall back to UTC
+fn utc_offset(file: &Path, results: &ResultSet) -> Option<FixedOffset> {
+    match results.get(&DataTagKind::UtcOffset)?.first()? {
+        Data::String(x) => parse_utc_offset(x)
+            .map_err(|e| warn!("{file:?}: {e}, showing times in UTC"))
+            .ok(),
+        _ => None,
+    }
+}
+
 #[derive(Debug, PartialEq)]
 enum SearchState {
     Pending,
@@ -125,7 +292,7 @@ enum SearchState {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::tag::Data;
+    use crate::tag::{Data, FIFFV_NEXT_SEQ};
 
     #[test]
     fn can_create_search() {
@@ -138,10 +305,7 @@ mod tests {
         }
 
         assert_eq!(search.state, state);
-        assert_eq!(
-            search.query,
-            HashSet::from_iter(default_query().into_iter())
-        );
+        assert_eq!(search.query, HashSet::from_iter(default_query()));
     }
 
     #[test]
@@ -155,8 +319,132 @@ mod tests {
         println!("{search}");
     }
 
+    #[test]
+    fn derived_columns_query_their_tags() {
+        let columns = vec![
+            Column::Tag(DataTagKind::Sfreq),
+            Column::Derived(Derived::from_name("subj_age").unwrap()),
+        ];
+        let search = Search::new(columns, default_files());
+
+        assert_eq!(
+            search.query,
+            HashSet::from([
+                DataTagKind::Sfreq,
+                DataTagKind::MeasDate,
+                DataTagKind::SubjBirthDay
+            ])
+        );
+    }
+
+    #[test]
+    fn can_show_local_time() {
+        let mut results = ResultSet::new();
+        results.insert(
+            DataTagKind::MeasDate,
+            vec![Data::Int32(vec![1646226731, 138511])],
+        );
+        results.insert(DataTagKind::UtcOffset, vec![Data::String("+02:00".into())]);
+
+        let file = PathBuf::from("file_0.fif");
+        let mut search = Search::new(vec![DataTagKind::MeasDate], vec![file.clone()]);
+        search
+            .state
+            .insert(file.clone(), SearchState::Complete(results));
+
+        assert!(search.to_string().contains("2022-03-02T13:12:11.138511Z"));
+
+        search = search.with_local_time();
+        assert!(search.query.contains(&DataTagKind::UtcOffset));
+        assert!(search
+            .to_string()
+            .contains("2022-03-02T15:12:11.138511+02:00"));
+    }
+
+    #[test]
+    fn can_filter_by_symbolic_value() {
+        let files: Vec<PathBuf> = ["female.fif", "male.fif", "unknown.fif"]
+            .iter()
+            .map(|x| x.into())
+            .collect();
+
+        let filter = Expr::filter("subj_sex=female").unwrap();
+        let mut search =
+            Search::new(vec![DataTagKind::SubjSex], files.clone()).with_condition(filter);
+
+        for (file, sex) in files.iter().zip([2, 1, 0]) {
+            let results = ResultSet::from([(DataTagKind::SubjSex, vec![Data::Int32(vec![sex])])]);
+            search
+                .state
+                .insert(file.clone(), SearchState::Complete(results));
+        }
+
+        assert_eq!(search.to_string(), "file,subj_sex\nfemale.fif,female\n");
+
+        search.condition = Some(Expr::filter("subj_sex = 1").unwrap());
+        assert_eq!(search.to_string(), "file,subj_sex\nmale.fif,male\n");
+    }
+
+    #[test]
+    fn can_answer_from_index() {
+        let dir = std::env::temp_dir().join("meginfo-query-index");
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("raw.fif");
+
+        let mut writer = crate::writer::FifWriter::create(&file).unwrap();
+        writer.write_tags(&default_tags()).unwrap();
+        writer.finish().unwrap();
+
+        let mut index = Index::open(&dir.join("meginfo.idx")).unwrap();
+        let mut search = Search::new(vec![DataTagKind::Sfreq], vec![file.clone()]);
+        search.execute_with_index(&mut index);
+        assert_eq!(search.to_string(), "file,sfreq\nraw.fif,200\n");
+
+        // fresh entries are used as they are, without reading the file
+        let sfreq = Tag::Data {
+            kind: DataTagKind::Sfreq,
+            data: Data::Float(vec![600.0]),
+            next: FIFFV_NEXT_SEQ,
+        };
+        let entry = index.fresh(&file).unwrap();
+        let entry = Entry::from_tags(entry.size, entry.mtime, &[sfreq]);
+        index.insert(file.clone(), entry);
+
+        search.execute_with_index(&mut index);
+        assert_eq!(search.to_string(), "file,sfreq\nraw.fif,600\n");
+    }
+
+    #[test]
+    fn can_filter_by_condition() {
+        let files: Vec<PathBuf> = ["a.fif", "b.fif"].iter().map(|x| x.into()).collect();
+        let condition = Expr::parse("sfreq >= 1000 && bad_chs contains 'MEG 2443'").unwrap();
+        let mut search =
+            Search::new(vec![DataTagKind::Sfreq], files.clone()).with_condition(condition);
+        assert!(search.query.contains(&DataTagKind::BadChs));
+
+        for (file, bads) in files.iter().zip(["MEG 2443", "MEG 1113"]) {
+            let results = ResultSet::from([
+                (DataTagKind::Sfreq, vec![Data::Float(vec![1000.0])]),
+                (DataTagKind::BadChs, vec![Data::String(bads.into())]),
+            ]);
+            search
+                .state
+                .insert(file.clone(), SearchState::Complete(results));
+        }
+
+        assert_eq!(search.to_string(), "file,sfreq\na.fif,1000\n");
+    }
+
+    #[test]
+    fn can_collect_tags() {
+        let query = HashSet::from_iter(default_query());
+        let results = Search::collect_tags(default_tags(), &query);
+
+        assert_eq!(results, default_results());
+    }
+
     fn default_files() -> Vec<PathBuf> {
-        vec!["data/file_0.fif", "data/file_1.fif", "data/file_2.fif"]
+        ["data/file_0.fif", "data/file_1.fif", "data/file_2.fif"]
             .iter()
             .map(|x| x.into())
             .collect()
@@ -177,34 +465,45 @@ mod tests {
             Tag::Data {
                 kind: DataTagKind::BadChs,
                 data: Data::String("sensorA".into()),
+                next: FIFFV_NEXT_SEQ,
             },
             Tag::Data {
                 kind: DataTagKind::BadChs,
                 data: Data::String("sensorB".into()),
+                next: FIFFV_NEXT_SEQ,
             },
             Tag::Data {
                 kind: DataTagKind::BadChs,
                 data: Data::String("sensorC".into()),
+                next: FIFFV_NEXT_SEQ,
             },
             Tag::Data {
                 kind: DataTagKind::SphereLayers,
                 data: Data::Int32(vec![3, 4, 5]),
+                next: FIFFV_NEXT_SEQ,
             },
             Tag::Data {
                 kind: DataTagKind::Sfreq,
                 data: Data::Float(vec![200.0]),
+                next: FIFFV_NEXT_SEQ,
             },
             Tag::Data {
                 kind: DataTagKind::FileId,
-                data: Data::Slice("test".into()),
+                data: Data::Slice {
+                    dtype: 31,
+                    bytes: "test".into(),
+                },
+                next: FIFFV_NEXT_SEQ,
             },
             Tag::Data {
                 kind: DataTagKind::DacqPars,
                 data: Data::Float(vec![1.5, 1.2, -0.5]),
+                next: FIFFV_NEXT_SEQ,
             },
             Tag::Data {
                 kind: DataTagKind::FreeBlock,
                 data: Data::String("free blocks".into()),
+                next: FIFFV_NEXT_SEQ,
             },
         ]
     }
@@ -212,7 +511,13 @@ mod tests {
     fn default_results() -> ResultSet {
         let mut map = HashMap::new();
 
-        map.insert(DataTagKind::FileId, vec![Data::Slice("test".into())]);
+        map.insert(
+            DataTagKind::FileId,
+            vec![Data::Slice {
+                dtype: 31,
+                bytes: "test".into(),
+            }],
+        );
 
         map.insert(DataTagKind::SphereLayers, vec![Data::Int32(vec![3, 4, 5])]);
 
@@ -0,0 +1,625 @@
+//! Statistics across many files: counts of files, summaries and histograms of numeric tags, and
+//! how often each item of list-valued tags (e.g. bad_chs) turns up, grouped by the values of tags.
+//!
+//! Each file counts once: summaries and histograms use the first value of a tag, as query shows,
+//! and items are counted once per file however many times they appear in it.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::expr::{is_date, times};
+use crate::query::{Column, ResultSet};
+use crate::tag::{Data, LabelledData};
+
+// a file and the tags found in it
+type Found<'a> = (&'a Path, &'a ResultSet);
+
+// shown for files without the tag, as query does
+const NOT_FOUND: &str = "Not found";
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum StatsFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+impl FromStr for StatsFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "csv" => Ok(StatsFormat::Csv),
+            "json" => Ok(StatsFormat::Json),
+            _ => Err(anyhow!("Unknown stats format {s:?}, expected csv or json")),
+        }
+    }
+}
+
+/// Part of a date to group by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Period {
+    Year,
+    Month,
+    Day,
+}
+
+impl Period {
+    fn format(&self) -> &'static str {
+        match self {
+            Period::Year => "%Y",
+            Period::Month => "%Y-%m",
+            Period::Day => "%Y-%m-%d",
+        }
+    }
+}
+
+/// Tag (or derived field) to group files by, given as name or name:period for dates, e.g.
+/// device_model or meas_date:month.  Dates are grouped in UTC.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupKey {
+    column: Column,
+    period: Option<Period>,
+}
+
+impl GroupKey {
+    pub fn new(key: &str) -> Result<Self> {
+        let (name, period) = match key.split_once(':') {
+            Some((name, period)) => (name, Some(period)),
+            None => (key, None),
+        };
+        let column = Column::from_name(name.trim())?;
+
+        let period = match period.map(str::trim) {
+            None => None,
+            Some(_) if !column.kind().is_some_and(is_date) => {
+                return Err(anyhow!(
+                    "Can only group dates by year, month or day, not {name}"
+                ))
+            }
+            Some("year") => Some(Period::Year),
+            Some("month") => Some(Period::Month),
+            Some("day") => Some(Period::Day),
+            Some(period) => {
+                return Err(anyhow!(
+                    "Unknown period {period:?} in {key:?}, expected year, month or day"
+                ))
+            }
+        };
+
+        Ok(GroupKey { column, period })
+    }
+
+    pub fn column(&self) -> &Column {
+        &self.column
+    }
+
+    fn name(&self) -> String {
+        match self.period {
+            Some(Period::Year) => format!("{}:year", self.column),
+            Some(Period::Month) => format!("{}:month", self.column),
+            Some(Period::Day) => format!("{}:day", self.column),
+            None => self.column.to_string(),
+        }
+    }
+
+    // value of the key for a file, from the first occurrence of the tag
+    fn value(&self, (file, results): Found) -> String {
+        let Some(data) = self.column.values(file, results).into_iter().next() else {
+            return NOT_FOUND.to_owned();
+        };
+
+        match (self.period, self.column.kind()) {
+            (Some(period), kind) => times(kind, &data)
+                .and_then(|x| x.first().map(|x| x.format(period.format()).to_string()))
+                .unwrap_or(NOT_FOUND.to_owned()),
+            (None, Some(kind)) => LabelledData::new(kind.clone(), data).to_string(),
+            (None, None) => data.to_string(),
+        }
+    }
+}
+
+/// What to work out for each group of files.
+#[derive(Debug, Clone, Default)]
+pub struct StatsOptions {
+    pub group_by: Vec<GroupKey>,
+    /// Count, minimum, maximum and mean of these
+    pub summary: Vec<Column>,
+    /// Number of files with each value of these
+    pub histogram: Vec<Column>,
+    /// Split histograms into this many bins of equal width, rather than counting each value
+    pub bins: Option<usize>,
+    /// Number of files each item of these turns up in
+    pub frequency: Vec<Column>,
+    /// Only keep this many of the most frequent items
+    pub top: Option<usize>,
+}
+
+impl StatsOptions {
+    /// Every column that has to be searched for.
+    pub fn columns(&self) -> Vec<Column> {
+        let keys = self.group_by.iter().map(|x| x.column.clone());
+        let columns = [&self.summary, &self.histogram, &self.frequency];
+
+        keys.chain(columns.into_iter().flatten().cloned()).collect()
+    }
+}
+
+/// Count, minimum, maximum and mean of the values of a tag.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Summary {
+    pub n: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+impl Summary {
+    fn new(values: &[f64]) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+
+        Some(Summary {
+            n: values.len(),
+            min: values.iter().copied().fold(f64::INFINITY, f64::min),
+            max: values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            mean: values.iter().sum::<f64>() / values.len() as f64,
+        })
+    }
+}
+
+/// Number of files with a value, bin or item.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Count {
+    pub value: String,
+    pub files: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Group {
+    pub key: Vec<String>,
+    pub files: usize,
+    pub summaries: Vec<Option<Summary>>,
+    pub histograms: Vec<Vec<Count>>,
+    pub frequencies: Vec<Vec<Count>>,
+}
+
+// first value of a column in a file, as a number
+fn number(column: &Column, (file, results): Found) -> Option<f64> {
+    match column.values(file, results).first()? {
+        Data::Int32(x) => x.first().map(|x| f64::from(*x)),
+        Data::Float(x) => x.first().map(|x| f64::from(*x)),
+        _ => None,
+    }
+}
+
+// items of each occurrence of a column in a file, with colon-separated lists (e.g. bad_chs) split
+fn items(column: &Column, (file, results): Found) -> Vec<String> {
+    let mut items: Vec<String> = column
+        .values(file, results)
+        .into_iter()
+        .flat_map(|data| match (data, column.kind()) {
+            (Data::String(x), _) => x
+                .trim_end_matches('\0')
+                .split(':')
+                .map(|x| x.trim().to_owned())
+                .filter(|x| !x.is_empty())
+                .collect(),
+            (data, Some(kind)) => vec![LabelledData::new(kind.clone(), data).to_string()],
+            (data, None) => vec![data.to_string()],
+        })
+        .collect();
+
+    items.sort();
+    items.dedup();
+    items
+}
+
+// the most frequent first, then by name
+fn sorted(counts: HashMap<String, usize>, top: Option<usize>) -> Vec<Count> {
+    let mut counts: Vec<Count> = counts
+        .into_iter()
+        .map(|(value, files)| Count { value, files })
+        .collect();
+    counts.sort_by(|a, b| b.files.cmp(&a.files).then_with(|| a.value.cmp(&b.value)));
+    counts.truncate(top.unwrap_or(counts.len()));
+    counts
+}
+
+// bins of equal width covering values, shared by all groups so that they can be compared
+#[derive(Debug, Clone, Copy)]
+struct Bins {
+    min: f64,
+    width: f64,
+    n: usize,
+}
+
+impl Bins {
+    fn new(values: &[f64], n: usize) -> Option<Self> {
+        let summary = Summary::new(values)?;
+        let n = n.max(1);
+        let width = match summary.max - summary.min {
+            0.0 => 1.0,
+            range => range / n as f64,
+        };
+
+        Some(Bins {
+            min: summary.min,
+            width,
+            n,
+        })
+    }
+
+    fn bin(&self, value: f64) -> usize {
+        (((value - self.min) / self.width) as usize).min(self.n - 1)
+    }
+
+    fn label(&self, i: usize) -> String {
+        let start = self.min + self.width * i as f64;
+        format!("{start}..{}", start + self.width)
+    }
+}
+
+fn histogram(values: &[f64], bins: Option<Bins>) -> Vec<Count> {
+    match bins {
+        Some(bins) => {
+            let mut counts = vec![0; bins.n];
+            for value in values {
+                counts[bins.bin(*value)] += 1;
+            }
+
+            (0..bins.n)
+                .map(|i| Count {
+                    value: bins.label(i),
+                    files: counts[i],
+                })
+                .collect()
+        }
+        None => {
+            let mut values = values.to_vec();
+            values.sort_by(f64::total_cmp);
+
+            let mut counts: Vec<Count> = vec![];
+            for value in values {
+                match counts.last_mut() {
+                    Some(last) if last.value == value.to_string() => last.files += 1,
+                    _ => counts.push(Count {
+                        value: value.to_string(),
+                        files: 1,
+                    }),
+                }
+            }
+            counts
+        }
+    }
+}
+
+/// Statistics for groups of files, sorted by key.
+#[derive(Debug)]
+pub struct Stats {
+    options: StatsOptions,
+    pub groups: Vec<Group>,
+}
+
+impl Stats {
+    pub fn new<'a>(options: StatsOptions, results: impl IntoIterator<Item = Found<'a>>) -> Self {
+        let results: Vec<Found> = results.into_iter().collect();
+
+        let bins: Vec<Option<Bins>> = options
+            .histogram
+            .iter()
+            .map(|column| {
+                let values: Vec<f64> = results.iter().filter_map(|x| number(column, *x)).collect();
+                Bins::new(&values, options.bins?)
+            })
+            .collect();
+
+        let mut grouped: BTreeMap<Vec<String>, Vec<Found>> = BTreeMap::new();
+        for result in results {
+            let key = options.group_by.iter().map(|x| x.value(result)).collect();
+            grouped.entry(key).or_default().push(result);
+        }
+
+        let groups = grouped
+            .into_iter()
+            .map(|(key, results)| {
+                let numbers = |column: &Column| -> Vec<f64> {
+                    results.iter().filter_map(|x| number(column, *x)).collect()
+                };
+
+                let frequency = |column: &Column| {
+                    let mut counts = HashMap::new();
+                    for item in results.iter().flat_map(|x| items(column, *x)) {
+                        *counts.entry(item).or_default() += 1;
+                    }
+                    sorted(counts, options.top)
+                };
+
+                Group {
+                    key,
+                    files: results.len(),
+                    summaries: options
+                        .summary
+                        .iter()
+                        .map(|x| Summary::new(&numbers(x)))
+                        .collect(),
+                    histograms: options
+                        .histogram
+                        .iter()
+                        .zip(&bins)
+                        .map(|(x, bins)| histogram(&numbers(x), *bins))
+                        .collect(),
+                    frequencies: options.frequency.iter().map(frequency).collect(),
+                }
+            })
+            .collect();
+
+        Stats { options, groups }
+    }
+
+    fn key_names(&self) -> Vec<String> {
+        self.options.group_by.iter().map(GroupKey::name).collect()
+    }
+
+    /// A table of counts and summaries with a row per group, followed by a table for each
+    /// histogram and frequency with a row per value, separated by blank lines.
+    pub fn to_csv(&self) -> Result<String> {
+        let keys = self.key_names();
+        let mut tables = vec![];
+
+        let mut wtr = csv::Writer::from_writer(vec![]);
+        let mut header = keys.clone();
+        header.push(String::from("files"));
+        for column in &self.options.summary {
+            header.extend(["n", "min", "max", "mean"].map(|x| format!("{column}_{x}")));
+        }
+        wtr.write_record(header)?;
+
+        for group in &self.groups {
+            let mut row = group.key.clone();
+            row.push(group.files.to_string());
+            for summary in &group.summaries {
+                match summary {
+                    Some(x) => {
+                        row.push(x.n.to_string());
+                        row.extend([x.min, x.max, x.mean].map(|x| x.to_string()));
+                    }
+                    None => row.extend([
+                        String::from("0"),
+                        String::new(),
+                        String::new(),
+                        String::new(),
+                    ]),
+                }
+            }
+            wtr.write_record(row)?;
+        }
+        tables.push(String::from_utf8(wtr.into_inner()?)?);
+
+        for (i, column) in self.options.histogram.iter().enumerate() {
+            tables.push(self.count_table(&keys, column, |x| &x.histograms[i])?);
+        }
+        for (i, column) in self.options.frequency.iter().enumerate() {
+            tables.push(self.count_table(&keys, column, |x| &x.frequencies[i])?);
+        }
+
+        Ok(tables.join("\n"))
+    }
+
+    // a row for each value counted in each group
+    fn count_table(
+        &self,
+        keys: &[String],
+        column: &Column,
+        counts: impl Fn(&Group) -> &Vec<Count>,
+    ) -> Result<String> {
+        let mut wtr = csv::Writer::from_writer(vec![]);
+        wtr.write_record(
+            keys.iter()
+                .chain([&column.to_string(), &String::from("files")]),
+        )?;
+
+        for group in &self.groups {
+            for count in counts(group) {
+                let mut row = group.key.clone();
+                row.extend([count.value.clone(), count.files.to_string()]);
+                wtr.write_record(row)?;
+            }
+        }
+
+        Ok(String::from_utf8(wtr.into_inner()?)?)
+    }
+
+    /// An object per group, with only the statistics that were asked for.
+    pub fn to_json(&self) -> Value {
+        let keys = self.key_names();
+
+        let by_column = |columns: &[Column], values: Vec<Value>| -> Value {
+            let map: Map<String, Value> =
+                columns.iter().map(|x| x.to_string()).zip(values).collect();
+            Value::Object(map)
+        };
+
+        let groups = self
+            .groups
+            .iter()
+            .map(|group| {
+                let mut object = Map::new();
+                if !keys.is_empty() {
+                    let key: Map<String, Value> = keys
+                        .iter()
+                        .cloned()
+                        .zip(group.key.iter().map(|x| json!(x)))
+                        .collect();
+                    object.insert(String::from("group"), Value::Object(key));
+                }
+                object.insert(String::from("files"), json!(group.files));
+
+                let sections = [
+                    ("summary", &self.options.summary, json!(group.summaries)),
+                    (
+                        "histogram",
+                        &self.options.histogram,
+                        json!(group.histograms),
+                    ),
+                    (
+                        "frequency",
+                        &self.options.frequency,
+                        json!(group.frequencies),
+                    ),
+                ];
+                for (name, columns, values) in sections {
+                    if let (false, Value::Array(values)) = (columns.is_empty(), values) {
+                        object.insert(name.to_owned(), by_column(columns, values));
+                    }
+                }
+
+                Value::Object(object)
+            })
+            .collect();
+
+        Value::Array(groups)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::DataTagKind;
+
+    fn results() -> Vec<ResultSet> {
+        let file = |model: &str, sfreq: f32, meas_date: i32, bad_chs: &str| {
+            ResultSet::from([
+                (DataTagKind::DeviceModel, vec![Data::String(model.into())]),
+                (DataTagKind::Sfreq, vec![Data::Float(vec![sfreq])]),
+                (DataTagKind::MeasDate, vec![Data::Int32(vec![meas_date, 0])]),
+                (DataTagKind::BadChs, vec![Data::String(bad_chs.into())]),
+            ])
+        };
+
+        // 2022-03-02, 2022-03-20 and 2022-04-01
+        vec![
+            file("TRIUX", 1000.0, 1646226731, "MEG 2443:MEG 0113"),
+            file("TRIUX", 2000.0, 1647734400, "MEG 2443"),
+            file("VectorView", 1000.0, 1648771200, ""),
+        ]
+    }
+
+    fn found(results: &[ResultSet]) -> Vec<Found<'_>> {
+        results.iter().map(|x| (Path::new("test.fif"), x)).collect()
+    }
+
+    fn options(group_by: &[&str]) -> StatsOptions {
+        StatsOptions {
+            group_by: group_by.iter().map(|x| GroupKey::new(x).unwrap()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn can_group_files() {
+        let results = results();
+        let stats = Stats::new(options(&["device_model"]), found(&results));
+
+        let groups: Vec<(Vec<String>, usize)> = stats
+            .groups
+            .iter()
+            .map(|x| (x.key.clone(), x.files))
+            .collect();
+        assert_eq!(
+            groups,
+            vec![
+                (vec![String::from("TRIUX")], 2),
+                (vec![String::from("VectorView")], 1)
+            ]
+        );
+
+        let stats = Stats::new(options(&["meas_date:month", "subj_sex"]), found(&results));
+        assert_eq!(
+            stats.to_csv().unwrap(),
+            "meas_date:month,subj_sex,files\n2022-03,Not found,2\n2022-04,Not found,1\n"
+        );
+
+        assert!(GroupKey::new("sfreq:month").is_err());
+        assert!(GroupKey::new("meas_date:week").is_err());
+    }
+
+    #[test]
+    fn can_summarize_values() {
+        let results = results();
+        let options = StatsOptions {
+            summary: vec![
+                Column::Tag(DataTagKind::Sfreq),
+                Column::Tag(DataTagKind::HeliumLevel),
+            ],
+            histogram: vec![Column::Tag(DataTagKind::Sfreq)],
+            ..options(&["device_model"])
+        };
+        let stats = Stats::new(options, found(&results));
+
+        assert_eq!(
+            stats.groups[0].summaries,
+            vec![
+                Some(Summary {
+                    n: 2,
+                    min: 1000.0,
+                    max: 2000.0,
+                    mean: 1500.0
+                }),
+                None
+            ]
+        );
+        assert_eq!(
+            stats.to_csv().unwrap(),
+            "device_model,files,sfreq_n,sfreq_min,sfreq_max,sfreq_mean,helium_level_n,helium_level_min,helium_level_max,helium_level_mean\n\
+             TRIUX,2,2,1000,2000,1500,0,,,\n\
+             VectorView,1,1,1000,1000,1000,0,,,\n\
+             \n\
+             device_model,sfreq,files\n\
+             TRIUX,1000,1\n\
+             TRIUX,2000,1\n\
+             VectorView,1000,1\n"
+        );
+    }
+
+    #[test]
+    fn can_bin_histograms() {
+        let bins = Bins::new(&[0.0, 10.0], 4).unwrap();
+        let counts = histogram(&[0.0, 2.0, 3.0, 9.9, 10.0], Some(bins));
+
+        let files: Vec<usize> = counts.iter().map(|x| x.files).collect();
+        assert_eq!(files, vec![2, 1, 0, 2]);
+        assert_eq!(counts[1].value, "2.5..5");
+
+        // a single value still has somewhere to go
+        let bins = Bins::new(&[5.0], 3).unwrap();
+        assert_eq!(histogram(&[5.0], Some(bins))[0].files, 1);
+    }
+
+    #[test]
+    fn can_count_list_items() {
+        let results = results();
+        let options = StatsOptions {
+            frequency: vec![Column::Tag(DataTagKind::BadChs)],
+            ..Default::default()
+        };
+        let stats = Stats::new(options, found(&results));
+
+        assert_eq!(
+            stats.to_json(),
+            json!([{
+                "files": 3,
+                "frequency": {"bad_chs": [
+                    {"value": "MEG 2443", "files": 2},
+                    {"value": "MEG 0113", "files": 1},
+                ]},
+            }])
+        );
+    }
+}
@@ -11,22 +11,34 @@
 //! size: the size in bytes of the ensuing data block
 //! next: supposedly a file pointer to the next block, but typically set to 0 and ignored
 //!
-//! Contains code to parse these from u8 slices using nomparser.
+//! Contains code to parse these from u8 slices using nomparser.  The inverse (encoding back to
+//! bytes) lives in the writer module.
 //!
 
-use csv::ReaderBuilder;
+use nom::bytes::complete::take;
+use nom::combinator::{all_consuming, map};
+use nom::multi;
 use nom::number::complete::{be_f32, be_i32};
-use nom::{multi, AsBytes};
 use nom::{sequence, IResult};
-use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
 use std::fmt::Display;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use chrono::FixedOffset;
 
-use crate::enums::{BlockKind, BlockTagKind, DataTagKind};
-use serde::Deserialize;
+use crate::date::{decode_julian_date, display_julian_dates, parse_julian_dates, MeasDate};
+use crate::dictionary;
+use crate::enums::{BlockKind, BlockTagKind, DType, DataTagKind};
+use crate::graph::Tree;
+use crate::values::{Cardinal, ChType, Coil, Coord, Point, Unit};
+use crate::writer::encode_data;
+use petgraph::stable_graph::NodeIndex;
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum FiffNode {
     Tag { kind: DataTagKind, data: Data },
     Block { kind: BlockKind },
@@ -35,22 +47,17 @@ pub enum FiffNode {
 impl FiffNode {
     pub fn from_tag(tag: Tag) -> Self {
         match tag {
-            Tag::Block { kind, data } => match kind {
-                BlockTagKind::BlockStart => {
-                    if let Data::Int32(data) = data {
-                        let kind = BlockKind::from_code(data[0]);
-                        FiffNode::Block { kind }
-                    } else {
-                        panic!("block start has non-int32 dtype")
-                    }
-                }
+            Tag::Block { kind, data, .. } => match kind {
+                BlockTagKind::BlockStart => match data {
+                    Data::Int32(data) if !data.is_empty() => FiffNode::Block {
+                        kind: BlockKind::from_code(data[0]),
+                    },
+                    _ => panic!("block start has non-int32 dtype"),
+                },
                 _ => panic!("tried to created block using non-start code"),
             },
 
-            Tag::Data { kind, data } => FiffNode::Tag {
-                kind: kind,
-                data: data,
-            },
+            Tag::Data { kind, data, .. } => FiffNode::Tag { kind, data },
         }
     }
 }
@@ -63,9 +70,85 @@ impl Default for FiffNode {
     }
 }
 
+// longest value shown in a node's label before it's cut short
+const LABEL_VALUE_LEN: usize = 60;
+
+// a compact label: the block kind, or the tag with its value (or size, if it's left in the file)
 impl Display for FiffNode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            FiffNode::Block { kind } => write!(f, "{kind}"),
+            FiffNode::Tag {
+                kind,
+                data: Data::InFile { size, .. },
+            } => write!(f, "{kind} ({})", human_size(*size)),
+            FiffNode::Tag { kind, data } => {
+                let value = LabelledData::new(kind.clone(), data.clone()).to_string();
+                let value = value.replace('\n', " ");
+
+                if value.chars().count() > LABEL_VALUE_LEN {
+                    let cut: String = value.chars().take(LABEL_VALUE_LEN - 1).collect();
+                    write!(f, "{kind} = {cut}…")
+                } else {
+                    write!(f, "{kind} = {value}")
+                }
+            }
+        }
+    }
+}
+
+/// A number of bytes in decimal units, e.g. 1.4 GB.
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["kB", "MB", "GB", "TB", "PB"];
+
+    if bytes < 1000 {
+        return format!("{bytes} B");
+    }
+
+    let mut size = bytes as f64 / 1000.0;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+// lookups for reading a parsed file
+impl Tree<FiffNode> {
+    /// Blocks of a kind anywhere in the tree, in file order.
+    pub fn blocks(&self, kind: &BlockKind) -> Vec<NodeIndex> {
+        self.depth_first(self.root)
+            .into_iter()
+            .filter(|x| matches!(&self[*x], FiffNode::Block { kind: k } if k == kind))
+            .collect()
+    }
+
+    /// Data of the tags of a kind directly inside a block, in file order.
+    pub fn tags_in(&self, block: NodeIndex, kind: &DataTagKind) -> Vec<&Data> {
+        self.children(block)
+            .into_iter()
+            .filter_map(|x| match &self[x] {
+                FiffNode::Tag { kind: k, data } if k == kind => Some(data),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Data of the first tag of a kind directly inside a block.
+    pub fn tag_in(&self, block: NodeIndex, kind: &DataTagKind) -> Option<&Data> {
+        self.tags_in(block, kind).into_iter().next()
+    }
+
+    /// Data of the first tag of a kind anywhere below a block.
+    pub fn find_tag(&self, block: NodeIndex, kind: &DataTagKind) -> Option<&Data> {
+        self.depth_first(block)
+            .into_iter()
+            .find_map(|x| match &self[x] {
+                FiffNode::Tag { kind: k, data } if k == kind => Some(data),
+                _ => None,
+            })
     }
 }
 
@@ -74,10 +157,19 @@ pub struct Block {
     pub kind: BlockKind,
 }
 
+// next is kept as read so that tags can be written back unchanged, see FIFFV_NEXT_SEQ
 #[derive(Debug, PartialEq, Clone)]
 pub enum Tag {
-    Data { kind: DataTagKind, data: Data },
-    Block { kind: BlockTagKind, data: Data },
+    Data {
+        kind: DataTagKind,
+        data: Data,
+        next: i32,
+    },
+    Block {
+        kind: BlockTagKind,
+        data: Data,
+        next: i32,
+    },
 }
 
 impl Default for Tag {
@@ -85,6 +177,7 @@ impl Default for Tag {
         Tag::Data {
             kind: DataTagKind::Nop,
             data: Data::Void,
+            next: FIFFV_NEXT_SEQ,
         }
     }
 }
@@ -93,28 +186,51 @@ impl Tag {
     pub fn from_header_slice(header: Header, slice: Vec<u8>) -> Result<Self> {
         // see if it's a block code first
         if let Ok(kind) = BlockTagKind::from_code(header.code) {
-            return Ok(Tag::Block {
+            Ok(Tag::Block {
                 kind,
-                data: Data::from_slice(slice, header.dtype), // TODO: implement this properly
-            });
+                data: Data::from_slice(slice, header.dtype),
+                next: header.next,
+            })
         // otherwise we assume it's a normal tag
         } else {
-            return Ok(Tag::Data {
-                kind: DataTagKind::from_code(header.code)?,
+            Ok(Tag::Data {
+                kind: DataTagKind::from_code(header.code),
                 data: Data::from_slice(slice, header.dtype),
-            });
+                next: header.next,
+            })
         }
     }
 
     pub fn from_header_file_position(header: Header, start: u64, size: u64) -> Result<Self> {
         Ok(Tag::Data {
-            kind: DataTagKind::from_code(header.code)?,
+            kind: DataTagKind::from_code(header.code),
             data: Data::InFile {
-                start: start,
-                size: size,
+                start,
+                size,
+                dtype: header.dtype,
             },
+            next: header.next,
         })
     }
+
+    pub fn data(&self) -> &Data {
+        match self {
+            Tag::Data { data, .. } | Tag::Block { data, .. } => data,
+        }
+    }
+
+    pub fn code(&self) -> i32 {
+        match self {
+            Tag::Data { kind, .. } => kind.to_code(),
+            Tag::Block { kind, .. } => kind.to_code(),
+        }
+    }
+
+    pub fn next(&self) -> i32 {
+        match self {
+            Tag::Data { next, .. } | Tag::Block { next, .. } => *next,
+        }
+    }
 }
 
 impl Display for Tag {
@@ -122,8 +238,25 @@ impl Display for Tag {
         write!(f, "{:?}", self)
     }
 }
+/// Value of `next` for a tag followed directly by the next tag in the file.
+pub const FIFFV_NEXT_SEQ: i32 = 0;
+/// Value of `next` for the last tag in a file.
+pub const FIFFV_NEXT_NONE: i32 = -1;
+
+/// FIFF dtype of a tag directory entry, see fiff/primitives.tsv
+pub const DIR_ENTRY_STRUCT: i32 = 32;
+
+// one entry of the tag directory, mirrors the tag header but with a file position as last field
+#[derive(Debug, PartialEq, Clone)]
+pub struct DirEntry {
+    pub code: i32,
+    pub dtype: i32,
+    pub size: i32,
+    pub position: i32,
+}
+
 // the tag header struct, corresponds exactly to the 16 byte headers in the file
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Header {
     pub code: i32,
     pub dtype: i32,
@@ -132,39 +265,147 @@ pub struct Header {
 }
 
 // data for a tag, either owns the actual data (for small data) or data position in the file
-// (for large data that requires deferred reading)
+// (for large data that requires deferred reading).  Payloads we don't decode keep their dtype,
+// so every variant knows how to describe itself in a tag header.
 #[derive(Debug, PartialEq, Clone, Default)]
 pub enum Data {
     #[default]
     Void,
-    Slice(Vec<u8>),
+    Slice {
+        dtype: i32,
+        bytes: Vec<u8>,
+    },
     InFile {
         start: u64,
         size: u64,
+        dtype: i32,
     },
     Int32(Vec<i32>),
     Float(Vec<f32>),
     JulianDate(Vec<i32>),
     String(String),
-    ChInfoStruct(Vec<u8>),
+    ChInfoStruct(Vec<ChInfo>),
     IdStruct(IdStruct),
-    DigPointStruct(Vec<u8>),
-    CoordTransStruct(Vec<u8>),
+    DigPointStruct(Vec<DigPoint>),
+    CoordTransStruct(CoordTrans),
 }
 
 impl Data {
+    // payloads which don't decode cleanly are kept as raw bytes rather than partially read
     pub fn from_slice(slice: Vec<u8>, dtype: i32) -> Self {
-        match dtype {
-            0 => Data::Void,
-            3 => Data::Int32(i32_many(&slice).unwrap().1),
-            4 => Data::Float(f32_many(&slice).unwrap().1),
-            6 => Data::JulianDate(i32_many(&slice).unwrap().1),
-            10 => Data::String(string(slice)),
-            30 => Data::ChInfoStruct(slice),
-            31 => Data::IdStruct(idstruct(&slice).unwrap().1),
-            33 => Data::DigPointStruct(slice),
-            35 => Data::CoordTransStruct(slice),
-            _ => Data::Slice(slice),
+        let decoded = match dtype {
+            0 if slice.is_empty() => Some(Data::Void),
+            3 => all_consuming(i32_many)(&slice)
+                .ok()
+                .map(|x| Data::Int32(x.1)),
+            4 => all_consuming(f32_many)(&slice)
+                .ok()
+                .map(|x| Data::Float(x.1)),
+            6 => all_consuming(i32_many)(&slice)
+                .ok()
+                .map(|x| Data::JulianDate(x.1)),
+            10 => Some(Data::String(latin1(&slice))),
+            30 => all_consuming(multi::many0(ch_info))(&slice)
+                .ok()
+                .map(|x| Data::ChInfoStruct(x.1)),
+            31 => all_consuming(idstruct)(&slice)
+                .ok()
+                .map(|x| Data::IdStruct(x.1)),
+            33 => all_consuming(multi::many0(dig_point))(&slice)
+                .ok()
+                .map(|x| Data::DigPointStruct(x.1)),
+            35 => all_consuming(coord_trans)(&slice)
+                .ok()
+                .map(|x| Data::CoordTransStruct(x.1)),
+            _ => None,
+        };
+
+        decoded.unwrap_or(Data::Slice {
+            dtype,
+            bytes: slice,
+        })
+    }
+
+    /// Parse a value given on the command line into data of the same type as this.
+    ///
+    /// Lists of numbers are separated by spaces or commas.
+    pub fn parse_as(&self, value: &str) -> Result<Data> {
+        fn many<T: std::str::FromStr>(value: &str) -> Result<Vec<T>>
+        where
+            T::Err: Display,
+        {
+            value
+                .split(|x: char| x == ',' || x.is_whitespace())
+                .filter(|x| !x.is_empty())
+                .map(|x| x.parse::<T>().map_err(|e| anyhow!("{x:?}: {e}")))
+                .collect()
+        }
+
+        let data = match self {
+            Data::Int32(_) => Data::Int32(many(value)?),
+            Data::Float(_) => Data::Float(many(value)?),
+            Data::JulianDate(_) => Data::JulianDate(parse_julian_dates(value)?),
+            Data::String(_) => {
+                if let Some(x) = value.chars().find(|x| *x as u32 > 0xff) {
+                    return Err(anyhow!("{x:?} can't be stored in a .fif file (ISO 8859-1)"));
+                }
+                Data::String(value.to_owned())
+            }
+            x => {
+                return Err(anyhow!(
+                    "Setting values of type {} is not supported",
+                    x.dtype()
+                ))
+            }
+        };
+
+        Ok(data)
+    }
+
+    /// Parse a value given on the command line into data of the type the dictionary gives for a
+    /// tag.  Enumerated values can be given by name.
+    pub fn parse_for(kind: &DataTagKind, value: &str) -> Result<Data> {
+        if let Some(code) = kind.parse_value(value) {
+            return Ok(Data::Int32(vec![code?]));
+        }
+
+        let template = match dictionary::find_dtype(kind.dtype()) {
+            Ok(DType::Int32) => Data::Int32(vec![]),
+            Ok(DType::Float) => Data::Float(vec![]),
+            Ok(DType::Julian) => Data::JulianDate(vec![]),
+            Ok(DType::String) => Data::String(String::new()),
+            _ => {
+                return Err(anyhow!(
+                    "Values of {kind} (type {:?}) can't be given on the command line",
+                    kind.dtype()
+                ))
+            }
+        };
+
+        template.parse_as(value)
+    }
+
+    /// Size of the payload in bytes, as stored in the file.
+    pub fn size(&self) -> u64 {
+        match self {
+            Data::InFile { size, .. } => *size,
+            data => encode_data(data).map_or(0, |x| x.len() as u64),
+        }
+    }
+
+    /// The FIFF dtype code (see fiff/primitives.tsv) used when writing this data.
+    pub fn dtype(&self) -> i32 {
+        match self {
+            Data::Void => 0,
+            Data::Slice { dtype, .. } | Data::InFile { dtype, .. } => *dtype,
+            Data::Int32(_) => 3,
+            Data::Float(_) => 4,
+            Data::JulianDate(_) => 6,
+            Data::String(_) => 10,
+            Data::ChInfoStruct(_) => 30,
+            Data::IdStruct(_) => 31,
+            Data::DigPointStruct(_) => 33,
+            Data::CoordTransStruct(_) => 35,
         }
     }
 }
@@ -174,7 +415,15 @@ impl Display for Data {
         let disp = match self {
             Data::Float(x) => display_vec(x),
             Data::Int32(x) => display_vec(x),
-            Data::String(x) => format!("{x}"),
+            Data::JulianDate(x) => display_julian_dates(x),
+            Data::String(x) => trim_string(x).to_owned(),
+            Data::ChInfoStruct(x) => {
+                let names: Vec<&str> = x.iter().map(|x| x.ch_name()).collect();
+                display_vec(&names)
+            }
+            Data::IdStruct(x) => x.to_string(),
+            Data::DigPointStruct(x) => display_vec(x),
+            Data::CoordTransStruct(x) => x.to_string(),
             x => {
                 format!("{x:?}")
             }
@@ -184,7 +433,7 @@ impl Display for Data {
     }
 }
 
-fn display_vec<T: Display>(input: &[T]) -> String {
+pub(crate) fn display_vec<T: Display>(input: &[T]) -> String {
     match input.len() {
         0 => String::from("None"),
         1 => format!("{}", input[0]),
@@ -195,36 +444,394 @@ fn display_vec<T: Display>(input: &[T]) -> String {
     }
 }
 
-pub struct LabelledData(DataTagKind, Data);
+// data along with its kind, so that it can be displayed in a way that fits the kind.  Times are
+// shown in UTC, unless the file's utc_offset is supplied.
+pub struct LabelledData(DataTagKind, Data, Option<FixedOffset>);
 
 impl LabelledData {
     pub fn new(kind: DataTagKind, data: Data) -> Self {
-        LabelledData(kind, data)
+        LabelledData(kind, data, None)
+    }
+
+    pub fn with_utc_offset(mut self, offset: Option<FixedOffset>) -> Self {
+        self.2 = offset;
+        self
     }
 }
 
 impl Display for LabelledData {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let out = match self {
-            LabelledData(DataTagKind::MeasDate, Data::Int32(data)) => {
-                format!("{}", decode_unix_date(data))
+            LabelledData(DataTagKind::MeasDate, Data::Int32(data), offset) => {
+                match MeasDate::from_ints(data) {
+                    Ok(date) => date.display(*offset),
+                    Err(_) => display_vec(data),
+                }
+            }
+            // some writers store the birthday as a plain int32 rather than a julian date
+            LabelledData(DataTagKind::SubjBirthDay, Data::Int32(data), _) => {
+                display_julian_dates(data)
             }
-            LabelledData(_, data) => format!("{data}"),
+            LabelledData(_, Data::IdStruct(id), offset) => id.display(*offset),
+            LabelledData(kind, Data::Int32(data), _) if Bitmask::for_tag(kind, data).is_some() => {
+                let masks: Vec<String> = Bitmask::for_tag(kind, data)
+                    .unwrap()
+                    .iter()
+                    .map(|(label, mask)| match label {
+                        Some(label) => format!("{label}={mask}"),
+                        None => mask.to_string(),
+                    })
+                    .collect();
+                display_vec(&masks)
+            }
+            LabelledData(kind, Data::Int32(data), _) if kind.value_name(0).is_some() => {
+                let names: Vec<String> = data.iter().filter_map(|&x| kind.value_name(x)).collect();
+                display_vec(&names)
+            }
+            LabelledData(_, data, _) => format!("{data}"),
         };
 
         write!(f, "{}", out)
     }
 }
 
+// single values become scalars (dates as ISO 8601 strings, or null when unset, and enumerated
+// values by name), bitmasks lists of their flags, and anything else is serialized as it's
+// displayed
+impl Serialize for LabelledData {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            LabelledData(_, Data::JulianDate(x), _)
+            | LabelledData(DataTagKind::SubjBirthDay, Data::Int32(x), _)
+                if x.len() == 1 =>
+            {
+                match decode_julian_date(x[0]) {
+                    Some(date) => serializer.serialize_str(&date.to_string()),
+                    None if x[0] == 0 => serializer.serialize_none(),
+                    None => serializer.serialize_i32(x[0]),
+                }
+            }
+            LabelledData(DataTagKind::MeasDate, Data::Int32(x), _)
+                if MeasDate::from_ints(x).is_ok_and(|x| x.is_unset()) =>
+            {
+                serializer.serialize_none()
+            }
+            LabelledData(DataTagKind::MeasDate, _, _) => serializer.collect_str(self),
+            LabelledData(kind, Data::Int32(_), _) if kind.value_name(0).is_some() => {
+                serializer.collect_str(self)
+            }
+            LabelledData(kind, Data::Int32(data), _) if Bitmask::for_tag(kind, data).is_some() => {
+                match Bitmask::for_tag(kind, data).unwrap().as_slice() {
+                    [(None, mask)] => mask.serialize(serializer),
+                    masks if masks.iter().all(|(label, _)| label.is_some()) => {
+                        let mut map = serializer.serialize_map(Some(masks.len()))?;
+                        for (label, mask) in masks {
+                            map.serialize_entry(label.unwrap(), mask)?;
+                        }
+                        map.end()
+                    }
+                    _ => serializer.collect_str(self),
+                }
+            }
+            LabelledData(_, Data::Int32(x), _) if x.len() == 1 => serializer.serialize_i32(x[0]),
+            LabelledData(_, Data::Float(x), _) if x.len() == 1 => serializer.serialize_f32(x[0]),
+            LabelledData(_, Data::Void, _) => serializer.serialize_none(),
+            _ => serializer.collect_str(self),
+        }
+    }
+}
+
+// the four values of event_bits, describing a transition of the trigger lines
+const EVENT_BITS: [&str; 4] = ["from_mask", "from_state", "to_mask", "to_state"];
+
+/// Flags packed into the bits of an int32, e.g. the HPI coils accepted in hpi_fit_accept.
+///
+/// Flags are named by a prefix and their bit's number counted from first, so bit 0 of
+/// hpi_fit_accept is coil1, and bit 0 of event_bits (a trigger line) is bit0.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Bitmask {
+    pub bits: i32,
+    prefix: &'static str,
+    first: u32,
+}
+
+impl Bitmask {
+    pub fn new(bits: i32, prefix: &'static str, first: u32) -> Self {
+        Bitmask {
+            bits,
+            prefix,
+            first,
+        }
+    }
+
+    /// Split the data of a tag holding bitmasks, labelling each one if the tag has several.
+    ///
+    /// None if the tag doesn't hold bitmasks, or has the wrong number of values.
+    pub fn for_tag(kind: &DataTagKind, data: &[i32]) -> Option<Vec<(Option<&'static str>, Self)>> {
+        match kind {
+            DataTagKind::HpiFitAccept => Some(
+                data.iter()
+                    .map(|&x| (None, Bitmask::new(x, "coil", 1)))
+                    .collect(),
+            ),
+            DataTagKind::EventBits if data.len() == EVENT_BITS.len() => Some(
+                EVENT_BITS
+                    .iter()
+                    .zip(data)
+                    .map(|(label, &x)| (Some(*label), Bitmask::new(x, "bit", 0)))
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Numbers of the set bits, lowest first.
+    pub fn set_bits(&self) -> Vec<u32> {
+        (0..i32::BITS)
+            .filter(|x| self.bits & (1 << x) != 0)
+            .collect()
+    }
+
+    /// Names of the set flags, e.g. coil1 and coil3 for 0b101 in hpi_fit_accept.
+    pub fn flags(&self) -> Vec<String> {
+        self.set_bits()
+            .iter()
+            .map(|x| format!("{}{}", self.prefix, x + self.first))
+            .collect()
+    }
+}
+
+impl Display for Bitmask {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.flags().as_slice() {
+            [] => write!(f, "none"),
+            flags => write!(f, "{}", flags.join("|")),
+        }
+    }
+}
+
+impl Serialize for Bitmask {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.flags().serialize(serializer)
+    }
+}
+
+/// Globally unique identifier used for file_id, block_id and friends.
 #[derive(Debug, PartialEq, Clone)]
 pub struct IdStruct {
-    version: i32,
-    machid: (i32, i32),
-    secs: i32,
-    usecs: i32,
+    pub version: i32,
+    pub machid: (i32, i32),
+    pub secs: i32,
+    pub usecs: i32,
+}
+
+/// Version number written into newly generated ids (major 1, minor 3).
+pub const FIFFC_VERSION: i32 = (1 << 16) | 3;
+
+impl IdStruct {
+    /// The time the id was created.
+    pub fn timestamp(&self) -> MeasDate {
+        MeasDate {
+            secs: self.secs,
+            usecs: self.usecs,
+        }
+    }
+
+    // version as major.minor, then the machine id in hex and the timestamp
+    pub(crate) fn display(&self, offset: Option<FixedOffset>) -> String {
+        format!(
+            "{}.{} {:08x}{:08x} {}",
+            self.version >> 16,
+            self.version & 0xffff,
+            self.machid.0,
+            self.machid.1,
+            self.timestamp().display(offset)
+        )
+    }
+
+    /// Generate a fresh id stamped with the current time.
+    ///
+    /// The machine id is meant to be unique per host; we don't have access to a hardware address
+    /// so it is filled from the std library's randomly seeded hasher instead.
+    pub fn generate() -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(now.as_nanos());
+        let random = hasher.finish();
+
+        IdStruct {
+            version: FIFFC_VERSION,
+            machid: ((random >> 32) as i32, random as i32),
+            secs: now.as_secs() as i32,
+            usecs: now.subsec_micros() as i32,
+        }
+    }
+}
+
+impl Display for IdStruct {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.display(None))
+    }
+}
+
+/// Length of the fixed width channel name in a ch_info_struct.
+pub const CH_NAME_LEN: usize = 16;
+
+/// Description of a single measurement channel (ch_info_struct).
+///
+/// The coil position and orientation (r0, ex, ey, ez) are in device coordinates.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ChInfo {
+    pub scan_no: i32,
+    pub log_no: i32,
+    pub kind: i32,
+    pub range: f32,
+    pub cal: f32,
+    pub coil_type: i32,
+    pub r0: [f32; 3],
+    pub ex: [f32; 3],
+    pub ey: [f32; 3],
+    pub ez: [f32; 3],
+    pub unit: i32,
+    pub unit_mul: i32,
+    // all CH_NAME_LEN characters, including any padding, see ch_name
+    pub name: String,
+}
+
+impl ChInfo {
+    pub fn ch_name(&self) -> &str {
+        trim_string(&self.name)
+    }
+
+    pub fn ch_type(&self) -> ChType {
+        ChType::from_code(self.kind)
+    }
+
+    pub fn coil(&self) -> Coil {
+        Coil::from_code(self.coil_type)
+    }
+
+    pub fn unit(&self) -> Unit {
+        Unit::from_code(self.unit)
+    }
+}
+
+/// A digitized point (dig_point_struct): a fiducial, HPI coil, EEG electrode or point on the head
+/// surface, in metres.
+#[derive(Debug, PartialEq, Clone)]
+pub struct DigPoint {
+    pub kind: i32,
+    /// Number of the point among those of its kind, e.g. which fiducial (see `cardinal`)
+    pub ident: i32,
+    pub r: [f32; 3],
+}
+
+impl DigPoint {
+    pub fn point(&self) -> Point {
+        Point::from_code(self.kind)
+    }
+
+    /// Which fiducial a cardinal point is, None for other kinds of point.
+    pub fn cardinal(&self) -> Option<Cardinal> {
+        (self.point() == Point::Cardinal).then(|| Cardinal::from_code(self.ident))
+    }
+}
+
+impl Display for DigPoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.cardinal() {
+            Some(cardinal) => write!(f, "{cardinal}")?,
+            None => write!(f, "{}{}", self.point(), self.ident)?,
+        }
+        write!(f, " ({} m)", display_vec(&self.r))
+    }
+}
+
+/// Transformation between two coordinate frames (coord_trans_struct): a rotation followed by a
+/// translation in metres, stored along with its inverse.
+#[derive(Debug, PartialEq, Clone)]
+pub struct CoordTrans {
+    pub from: i32,
+    pub to: i32,
+    pub rot: [[f32; 3]; 3],
+    pub translation: [f32; 3],
+    pub inv_rot: [[f32; 3]; 3],
+    pub inv_translation: [f32; 3],
+}
+
+impl CoordTrans {
+    pub fn from_frame(&self) -> Coord {
+        Coord::from_code(self.from)
+    }
+
+    pub fn to_frame(&self) -> Coord {
+        Coord::from_code(self.to)
+    }
+
+    /// A point in the `from` frame, in the `to` frame.
+    pub fn apply(&self, point: [f32; 3]) -> [f32; 3] {
+        let rotated = self.rotate(point);
+        [0, 1, 2].map(|i| rotated[i] + self.translation[i])
+    }
+
+    /// A direction in the `from` frame, in the `to` frame.
+    pub fn rotate(&self, direction: [f32; 3]) -> [f32; 3] {
+        self.rot
+            .map(|row| row.iter().zip(direction).map(|(a, b)| a * b).sum())
+    }
+
+    /// This transformation followed by another, from this one's `from` to the other's `to`.
+    pub fn then(&self, next: &CoordTrans) -> CoordTrans {
+        // rotation and translation of b applied after a
+        let compose = |a: &CoordTrans, b: &CoordTrans| {
+            let rot = b
+                .rot
+                .map(|row| [0, 1, 2].map(|j| (0..3).map(|k| row[k] * a.rot[k][j]).sum()));
+            (rot, b.apply(a.translation))
+        };
+
+        let (rot, translation) = compose(self, next);
+        let (inv_rot, inv_translation) = compose(&next.inverse(), &self.inverse());
+
+        CoordTrans {
+            from: self.from,
+            to: next.to,
+            rot,
+            translation,
+            inv_rot,
+            inv_translation,
+        }
+    }
+
+    /// The transformation back from `to` to `from`.
+    pub fn inverse(&self) -> CoordTrans {
+        CoordTrans {
+            from: self.to,
+            to: self.from,
+            rot: self.inv_rot,
+            translation: self.inv_translation,
+            inv_rot: self.rot,
+            inv_translation: self.translation,
+        }
+    }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+impl Display for CoordTrans {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} -> {}, translation {} m",
+            self.from_frame(),
+            self.to_frame(),
+            display_vec(&self.translation)
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct TagDef {
     pub code: i32,
     pub name: String,
@@ -233,6 +840,23 @@ pub struct TagDef {
     description: String,
 }
 
+impl TagDef {
+    // for fields which don't come from the tag dictionary, so have no code
+    pub fn new(name: &str, dtype: &str, unit: &str, description: &str) -> Self {
+        TagDef {
+            code: 0,
+            name: name.to_owned(),
+            dtype: dtype.to_owned(),
+            unit: unit.to_owned(),
+            description: description.to_owned(),
+        }
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+}
+
 impl Default for TagDef {
     fn default() -> Self {
         TagDef {
@@ -245,6 +869,18 @@ impl Default for TagDef {
     }
 }
 
+impl From<&DataTagKind> for TagDef {
+    fn from(kind: &DataTagKind) -> Self {
+        TagDef {
+            code: kind.to_code(),
+            name: kind.to_string(),
+            dtype: kind.dtype().to_owned(),
+            unit: kind.unit().to_owned(),
+            description: kind.description().to_owned(),
+        }
+    }
+}
+
 impl Display for TagDef {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let out = format!(
@@ -255,23 +891,18 @@ impl Display for TagDef {
     }
 }
 
-pub fn read_tag_dict() -> HashMap<String, TagDef> {
-    let file = include_bytes!("../fiff/tags.tsv");
-    let mut reader = ReaderBuilder::new()
-        .delimiter(b'\t')
-        .from_reader(file.as_bytes());
-
-    let mut string_to_tag: HashMap<String, TagDef> = HashMap::new();
+/// Look a tag up by name, or by code as 3999 or code:3999.
+///
+/// Codes don't have to be in the dictionary, so that vendor specific tags can be queried.
+pub fn find_tag(name: &str) -> Result<DataTagKind> {
+    let code = name.strip_prefix("code:").unwrap_or(name);
 
-    for result in reader.deserialize() {
-        let record: TagDef = result.expect("static tsv should have been readable");
-
-        if let Ok(_) = DataTagKind::from_code(record.code) {
-            string_to_tag.insert(record.name.clone(), record);
-        }
+    if let Ok(code) = code.parse::<i32>() {
+        return Ok(DataTagKind::from_code(code));
     }
 
-    string_to_tag
+    name.parse()
+        .map_err(|_| dictionary::unrecognized_tag(name, false))
 }
 
 pub fn tag_header(input: &[u8]) -> IResult<&[u8], (u64, Header)> {
@@ -292,16 +923,113 @@ pub fn tag_header(input: &[u8]) -> IResult<&[u8], (u64, Header)> {
     ))
 }
 
+pub fn dir_entries(input: &[u8]) -> IResult<&[u8], Vec<DirEntry>> {
+    let entry = sequence::tuple((be_i32, be_i32, be_i32, be_i32));
+
+    multi::many0(map(entry, |(code, dtype, size, position)| DirEntry {
+        code,
+        dtype,
+        size,
+        position,
+    }))(input)
+}
+
 pub fn i32_many(input: &[u8]) -> IResult<&[u8], Vec<i32>> {
-    multi::many1(be_i32)(input)
+    multi::many0(be_i32)(input)
 }
 
 pub fn f32_many(input: &[u8]) -> IResult<&[u8], Vec<f32>> {
-    multi::many1(be_f32)(input)
+    multi::many0(be_f32)(input)
+}
+
+/// Decode ISO 8859-1 (Latin-1), the encoding of all strings in .fif files.
+///
+/// Every byte is a valid character, so this can't fail, and padding is kept so that the string can
+/// be written back unchanged.  See `trim_string` for the part worth showing.
+pub fn latin1(input: &[u8]) -> String {
+    input.iter().map(|&x| char::from(x)).collect()
+}
+
+/// The meaningful part of a string read from a file: strings may be NUL terminated, with anything
+/// after the NUL being padding, and may be padded with spaces.
+pub fn trim_string(input: &str) -> &str {
+    input.split('\0').next().unwrap_or_default().trim_end()
+}
+
+pub fn ch_info(input: &[u8]) -> IResult<&[u8], ChInfo> {
+    let vec3 = || {
+        map(sequence::tuple((be_f32, be_f32, be_f32)), |(x, y, z)| {
+            [x, y, z]
+        })
+    };
+
+    let (input, (scan_no, log_no, kind, range, cal, coil_type)) =
+        sequence::tuple((be_i32, be_i32, be_i32, be_f32, be_f32, be_i32))(input)?;
+    let (input, (r0, ex, ey, ez)) = sequence::tuple((vec3(), vec3(), vec3(), vec3()))(input)?;
+    let (input, (unit, unit_mul, name)) =
+        sequence::tuple((be_i32, be_i32, take(CH_NAME_LEN)))(input)?;
+
+    Ok((
+        input,
+        ChInfo {
+            scan_no,
+            log_no,
+            kind,
+            range,
+            cal,
+            coil_type,
+            r0,
+            ex,
+            ey,
+            ez,
+            unit,
+            unit_mul,
+            name: latin1(name),
+        },
+    ))
+}
+
+pub fn dig_point(input: &[u8]) -> IResult<&[u8], DigPoint> {
+    let (input, (kind, ident, x, y, z)) =
+        sequence::tuple((be_i32, be_i32, be_f32, be_f32, be_f32))(input)?;
+
+    Ok((
+        input,
+        DigPoint {
+            kind,
+            ident,
+            r: [x, y, z],
+        },
+    ))
 }
 
-pub fn string(input: Vec<u8>) -> String {
-    String::from_utf8(input).unwrap()
+pub fn coord_trans(input: &[u8]) -> IResult<&[u8], CoordTrans> {
+    let vec3 = || {
+        map(sequence::tuple((be_f32, be_f32, be_f32)), |(x, y, z)| {
+            [x, y, z]
+        })
+    };
+    let mat3 = || {
+        map(sequence::tuple((vec3(), vec3(), vec3())), |(x, y, z)| {
+            [x, y, z]
+        })
+    };
+
+    let (input, (from, to)) = sequence::tuple((be_i32, be_i32))(input)?;
+    let (input, (rot, translation, inv_rot, inv_translation)) =
+        sequence::tuple((mat3(), vec3(), mat3(), vec3()))(input)?;
+
+    Ok((
+        input,
+        CoordTrans {
+            from,
+            to,
+            rot,
+            translation,
+            inv_rot,
+            inv_translation,
+        },
+    ))
 }
 
 pub fn idstruct(input: &[u8]) -> IResult<&[u8], IdStruct> {
@@ -319,25 +1047,287 @@ pub fn idstruct(input: &[u8]) -> IResult<&[u8], IdStruct> {
         },
     ))
 }
-use chrono::NaiveDateTime;
-
-pub fn decode_unix_date(ivec: &Vec<i32>) -> String {
-    NaiveDateTime::from_timestamp_opt(ivec[0].into(), ivec[1].try_into().unwrap())
-        .unwrap()
-        .to_string()
-}
-
+#[cfg(test)]
 mod tests {
     use super::*;
+    use crate::date::parse_utc_offset;
 
     #[test]
     fn can_decode_date() {
         let data = Data::Int32(vec![1646226731, 138511]);
 
-        if let Data::Int32(ivec) = data {
+        if let Data::Int32(ivec) = data.clone() {
             assert_eq!(ivec.len(), 2);
+        }
+
+        let date = LabelledData::new(DataTagKind::MeasDate, data);
+        assert_eq!(date.to_string(), "2022-03-02T13:12:11.138511Z");
+
+        let offset = parse_utc_offset("-01:00").unwrap();
+        assert_eq!(
+            date.with_utc_offset(Some(offset)).to_string(),
+            "2022-03-02T12:12:11.138511-01:00"
+        );
+    }
+
+    #[test]
+    fn can_display_id() {
+        let id = IdStruct {
+            version: FIFFC_VERSION,
+            machid: (1, -1),
+            secs: 1646226731,
+            usecs: 138511,
+        };
+
+        assert_eq!(
+            Data::IdStruct(id).to_string(),
+            "1.3 00000001ffffffff 2022-03-02T13:12:11.138511Z"
+        );
+    }
+
+    #[test]
+    fn keeps_undecodable_payloads() {
+        // 6 bytes can't be read as int32s, so they're kept as they are
+        let data = Data::from_slice(vec![0, 0, 0, 1, 0, 0], 3);
+        assert_eq!(
+            data,
+            Data::Slice {
+                dtype: 3,
+                bytes: vec![0, 0, 0, 1, 0, 0]
+            }
+        );
+
+        assert_eq!(Data::from_slice(vec![], 3), Data::Int32(vec![]));
+        assert_eq!(Data::from_slice(vec![], 0), Data::Void);
+        assert_eq!(Data::from_slice(vec![0; 8], 0).dtype(), 0);
+    }
+
+    #[test]
+    fn can_find_tags_by_code() {
+        assert_eq!(find_tag("sfreq").unwrap(), DataTagKind::Sfreq);
+        assert_eq!(find_tag("201").unwrap(), DataTagKind::Sfreq);
+        assert_eq!(find_tag("code:3999").unwrap(), DataTagKind::Unknown(3999));
+        assert_eq!(TagDef::from(&DataTagKind::Unknown(3999)).name, "3999");
+        assert!(find_tag("no_such_tag").is_err());
+    }
+
+    #[test]
+    fn keeps_unknown_tags() {
+        let header = Header {
+            code: 3999,
+            dtype: 3,
+            size: 4,
+            next: FIFFV_NEXT_SEQ,
+        };
+
+        let tag = Tag::from_header_slice(header, vec![0, 0, 0, 7]).unwrap();
+        assert_eq!(
+            tag,
+            Tag::Data {
+                kind: DataTagKind::Unknown(3999),
+                data: Data::Int32(vec![7]),
+                next: FIFFV_NEXT_SEQ,
+            }
+        );
+        assert_eq!(tag.code(), 3999);
+    }
+
+    #[test]
+    fn can_decode_latin1() {
+        let data = Data::from_slice(b"Ren\xe9e M\xfcller\0\0\0".to_vec(), 10);
+
+        assert_eq!(data, Data::String("Renée Müller\0\0\0".into()));
+        assert_eq!(data.to_string(), "Renée Müller");
+
+        assert_eq!(trim_string("MEG 0113  \0junk"), "MEG 0113");
+        assert!(data.parse_as("Zoë").is_ok());
+        assert!(data.parse_as("Zoë 🙂").is_err());
+    }
+
+    #[test]
+    fn can_decode_ch_info() {
+        let mut bytes: Vec<u8> = [1i32, 1, 1].iter().flat_map(|x| x.to_be_bytes()).collect();
+        bytes.extend([1.0f32, 2.5].iter().flat_map(|x| x.to_be_bytes()));
+        bytes.extend(3012i32.to_be_bytes());
+        bytes.extend([0.0f32; 12].iter().flat_map(|x| x.to_be_bytes()));
+        bytes.extend([112i32, 0].iter().flat_map(|x| x.to_be_bytes()));
+        bytes.extend(b"MEG 0113\0\0\0\0\0\0\0\0");
+        assert_eq!(bytes.len(), 96);
+
+        let data = Data::from_slice(bytes.clone(), 30);
+        let Data::ChInfoStruct(channels) = &data else {
+            panic!("ch_info should have been decoded, got {data:?}");
+        };
+
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0].coil_type, 3012);
+        assert_eq!(channels[0].coil(), Coil::VvPlanarT1);
+        assert_eq!(channels[0].ch_type(), ChType::Meg);
+        assert_eq!(channels[0].unit(), Unit::T);
+        assert_eq!(channels[0].cal, 2.5);
+        assert_eq!(channels[0].ch_name(), "MEG 0113");
+        assert_eq!(data.to_string(), "MEG 0113");
+
+        // a truncated struct is kept as it is
+        assert!(matches!(
+            Data::from_slice(bytes[..90].to_vec(), 30),
+            Data::Slice { dtype: 30, .. }
+        ));
+    }
+
+    #[test]
+    fn can_decode_dig_points() {
+        let point = |kind: i32, ident: i32, r: [f32; 3]| {
+            let mut bytes: Vec<u8> = [kind, ident].iter().flat_map(|x| x.to_be_bytes()).collect();
+            bytes.extend(r.iter().flat_map(|x| x.to_be_bytes()));
+            bytes
+        };
+        let bytes = [point(1, 2, [0.0, 0.1, 0.0]), point(3, 5, [0.05, 0.0, 0.1])].concat();
+
+        let data = Data::from_slice(bytes.clone(), 33);
+        let Data::DigPointStruct(points) = &data else {
+            panic!("dig_point should have been decoded, got {data:?}");
+        };
+
+        assert_eq!(points[0].cardinal(), Some(Cardinal::Nasion));
+        assert_eq!(points[1].point(), Point::Eeg);
+        assert_eq!(points[1].cardinal(), None);
+        assert_eq!(data.to_string(), "NAS (0 0.1 0 m) eeg5 (0.05 0 0.1 m)");
+        assert_eq!(encode_data(&data).unwrap(), bytes);
+    }
 
-            decode_unix_date(&ivec);
+    #[test]
+    fn can_decode_coord_trans() {
+        // device to head: a quarter turn about z, then 1 cm up
+        let rot = [0.0f32, -1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0];
+        let inv_rot = [0.0f32, 1.0, 0.0, -1.0, 0.0, 0.0, 0.0, 0.0, 1.0];
+        let mut bytes: Vec<u8> = [1i32, 4].iter().flat_map(|x| x.to_be_bytes()).collect();
+        for floats in [&rot[..], &[0.0, 0.0, 0.01], &inv_rot, &[0.0, 0.0, -0.01]] {
+            bytes.extend(floats.iter().flat_map(|x| x.to_be_bytes()));
         }
+        assert_eq!(bytes.len(), 104);
+
+        let data = Data::from_slice(bytes.clone(), 35);
+        let Data::CoordTransStruct(trans) = &data else {
+            panic!("coord_trans should have been decoded, got {data:?}");
+        };
+
+        assert_eq!(trans.from_frame(), Coord::Device);
+        assert_eq!(trans.to_frame(), Coord::Head);
+        assert_eq!(trans.apply([1.0, 0.0, 0.0]), [0.0, 1.0, 0.01]);
+        assert_eq!(trans.rotate([1.0, 0.0, 0.0]), [0.0, 1.0, 0.0]);
+        assert_eq!(trans.inverse().apply([0.0, 1.0, 0.01]), [1.0, 0.0, 0.0]);
+
+        let twice = trans.then(trans);
+        assert_eq!(twice.apply([1.0, 0.0, 0.0]), [-1.0, 0.0, 0.02]);
+        assert_eq!(twice.inverse().apply([-1.0, 0.0, 0.02]), [1.0, 0.0, 0.0]);
+        assert_eq!(data.to_string(), "device -> head, translation 0 0 0.01 m");
+        assert_eq!(encode_data(&data).unwrap(), bytes);
+    }
+
+    #[test]
+    fn can_display_julian_date() {
+        let birthday = |x| LabelledData::new(DataTagKind::SubjBirthDay, x).to_string();
+
+        assert_eq!(birthday(Data::JulianDate(vec![2451545])), "2000-01-01");
+        assert_eq!(birthday(Data::Int32(vec![2451545])), "2000-01-01");
+        assert_eq!(birthday(Data::JulianDate(vec![0])), "unset");
+        assert_eq!(
+            birthday(Data::JulianDate(vec![i32::MIN])),
+            i32::MIN.to_string()
+        );
+    }
+
+    #[test]
+    fn can_parse_julian_date() {
+        let data = Data::JulianDate(vec![]);
+
+        assert_eq!(
+            data.parse_as("2000-01-01").unwrap(),
+            Data::JulianDate(vec![2451545])
+        );
+        assert_eq!(
+            data.parse_as("2451546").unwrap(),
+            Data::JulianDate(vec![2451546])
+        );
+        assert!(data.parse_as("2000-13-01").is_err());
+    }
+
+    #[test]
+    fn can_parse_values_for_tags() {
+        let parse = |kind, value| Data::parse_for(&kind, value);
+
+        assert_eq!(
+            parse(DataTagKind::MeasDate, "0").unwrap(),
+            Data::Int32(vec![0])
+        );
+        assert_eq!(
+            parse(DataTagKind::SubjBirthDay, "2000-01-01").unwrap(),
+            Data::JulianDate(vec![2451545])
+        );
+        assert_eq!(
+            parse(DataTagKind::SubjSex, "female").unwrap(),
+            Data::Int32(vec![2])
+        );
+        assert_eq!(
+            parse(DataTagKind::Experimenter, "someone").unwrap(),
+            Data::String("someone".into())
+        );
+        assert!(parse(DataTagKind::MeasDate, "yesterday").is_err());
+        assert!(parse(DataTagKind::ChInfo, "MEG 0113").is_err());
+    }
+
+    #[test]
+    fn can_display_enumerated_values() {
+        let show = |kind, x| LabelledData::new(kind, Data::Int32(x)).to_string();
+
+        assert_eq!(show(DataTagKind::SubjSex, vec![2]), "female");
+        assert_eq!(show(DataTagKind::ChKind, vec![2]), "EEG");
+        assert_eq!(show(DataTagKind::ChCoordFrame, vec![4, 1]), "head device");
+        assert_eq!(show(DataTagKind::SubjHand, vec![9]), "9");
+        assert_eq!(show(DataTagKind::Nchan, vec![2]), "2");
+    }
+
+    #[test]
+    fn can_decode_bitmasks() {
+        let mask = Bitmask::new(0b1011, "coil", 1);
+        assert_eq!(mask.set_bits(), vec![0, 1, 3]);
+        assert_eq!(mask.to_string(), "coil1|coil2|coil4");
+        assert_eq!(Bitmask::new(0, "coil", 1).to_string(), "none");
+        assert_eq!(Bitmask::new(i32::MIN, "bit", 0).to_string(), "bit31");
+
+        let show = |kind, x| LabelledData::new(kind, Data::Int32(x)).to_string();
+        assert_eq!(show(DataTagKind::HpiFitAccept, vec![0b101]), "coil1|coil3");
+        assert_eq!(
+            show(DataTagKind::EventBits, vec![0b11, 0b01, 0b11, 0]),
+            "from_mask=bit0|bit1 from_state=bit0 to_mask=bit0|bit1 to_state=none"
+        );
+        assert_eq!(show(DataTagKind::EventBits, vec![3, 1]), "3 1");
+
+        let json = |kind, x| serde_json::to_string(&LabelledData::new(kind, Data::Int32(x)));
+        assert_eq!(
+            json(DataTagKind::HpiFitAccept, vec![0b110]).unwrap(),
+            r#"["coil2","coil3"]"#
+        );
+        assert_eq!(
+            json(DataTagKind::EventBits, vec![1, 0, 1, 1]).unwrap(),
+            r#"{"from_mask":["bit0"],"from_state":[],"to_mask":["bit0"],"to_state":["bit0"]}"#
+        );
+    }
+
+    #[test]
+    fn can_serialize_labelled_data() {
+        let mut wtr = csv::Writer::from_writer(vec![]);
+        wtr.serialize((
+            LabelledData::new(DataTagKind::SubjBirthDay, Data::JulianDate(vec![2451545])),
+            LabelledData::new(DataTagKind::SubjBirthDay, Data::JulianDate(vec![0])),
+            LabelledData::new(DataTagKind::Nchan, Data::Int32(vec![306])),
+            LabelledData::new(DataTagKind::SphereLayers, Data::Int32(vec![3, 4])),
+            LabelledData::new(DataTagKind::SubjSex, Data::Int32(vec![1])),
+        ))
+        .unwrap();
+
+        let out = String::from_utf8(wtr.into_inner().unwrap()).unwrap();
+        assert_eq!(out, "2000-01-01,,306,3 4,male\n");
     }
 }
@@ -0,0 +1,190 @@
+//! Check the structure of .fif files.
+//!
+//! Reading is deliberately forgiving (unknown tags are kept, unbalanced blocks are closed at the
+//! root), so this looks for the problems that reading glosses over.
+
+use std::fmt::Display;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use crate::enums::{BlockKind, BlockTagKind, DataTagKind};
+use crate::parser::FifParser;
+use crate::tag::{Data, Tag};
+
+/// Something wrong with a file, and the position of the tag where it was found.
+#[derive(Debug, PartialEq)]
+pub struct Problem {
+    pub position: Option<u64>,
+    pub message: String,
+}
+
+impl Problem {
+    fn at(position: u64, message: String) -> Self {
+        Problem {
+            position: Some(position),
+            message,
+        }
+    }
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.position {
+            Some(position) => write!(f, "at byte {position}: {}", self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Read a file and check its tags, a file which can't be read is a single problem.
+pub fn validate_file(file: &Path) -> Vec<Problem> {
+    let tags = File::open(file)
+        .map_err(anyhow::Error::from)
+        .and_then(|x| FifParser::read_positioned_tags_from(BufReader::new(x)));
+
+    match tags {
+        Ok(tags) => check_tags(&tags),
+        Err(e) => vec![Problem {
+            position: None,
+            message: format!("{e:#}"),
+        }],
+    }
+}
+
+/// Check that tags (with the positions of their headers) make up a well formed file.
+pub fn check_tags(tags: &[(u64, Tag)]) -> Vec<Problem> {
+    let mut problems = vec![];
+
+    match tags.first() {
+        None => problems.push(Problem {
+            position: None,
+            message: String::from("file has no tags"),
+        }),
+        Some((_, Tag::Data { kind, .. })) if *kind == DataTagKind::FileId => {}
+        Some((position, tag)) => problems.push(Problem::at(
+            *position,
+            format!("file should start with file_id, found code {}", tag.code()),
+        )),
+    }
+
+    // blocks still open, with the position of their block_start
+    let mut open: Vec<(u64, BlockKind)> = vec![];
+
+    for (position, tag) in tags {
+        let Tag::Block { kind, data, .. } = tag else {
+            continue;
+        };
+
+        let block = match data {
+            Data::Int32(x) if x.len() == 1 => BlockKind::from_code(x[0]),
+            _ => {
+                problems.push(Problem::at(
+                    *position,
+                    format!("{kind:?} should hold a single block kind, found {data}"),
+                ));
+                continue;
+            }
+        };
+
+        match kind {
+            BlockTagKind::BlockStart => open.push((*position, block)),
+            BlockTagKind::BlockEnd => match open.pop() {
+                Some((_, started)) if started == block => {}
+                Some((start, started)) => problems.push(Problem::at(
+                    *position,
+                    format!("end of {block} block, but {started} block started at byte {start} is still open"),
+                )),
+                None => problems.push(Problem::at(
+                    *position,
+                    format!("end of {block} block which was never started"),
+                )),
+            },
+        }
+    }
+
+    for (position, block) in open {
+        problems.push(Problem::at(
+            position,
+            format!("{block} block is never ended"),
+        ));
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tag::{IdStruct, FIFFV_NEXT_SEQ};
+
+    fn block(kind: BlockTagKind, block: BlockKind) -> Tag {
+        Tag::Block {
+            kind,
+            data: Data::Int32(vec![block.to_code()]),
+            next: FIFFV_NEXT_SEQ,
+        }
+    }
+
+    fn file(tags: Vec<Tag>) -> Vec<(u64, Tag)> {
+        let file_id = Tag::Data {
+            kind: DataTagKind::FileId,
+            data: Data::IdStruct(IdStruct::generate()),
+            next: FIFFV_NEXT_SEQ,
+        };
+
+        std::iter::once(file_id)
+            .chain(tags)
+            .enumerate()
+            .map(|(i, tag)| (i as u64 * 100, tag))
+            .collect()
+    }
+
+    #[test]
+    fn accepts_well_formed_files() {
+        let tags = file(vec![
+            block(BlockTagKind::BlockStart, BlockKind::Meas),
+            block(BlockTagKind::BlockStart, BlockKind::MeasInfo),
+            block(BlockTagKind::BlockEnd, BlockKind::MeasInfo),
+            block(BlockTagKind::BlockEnd, BlockKind::Meas),
+        ]);
+
+        assert_eq!(check_tags(&tags), vec![]);
+    }
+
+    #[test]
+    fn finds_unbalanced_blocks() {
+        let tags = file(vec![
+            block(BlockTagKind::BlockStart, BlockKind::Meas),
+            block(BlockTagKind::BlockStart, BlockKind::MeasInfo),
+            block(BlockTagKind::BlockEnd, BlockKind::Meas),
+            block(BlockTagKind::BlockEnd, BlockKind::Subject),
+        ]);
+
+        let problems: Vec<String> = check_tags(&tags).iter().map(|x| x.to_string()).collect();
+        assert_eq!(
+            problems,
+            vec![
+                "at byte 300: end of meas block, but meas_info block started at byte 200 is still open",
+                "at byte 400: end of subject block, but meas block started at byte 100 is still open",
+            ]
+        );
+
+        let tags = file(vec![block(BlockTagKind::BlockStart, BlockKind::Meas)]);
+        assert_eq!(
+            check_tags(&tags)[0].to_string(),
+            "at byte 100: meas block is never ended"
+        );
+    }
+
+    #[test]
+    fn needs_file_id_first() {
+        assert_eq!(check_tags(&[]).len(), 1);
+
+        let tags = vec![(0, block(BlockTagKind::BlockStart, BlockKind::Meas))];
+        assert_eq!(
+            check_tags(&tags)[0].to_string(),
+            "at byte 0: file should start with file_id, found code 104"
+        );
+    }
+}
@@ -0,0 +1,56 @@
+//! Enumerated values of tags
+//!
+//! Generated by build.rs from the dictionaries in fiff/values/, e.g. ChType (ch_type.tsv) for
+//! ch_kind and Sex (sex.tsv) for subj_sex.  They work like the tag enums, except that `Display`
+//! gives the symbol (EEG, T/m, ...), and `FromStr` accepts either the name or the symbol,
+//! ignoring case.
+//!
+//! `DataTagKind::value_name` and `DataTagKind::parse_value` convert the values of the tags
+//! declared as enum(..) in tags.tsv.
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::enums::DataTagKind;
+
+include!(concat!(env!("OUT_DIR"), "/values.rs"));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_look_up_values() {
+        assert_eq!(ChType::from_code(2), ChType::Eeg);
+        assert_eq!(ChType::Eeg.to_string(), "EEG");
+        assert_eq!(Unit::from_code(201).to_string(), "T/m");
+        assert_eq!(Sex::from_code(7), Sex::Unknown(7));
+
+        assert_eq!("eeg".parse::<ChType>().unwrap(), ChType::Eeg);
+        assert_eq!("REF_MEG".parse::<ChType>().unwrap(), ChType::RefMeg);
+        assert_eq!("T/m".parse::<Unit>().unwrap(), Unit::TM);
+        assert!("xyz".parse::<Coil>().is_err());
+    }
+
+    #[test]
+    fn knows_values_of_tags() {
+        assert_eq!(
+            DataTagKind::SubjSex.value_name(2),
+            Some(String::from("female"))
+        );
+        assert_eq!(DataTagKind::ChKind.value_name(2), Some(String::from("EEG")));
+        assert_eq!(DataTagKind::Nchan.value_name(2), None);
+        // no value list for gantry_type, so its values are shown as numbers
+        assert_eq!(DataTagKind::GantryType.value_name(1), None);
+
+        assert_eq!(
+            DataTagKind::SubjHand.parse_value("Left").unwrap().unwrap(),
+            2
+        );
+        assert_eq!(DataTagKind::SubjHand.parse_value("2").unwrap().unwrap(), 2);
+        assert!(DataTagKind::SubjHand
+            .parse_value("upside down")
+            .unwrap()
+            .is_err());
+        assert!(DataTagKind::Nchan.parse_value("2").is_none());
+    }
+}
@@ -0,0 +1,467 @@
+//! Views of a file's tree as text, JSON or Graphviz DOT, with options to leave parts of it out.
+//!
+//! Files hold hundreds of ch_info tags and thousands of data buffers, so by default runs of tags
+//! of the same kind are collapsed into a single line, e.g. `data_buffer ×8123 (1.4 GB)`.
+
+use std::str::FromStr;
+
+use anyhow::bail;
+use petgraph::stable_graph::NodeIndex;
+use serde_json::{json, Value};
+
+use crate::enums::DataTagKind;
+use crate::graph::Tree;
+use crate::parser::Positions;
+use crate::tag::{human_size, Data, FiffNode, LabelledData};
+
+// runs of at least this many tags of the same kind are collapsed
+const COLLAPSE_RUN: usize = 3;
+
+// bytes taken by a tag header, and by the block_start / block_end tags around a block
+const HEADER_SIZE: u64 = 16;
+const BLOCK_TAGS_SIZE: u64 = 2 * (HEADER_SIZE + 4);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum TreeFormat {
+    #[default]
+    Text,
+    Json,
+    Dot,
+}
+
+impl FromStr for TreeFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "text" => TreeFormat::Text,
+            "json" => TreeFormat::Json,
+            "dot" => TreeFormat::Dot,
+            _ => bail!("Unknown tree format {s}, expected text, json or dot"),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TreeOptions {
+    /// Only show nodes down to this many blocks deep
+    pub max_depth: Option<usize>,
+    /// Leave out all tags, showing just the block structure
+    pub blocks_only: bool,
+    /// Only show tags of these kinds, all tags if empty
+    pub include: Vec<DataTagKind>,
+    /// Leave out tags of these kinds
+    pub hide: Vec<DataTagKind>,
+    /// Show every tag, rather than collapsing runs of the same kind
+    pub expand: bool,
+}
+
+impl TreeOptions {
+    pub(crate) fn shows(&self, node: &FiffNode) -> bool {
+        match node {
+            FiffNode::Block { .. } => true,
+            FiffNode::Tag { kind, .. } => {
+                !self.blocks_only
+                    && (self.include.is_empty() || self.include.contains(kind))
+                    && !self.hide.contains(kind)
+            }
+        }
+    }
+}
+
+/// Number of tags (at any depth) in a block, and the bytes they take up including headers.
+pub fn block_stats(tree: &Tree<FiffNode>, block: NodeIndex) -> (usize, u64) {
+    let mut tags = 0;
+    let mut size = 0;
+
+    for node in tree.depth_first(block).into_iter().skip(1) {
+        match &tree[node] {
+            FiffNode::Tag { data, .. } => {
+                tags += 1;
+                size += HEADER_SIZE + data.size();
+            }
+            FiffNode::Block { .. } => size += BLOCK_TAGS_SIZE,
+        }
+    }
+
+    (tags, size)
+}
+
+/// Children of a node which the options show, with runs of tags of the same kind grouped
+/// together (unless expanded).
+pub(crate) fn grouped_children(
+    tree: &Tree<FiffNode>,
+    node: NodeIndex,
+    options: &TreeOptions,
+) -> Vec<Vec<NodeIndex>> {
+    let mut groups: Vec<Vec<NodeIndex>> = vec![];
+
+    for child in tree.children(node) {
+        if !options.shows(&tree[child]) {
+            continue;
+        }
+
+        let same_kind = |x: &NodeIndex| match (&tree[*x], &tree[child]) {
+            (FiffNode::Tag { kind: a, .. }, FiffNode::Tag { kind: b, .. }) => a == b,
+            _ => false,
+        };
+
+        match groups.last_mut() {
+            Some(group) if !options.expand && same_kind(&group[0]) => group.push(child),
+            _ => groups.push(vec![child]),
+        }
+    }
+
+    // short runs are shown in full
+    groups
+        .into_iter()
+        .flat_map(|x| {
+            if x.len() < COLLAPSE_RUN {
+                x.into_iter().map(|y| vec![y]).collect()
+            } else {
+                vec![x]
+            }
+        })
+        .collect()
+}
+
+fn block_label(tree: &Tree<FiffNode>, block: NodeIndex) -> String {
+    let (tags, size) = block_stats(tree, block);
+    format!("{} ({tags} tags, {})", tree[block], human_size(size))
+}
+
+fn group_label(tree: &Tree<FiffNode>, group: &[NodeIndex]) -> String {
+    let size: u64 = group
+        .iter()
+        .map(|x| match &tree[*x] {
+            FiffNode::Tag { data, .. } => HEADER_SIZE + data.size(),
+            FiffNode::Block { .. } => 0,
+        })
+        .sum();
+
+    match &tree[group[0]] {
+        FiffNode::Tag { kind, .. } => format!("{kind} ×{} ({})", group.len(), human_size(size)),
+        node => node.to_string(),
+    }
+}
+
+fn view(
+    tree: &Tree<FiffNode>,
+    node: NodeIndex,
+    depth: usize,
+    options: &TreeOptions,
+) -> termtree::Tree<String> {
+    let mut view = termtree::Tree::new(block_label(tree, node));
+
+    if options.max_depth.is_some_and(|x| depth >= x) {
+        return view;
+    }
+
+    for group in grouped_children(tree, node, options) {
+        let child = group[0];
+
+        view.push(match &tree[child] {
+            FiffNode::Block { .. } => self::view(tree, child, depth + 1, options),
+            FiffNode::Tag { .. } if group.len() > 1 => {
+                termtree::Tree::new(group_label(tree, &group))
+            }
+            FiffNode::Tag { .. } => termtree::Tree::new(tree[child].to_string()),
+        });
+    }
+
+    view
+}
+
+/// Draw the tree as text, one node per line.
+pub fn render_text(tree: &Tree<FiffNode>, options: &TreeOptions) -> String {
+    view(tree, tree.root, 0, options).to_string()
+}
+
+fn tag_size(tree: &Tree<FiffNode>, tag: NodeIndex) -> u64 {
+    match &tree[tag] {
+        FiffNode::Tag { data, .. } => data.size(),
+        FiffNode::Block { .. } => 0,
+    }
+}
+
+fn json_node(
+    tree: &Tree<FiffNode>,
+    positions: &Positions,
+    node: NodeIndex,
+    depth: usize,
+    options: &TreeOptions,
+) -> Value {
+    let kind = match &tree[node] {
+        FiffNode::Block { kind } => kind,
+        FiffNode::Tag { .. } => return json_group(tree, positions, &[node]),
+    };
+
+    let children: Vec<Value> = if options.max_depth.is_some_and(|x| depth >= x) {
+        vec![]
+    } else {
+        grouped_children(tree, node, options)
+            .iter()
+            .map(|group| match &tree[group[0]] {
+                FiffNode::Block { .. } => json_node(tree, positions, group[0], depth + 1, options),
+                FiffNode::Tag { .. } => json_group(tree, positions, group),
+            })
+            .collect()
+    };
+
+    let (tags, size) = block_stats(tree, node);
+    json!({
+        "block": kind.to_string(),
+        "code": kind.to_code(),
+        "offset": positions.get(&node),
+        "tags": tags,
+        "size": size,
+        "children": children,
+    })
+}
+
+// a single tag with its decoded value, or a collapsed run of tags with their offsets
+fn json_group(tree: &Tree<FiffNode>, positions: &Positions, group: &[NodeIndex]) -> Value {
+    let FiffNode::Tag { kind, data } = &tree[group[0]] else {
+        return Value::Null;
+    };
+
+    if let [tag] = group {
+        let value = match data {
+            Data::InFile { .. } => Value::Null,
+            _ => serde_json::to_value(LabelledData::new(kind.clone(), data.clone()))
+                .unwrap_or_default(),
+        };
+
+        return json!({
+            "tag": kind.to_string(),
+            "code": kind.to_code(),
+            "offset": positions.get(tag),
+            "size": data.size(),
+            "value": value,
+        });
+    }
+
+    let offsets: Vec<Option<&u64>> = group.iter().map(|x| positions.get(x)).collect();
+    json!({
+        "tag": kind.to_string(),
+        "code": kind.to_code(),
+        "count": group.len(),
+        "offsets": offsets,
+        "size": group.iter().map(|x| tag_size(tree, *x)).sum::<u64>(),
+    })
+}
+
+/// The tree as nested blocks and tags, with decoded values, the offsets of their headers and
+/// their sizes. Values left in the file (e.g. data buffers) are null.
+pub fn render_json(tree: &Tree<FiffNode>, positions: &Positions, options: &TreeOptions) -> Value {
+    json_node(tree, positions, tree.root, 0, options)
+}
+
+// quoted for use as a DOT id
+fn dot_quote(label: &str) -> String {
+    format!("\"{}\"", label.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn dot_nodes(
+    tree: &Tree<FiffNode>,
+    node: NodeIndex,
+    depth: usize,
+    options: &TreeOptions,
+    out: &mut Vec<String>,
+) {
+    let id = node.index();
+    out.push(format!(
+        "    n{id} [label={}];",
+        dot_quote(&block_label(tree, node))
+    ));
+
+    if options.max_depth.is_some_and(|x| depth >= x) {
+        return;
+    }
+
+    for group in grouped_children(tree, node, options) {
+        let child = group[0];
+        out.push(format!("    n{id} -> n{};", child.index()));
+
+        match &tree[child] {
+            FiffNode::Block { .. } => dot_nodes(tree, child, depth + 1, options, out),
+            FiffNode::Tag { .. } => {
+                let label = match group.len() {
+                    1 => tree[child].to_string(),
+                    _ => group_label(tree, &group),
+                };
+                out.push(format!(
+                    "    n{} [label={}, shape=plaintext];",
+                    child.index(),
+                    dot_quote(&label)
+                ));
+            }
+        }
+    }
+}
+
+/// The tree as a Graphviz digraph, blocks as boxes and tags as plain text.
+pub fn render_dot(tree: &Tree<FiffNode>, name: &str, options: &TreeOptions) -> String {
+    let mut lines = vec![
+        format!("digraph {} {{", dot_quote(name)),
+        String::from("    node [shape=box];"),
+    ];
+    dot_nodes(tree, tree.root, 0, options, &mut lines);
+    lines.push(String::from("}"));
+
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::BlockKind;
+    use crate::tag::Data;
+
+    fn make_tree() -> Tree<FiffNode> {
+        let mut tree = Tree::new();
+        tree.add_child(FiffNode::Tag {
+            kind: DataTagKind::FileId,
+            data: Data::Slice {
+                dtype: 31,
+                bytes: vec![0; 20],
+            },
+        });
+
+        let meas = tree.add_child(FiffNode::Block {
+            kind: BlockKind::Meas,
+        });
+        tree.move_to(meas);
+        tree.add_child(FiffNode::Tag {
+            kind: DataTagKind::Nchan,
+            data: Data::Int32(vec![2]),
+        });
+
+        let raw = tree.add_child(FiffNode::Block {
+            kind: BlockKind::RawData,
+        });
+        tree.move_to(raw);
+        for _ in 0..4 {
+            tree.add_child(FiffNode::Tag {
+                kind: DataTagKind::DataBuffer,
+                data: Data::InFile {
+                    start: 0,
+                    size: 1_000_000,
+                    dtype: 4,
+                },
+            });
+        }
+
+        tree
+    }
+
+    #[test]
+    fn can_collapse_runs() {
+        let text = render_text(&make_tree(), &TreeOptions::default());
+
+        assert_eq!(
+            text,
+            "root (6 tags, 4.0 MB)
+├── file_id = Slice { dtype: 31, bytes: [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,…
+└── meas (5 tags, 4.0 MB)
+    ├── nchan = 2
+    └── raw_data (4 tags, 4.0 MB)
+        └── data_buffer ×4 (4.0 MB)
+"
+        );
+
+        let options = TreeOptions {
+            expand: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            render_text(&make_tree(), &options)
+                .matches("data_buffer (1.0 MB)")
+                .count(),
+            4
+        );
+    }
+
+    #[test]
+    fn can_filter_tree() {
+        let options = TreeOptions {
+            max_depth: Some(1),
+            hide: vec![DataTagKind::FileId],
+            ..Default::default()
+        };
+        assert_eq!(
+            render_text(&make_tree(), &options),
+            "root (6 tags, 4.0 MB)\n└── meas (5 tags, 4.0 MB)\n"
+        );
+
+        let options = TreeOptions {
+            blocks_only: true,
+            ..Default::default()
+        };
+        assert!(!render_text(&make_tree(), &options).contains("nchan"));
+
+        let options = TreeOptions {
+            include: vec![DataTagKind::Nchan],
+            ..Default::default()
+        };
+        let text = render_text(&make_tree(), &options);
+        assert!(text.contains("nchan = 2"));
+        assert!(!text.contains("file_id"));
+        assert!(!text.contains("data_buffer"));
+    }
+
+    #[test]
+    fn can_write_json() {
+        let tree = make_tree();
+        let file_id = tree.children(tree.root)[0];
+        let positions = Positions::from([(file_id, 0)]);
+
+        let json = render_json(&tree, &positions, &TreeOptions::default());
+        assert_eq!(json["block"], "root");
+        assert_eq!(json["tags"], 6);
+        assert_eq!(json["children"][0]["tag"], "file_id");
+        assert_eq!(json["children"][0]["offset"], 0);
+        assert_eq!(json["children"][0]["size"], 20);
+
+        let meas = &json["children"][1];
+        assert_eq!(meas["offset"], Value::Null);
+        assert_eq!(meas["children"][0]["value"], 2);
+        assert_eq!(meas["children"][1]["children"][0]["count"], 4);
+        assert_eq!(meas["children"][1]["children"][0]["size"], 4_000_000);
+
+        let options = TreeOptions {
+            expand: true,
+            ..Default::default()
+        };
+        let json = render_json(&tree, &positions, &options);
+        let buffer = &json["children"][1]["children"][1]["children"][3];
+        assert_eq!(buffer["tag"], "data_buffer");
+        assert_eq!(buffer["value"], Value::Null);
+    }
+
+    #[test]
+    fn can_write_dot() {
+        let options = TreeOptions {
+            blocks_only: true,
+            ..Default::default()
+        };
+        let dot = render_dot(&make_tree(), "raw.fif", &options);
+
+        assert!(dot.starts_with("digraph \"raw.fif\" {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert_eq!(dot.matches("->").count(), 2);
+        assert!(dot.contains("[label=\"raw_data (4 tags, 4.0 MB)\"];"));
+        assert!(!dot.contains("plaintext"));
+
+        let dot = render_dot(&make_tree(), "raw.fif", &TreeOptions::default());
+        assert!(dot.contains("[label=\"data_buffer ×4 (4.0 MB)\", shape=plaintext];"));
+        assert_eq!(dot_quote("a \"b\""), "\"a \\\"b\\\"\"");
+    }
+
+    #[test]
+    fn can_show_sizes() {
+        assert_eq!(human_size(999), "999 B");
+        assert_eq!(human_size(1_400_000_000), "1.4 GB");
+        assert_eq!(human_size(u64::MAX), "18446.7 PB");
+    }
+}
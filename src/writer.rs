@@ -0,0 +1,488 @@
+//! Serialize tags and trees back to .fif files.
+//!
+//! Everything is written big-endian, using the same 16 byte header layout the parser reads (see
+//! the tag module).  A tree is written depth-first, with each block wrapped in a block_start /
+//! block_end pair.
+//!
+//! Payloads which were too large to read up front (`Data::InFile`) are copied from the file they
+//! were read from, which has to be supplied with `with_source`.
+//!
+//! Optionally the writer can produce a tag directory: a dir_pointer tag straight after the file
+//! id, pointing at a dir tag listing the position of every tag, written when finishing.
+
+use anyhow::{anyhow, Context, Result};
+use petgraph::stable_graph::NodeIndex;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::enums::{BlockTagKind, DataTagKind};
+use crate::graph::Tree;
+use crate::tag::{
+    ChInfo, CoordTrans, Data, DigPoint, DirEntry, FiffNode, IdStruct, Tag, CH_NAME_LEN,
+    DIR_ENTRY_STRUCT, FIFFV_NEXT_SEQ,
+};
+
+pub struct FifWriter<W: Write + Seek> {
+    writer: W,
+    position: u64,
+    source: Option<File>,
+    directory: bool,
+    entries: Vec<DirEntry>,
+    dir_pointer: Option<u64>,
+}
+
+impl FifWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let fh = File::create(path).with_context(|| format!("Could not create {path:?}"))?;
+        Ok(FifWriter::new(BufWriter::new(fh)))
+    }
+}
+
+impl<W: Write + Seek> FifWriter<W> {
+    pub fn new(writer: W) -> Self {
+        FifWriter {
+            writer,
+            position: 0,
+            source: None,
+            directory: false,
+            entries: vec![],
+            dir_pointer: None,
+        }
+    }
+
+    /// The file that `Data::InFile` payloads should be copied from.
+    pub fn with_source(mut self, source: File) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Write a fresh tag directory when writing a tree.
+    ///
+    /// Any dir and dir_pointer tags already in the tree are dropped, since their contents would be
+    /// stale.
+    pub fn with_directory(mut self) -> Self {
+        self.directory = true;
+        self
+    }
+
+    /// Write tags exactly as given, so reading a file and writing its tags gives the same bytes.
+    pub fn write_tags(&mut self, tags: &[Tag]) -> Result<()> {
+        for tag in tags {
+            self.write_tag(tag)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn write_tag(&mut self, tag: &Tag) -> Result<()> {
+        self.write_raw(tag.code(), tag.data(), tag.next())
+    }
+
+    pub fn write_tree(&mut self, tree: &Tree<FiffNode>) -> Result<()> {
+        let mut children = tree.children(tree.root).into_iter().peekable();
+
+        if self.directory {
+            // the directory pointer conventionally comes right after the file id
+            let file_id = children.next_if(|x| {
+                matches!(
+                    tree[*x],
+                    FiffNode::Tag {
+                        kind: DataTagKind::FileId,
+                        ..
+                    }
+                )
+            });
+
+            match file_id {
+                Some(node) => self.write_node(tree, node)?,
+                None => self.write_raw(
+                    DataTagKind::FileId.to_code(),
+                    &Data::IdStruct(IdStruct::generate()),
+                    FIFFV_NEXT_SEQ,
+                )?,
+            }
+
+            self.dir_pointer = Some(self.position + 16);
+            self.write_raw(
+                DataTagKind::DirPointer.to_code(),
+                &Data::Int32(vec![-1]),
+                FIFFV_NEXT_SEQ,
+            )?;
+        }
+
+        for child in children {
+            self.write_node(tree, child)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the tag directory (if asked for), flush and hand back the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        if let Some(pointer) = self.dir_pointer {
+            let dir_position = position_i32(self.position)?;
+
+            let dir = encode_dir(&self.entries);
+            self.write_raw(DataTagKind::Dir.to_code(), &dir, FIFFV_NEXT_SEQ)?;
+
+            self.writer.seek(SeekFrom::Start(pointer))?;
+            self.writer.write_all(&dir_position.to_be_bytes())?;
+            self.writer.seek(SeekFrom::Start(self.position))?;
+        }
+
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_node(&mut self, tree: &Tree<FiffNode>, node: NodeIndex) -> Result<()> {
+        match &tree[node] {
+            FiffNode::Tag { kind, data } => {
+                let stale = matches!(kind, DataTagKind::Dir | DataTagKind::DirPointer);
+
+                if !(self.directory && stale) {
+                    self.write_raw(kind.to_code(), data, FIFFV_NEXT_SEQ)?;
+                }
+            }
+            FiffNode::Block { kind } => {
+                let kind = Data::Int32(vec![kind.to_code()]);
+                self.write_raw(BlockTagKind::BlockStart.to_code(), &kind, FIFFV_NEXT_SEQ)?;
+
+                for child in tree.children(node) {
+                    self.write_node(tree, child)?;
+                }
+
+                self.write_raw(BlockTagKind::BlockEnd.to_code(), &kind, FIFFV_NEXT_SEQ)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_raw(&mut self, code: i32, data: &Data, next: i32) -> Result<()> {
+        let (payload, size) = match data {
+            Data::InFile { size, .. } => (None, *size),
+            data => {
+                let bytes = encode_data(data)?;
+                let size = bytes.len() as u64;
+                (Some(bytes), size)
+            }
+        };
+
+        let size = i32::try_from(size).map_err(|_| anyhow!("tag {code} is too large to write"))?;
+
+        self.entries.push(DirEntry {
+            code,
+            dtype: data.dtype(),
+            size,
+            position: position_i32(self.position)?,
+        });
+
+        self.writer
+            .write_all(&encode_header(code, data.dtype(), size, next))?;
+
+        if let Some(bytes) = payload {
+            self.writer.write_all(&bytes)?;
+        } else if let Data::InFile { start, size, .. } = data {
+            self.copy_from_source(*start, *size)?;
+        }
+
+        self.position += 16 + size as u64;
+
+        Ok(())
+    }
+
+    fn copy_from_source(&mut self, start: u64, size: u64) -> Result<()> {
+        let source = self.source.as_mut().ok_or(anyhow!(
+            "Can't write data stored in the original file without a source file"
+        ))?;
+
+        source.seek(SeekFrom::Start(start))?;
+        let copied = io::copy(&mut source.take(size), &mut self.writer)?;
+
+        if copied != size {
+            return Err(anyhow!(
+                "Source file ended after {copied} of {size} bytes at position {start}"
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+// file positions are stored as int32 in the directory
+fn position_i32(position: u64) -> Result<i32> {
+    i32::try_from(position).map_err(|_| anyhow!("File position {position} too large for FIFF"))
+}
+
+/// Encode the 16 byte header of a tag.
+pub fn encode_header(code: i32, dtype: i32, size: i32, next: i32) -> Vec<u8> {
+    [code, dtype, size, next]
+        .iter()
+        .flat_map(|x| x.to_be_bytes())
+        .collect()
+}
+
+/// Encode a tag directory as the data of a dir tag.
+pub fn encode_dir(entries: &[DirEntry]) -> Data {
+    let bytes = entries
+        .iter()
+        .flat_map(|x| encode_header(x.code, x.dtype, x.size, x.position))
+        .collect();
+
+    Data::Slice {
+        dtype: DIR_ENTRY_STRUCT,
+        bytes,
+    }
+}
+
+/// Encode data as a big-endian payload, the inverse of `Data::from_slice`.
+///
+/// Fails for `Data::InFile`, which has no payload in memory, and for strings which can't be
+/// encoded in ISO 8859-1.
+pub fn encode_data(data: &Data) -> Result<Vec<u8>> {
+    let bytes = match data {
+        Data::Void => vec![],
+        Data::Slice { bytes, .. } => bytes.clone(),
+        Data::InFile { .. } => {
+            return Err(anyhow!(
+                "Data stored in the original file has to be copied, not encoded"
+            ))
+        }
+        Data::Int32(x) | Data::JulianDate(x) => x.iter().flat_map(|x| x.to_be_bytes()).collect(),
+        Data::Float(x) => x.iter().flat_map(|x| x.to_be_bytes()).collect(),
+        Data::String(x) => encode_latin1(x)?,
+        Data::ChInfoStruct(x) => {
+            let mut bytes = vec![];
+            for ch in x {
+                bytes.append(&mut encode_ch_info(ch)?);
+            }
+            bytes
+        }
+        Data::DigPointStruct(x) => x.iter().flat_map(encode_dig_point).collect(),
+        Data::CoordTransStruct(x) => encode_coord_trans(x),
+        Data::IdStruct(id) => [id.version, id.machid.0, id.machid.1, id.secs, id.usecs]
+            .iter()
+            .flat_map(|x| x.to_be_bytes())
+            .collect(),
+    };
+
+    Ok(bytes)
+}
+
+/// Encode a string as ISO 8859-1 (Latin-1), the inverse of `tag::latin1`.
+pub fn encode_latin1(input: &str) -> Result<Vec<u8>> {
+    input
+        .chars()
+        .map(|x| u8::try_from(x).map_err(|_| anyhow!("{x:?} in {input:?} is not in ISO 8859-1")))
+        .collect()
+}
+
+fn encode_dig_point(point: &DigPoint) -> Vec<u8> {
+    let mut bytes: Vec<u8> = [point.kind, point.ident]
+        .iter()
+        .flat_map(|x| x.to_be_bytes())
+        .collect();
+    bytes.extend(point.r.iter().flat_map(|x| x.to_be_bytes()));
+
+    bytes
+}
+
+fn encode_coord_trans(trans: &CoordTrans) -> Vec<u8> {
+    let mut bytes: Vec<u8> = [trans.from, trans.to]
+        .iter()
+        .flat_map(|x| x.to_be_bytes())
+        .collect();
+
+    let floats = [
+        &trans.rot[..],
+        &[trans.translation],
+        &trans.inv_rot,
+        &[trans.inv_translation],
+    ];
+    bytes.extend(
+        floats
+            .concat()
+            .iter()
+            .flatten()
+            .flat_map(|x| x.to_be_bytes()),
+    );
+
+    bytes
+}
+
+fn encode_ch_info(ch: &ChInfo) -> Result<Vec<u8>> {
+    let mut bytes: Vec<u8> = [ch.scan_no, ch.log_no, ch.kind]
+        .iter()
+        .flat_map(|x| x.to_be_bytes())
+        .collect();
+
+    bytes.extend([ch.range, ch.cal].iter().flat_map(|x| x.to_be_bytes()));
+    bytes.extend(ch.coil_type.to_be_bytes());
+    bytes.extend(
+        [ch.r0, ch.ex, ch.ey, ch.ez]
+            .iter()
+            .flatten()
+            .flat_map(|x| x.to_be_bytes()),
+    );
+    bytes.extend([ch.unit, ch.unit_mul].iter().flat_map(|x| x.to_be_bytes()));
+
+    // names are fixed width, padded with NULs
+    let mut name = encode_latin1(&ch.name)?;
+    if name.len() > CH_NAME_LEN {
+        return Err(anyhow!(
+            "Channel name {:?} is longer than {CH_NAME_LEN} bytes",
+            ch.name
+        ));
+    }
+    name.resize(CH_NAME_LEN, 0);
+    bytes.append(&mut name);
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::FifParser;
+    use crate::tag::FIFFV_NEXT_NONE;
+    use std::io::Cursor;
+
+    #[test]
+    fn can_round_trip_tags() {
+        let tags = default_tags();
+
+        let bytes = write_tags(&tags);
+        let read = FifParser::read_tags_from(Cursor::new(&bytes)).unwrap();
+
+        assert_eq!(read, tags);
+        assert_eq!(write_tags(&read), bytes);
+    }
+
+    #[test]
+    fn can_write_directory() {
+        let tree = FifParser::make_fif_tree(default_tags()).unwrap();
+
+        let mut writer = FifWriter::new(Cursor::new(vec![])).with_directory();
+        writer.write_tree(&tree).unwrap();
+        let bytes = writer.finish().unwrap().into_inner();
+
+        let read = FifParser::read_tags_from(Cursor::new(&bytes)).unwrap();
+
+        assert!(matches!(
+            read[0],
+            Tag::Data {
+                kind: DataTagKind::FileId,
+                ..
+            }
+        ));
+        assert!(matches!(
+            read[1],
+            Tag::Data {
+                kind: DataTagKind::DirPointer,
+                ..
+            }
+        ));
+
+        // the pointer leads to the dir tag, the last one in the file
+        let pointer = match read[1].data() {
+            Data::Int32(x) => x[0] as usize,
+            x => panic!("unexpected pointer {x:?}"),
+        };
+        let code = i32::from_be_bytes(bytes[pointer..pointer + 4].try_into().unwrap());
+        assert_eq!(code, DataTagKind::Dir.to_code());
+
+        // one entry per tag, excluding the directory itself
+        let dir = read.last().unwrap().data();
+        assert_eq!(encode_data(dir).unwrap().len(), (read.len() - 1) * 16);
+    }
+
+    #[test]
+    fn needs_source_for_deferred_data() {
+        let tag = Tag::Data {
+            kind: DataTagKind::DataBuffer,
+            data: Data::InFile {
+                start: 0,
+                size: 1024,
+                dtype: 4,
+            },
+            next: FIFFV_NEXT_SEQ,
+        };
+
+        let mut writer = FifWriter::new(Cursor::new(vec![]));
+        assert!(writer.write_tag(&tag).is_err());
+    }
+
+    #[test]
+    fn can_encode_latin1() {
+        assert_eq!(encode_latin1("Renée").unwrap(), b"Ren\xe9e");
+        assert!(encode_latin1("Zoë 🙂").is_err());
+
+        let name = "x".repeat(CH_NAME_LEN + 1);
+        let ch = ChInfo {
+            scan_no: 1,
+            log_no: 1,
+            kind: 1,
+            range: 1.0,
+            cal: 1.0,
+            coil_type: 0,
+            r0: [0.0; 3],
+            ex: [0.0; 3],
+            ey: [0.0; 3],
+            ez: [0.0; 3],
+            unit: 0,
+            unit_mul: 0,
+            name,
+        };
+        assert!(encode_data(&Data::ChInfoStruct(vec![ch])).is_err());
+    }
+
+    fn write_tags(tags: &[Tag]) -> Vec<u8> {
+        let mut writer = FifWriter::new(Cursor::new(vec![]));
+        writer.write_tags(tags).unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn block(kind: BlockTagKind, code: i32) -> Tag {
+        Tag::Block {
+            kind,
+            data: Data::Int32(vec![code]),
+            next: FIFFV_NEXT_SEQ,
+        }
+    }
+
+    fn data(kind: DataTagKind, data: Data) -> Tag {
+        Tag::Data {
+            kind,
+            data,
+            next: FIFFV_NEXT_SEQ,
+        }
+    }
+
+    fn default_tags() -> Vec<Tag> {
+        vec![
+            data(DataTagKind::FileId, Data::IdStruct(IdStruct::generate())),
+            block(BlockTagKind::BlockStart, 100),
+            data(DataTagKind::BlockId, Data::IdStruct(IdStruct::generate())),
+            block(BlockTagKind::BlockStart, 101),
+            data(DataTagKind::Sfreq, Data::Float(vec![1000.0])),
+            data(DataTagKind::MeasDate, Data::Int32(vec![1646226731, 138511])),
+            data(DataTagKind::Experimenter, Data::String("someone".into())),
+            data(
+                DataTagKind::ChInfo,
+                Data::Slice {
+                    dtype: 0x40000004,
+                    bytes: vec![1, 2, 3],
+                },
+            ),
+            block(BlockTagKind::BlockEnd, 101),
+            block(BlockTagKind::BlockEnd, 100),
+            Tag::Data {
+                kind: DataTagKind::Nop,
+                data: Data::Void,
+                next: FIFFV_NEXT_NONE,
+            },
+        ]
+    }
+}
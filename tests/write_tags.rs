@@ -0,0 +1,248 @@
+use std::fs::File;
+use std::io::Cursor;
+
+use fiff::enums::{BlockKind, DataTagKind};
+use fiff::graph::Tree;
+use fiff::parser::FifParser;
+use fiff::tag::{ChInfo, CoordTrans, Data, DigPoint, FiffNode, IdStruct, Tag};
+use fiff::writer::FifWriter;
+use proptest::collection::vec;
+use proptest::prelude::*;
+use proptest::sample::select;
+
+#[ignore]
+#[test]
+pub fn can_write_tags_unchanged() {
+    let file = "data/file_0.fif";
+    let tags = FifParser::read_tags(file.into()).expect("Should have been able to read test file");
+
+    let mut writer = FifWriter::new(Cursor::new(vec![])).with_source(File::open(file).unwrap());
+    writer.write_tags(&tags).unwrap();
+    let written = writer.finish().unwrap().into_inner();
+
+    assert_eq!(written, std::fs::read(file).unwrap());
+}
+
+// a generated tree, before it's been put into a Tree<FiffNode>
+#[derive(Debug, Clone)]
+enum Node {
+    Tag(DataTagKind, Data),
+    Block(BlockKind, Vec<Node>),
+}
+
+fn data() -> impl Strategy<Value = Data> {
+    prop_oneof![
+        Just(Data::Void),
+        vec(any::<i32>(), 0..16).prop_map(Data::Int32),
+        vec(-1e6f32..1e6f32, 0..16).prop_map(Data::Float),
+        vec(any::<i32>(), 1..2).prop_map(Data::JulianDate),
+        // any latin-1 string, including NULs and padding
+        "[\\x00-\\xff]{0,40}".prop_map(Data::String),
+        vec(ch_info(), 0..4).prop_map(Data::ChInfoStruct),
+        vec(dig_point(), 0..4).prop_map(Data::DigPointStruct),
+        coord_trans().prop_map(Data::CoordTransStruct),
+        any::<(i32, (i32, i32), i32, i32)>().prop_map(|(version, machid, secs, usecs)| {
+            Data::IdStruct(IdStruct {
+                version,
+                machid,
+                secs,
+                usecs,
+            })
+        }),
+        // doubles aren't decoded, so they stay as bytes
+        vec(any::<u8>(), 0..64).prop_map(|bytes| Data::Slice { dtype: 5, bytes }),
+    ]
+}
+
+fn ch_info() -> impl Strategy<Value = ChInfo> {
+    let vec3 = || [-1f32..1f32, -1f32..1f32, -1f32..1f32];
+
+    (
+        any::<(i32, i32, i32, i32)>(),
+        (-1e6f32..1e6f32, -1e6f32..1e6f32),
+        (vec3(), vec3(), vec3(), vec3()),
+        any::<(i32, i32)>(),
+        "[\\x00-\\xff]{16}",
+    )
+        .prop_map(
+            |(
+                (scan_no, log_no, kind, coil_type),
+                (range, cal),
+                (r0, ex, ey, ez),
+                (unit, unit_mul),
+                name,
+            )| {
+                ChInfo {
+                    scan_no,
+                    log_no,
+                    kind,
+                    range,
+                    cal,
+                    coil_type,
+                    r0,
+                    ex,
+                    ey,
+                    ez,
+                    unit,
+                    unit_mul,
+                    name,
+                }
+            },
+        )
+}
+
+fn dig_point() -> impl Strategy<Value = DigPoint> {
+    (any::<(i32, i32)>(), [-1f32..1f32, -1f32..1f32, -1f32..1f32])
+        .prop_map(|((kind, ident), r)| DigPoint { kind, ident, r })
+}
+
+fn coord_trans() -> impl Strategy<Value = CoordTrans> {
+    let vec3 = || [-1f32..1f32, -1f32..1f32, -1f32..1f32];
+    let mat3 = || [vec3(), vec3(), vec3()];
+
+    (any::<(i32, i32)>(), mat3(), vec3(), mat3(), vec3()).prop_map(
+        |((from, to), rot, translation, inv_rot, inv_translation)| CoordTrans {
+            from,
+            to,
+            rot,
+            translation,
+            inv_rot,
+            inv_translation,
+        },
+    )
+}
+
+fn tag() -> impl Strategy<Value = Node> {
+    let kinds = vec![
+        DataTagKind::BlockId,
+        DataTagKind::Nchan,
+        DataTagKind::Sfreq,
+        DataTagKind::MeasDate,
+        DataTagKind::Experimenter,
+        DataTagKind::BadChs,
+        DataTagKind::SubjId,
+        DataTagKind::Nop,
+        DataTagKind::ChInfo,
+        DataTagKind::CoordTrans,
+        DataTagKind::DigPoint,
+        DataTagKind::Unknown(3999),
+    ];
+
+    (select(kinds), data()).prop_map(|(kind, data)| Node::Tag(kind, data))
+}
+
+fn block_kind() -> impl Strategy<Value = BlockKind> {
+    select(vec![
+        BlockKind::Meas,
+        BlockKind::MeasInfo,
+        BlockKind::RawData,
+        BlockKind::Subject,
+        BlockKind::HpiResult,
+        BlockKind::Unknown(12345),
+    ])
+}
+
+fn node() -> impl Strategy<Value = Node> {
+    tag().prop_recursive(4, 64, 8, |inner| {
+        (block_kind(), vec(inner, 0..8)).prop_map(|(kind, children)| Node::Block(kind, children))
+    })
+}
+
+// every generated file starts with an id, like a real one
+fn fif_tree() -> impl Strategy<Value = Tree<FiffNode>> {
+    vec(node(), 0..8).prop_map(|nodes| {
+        let mut tree = Tree::new();
+        tree.add_child(FiffNode::Tag {
+            kind: DataTagKind::FileId,
+            data: Data::IdStruct(IdStruct::generate()),
+        });
+        add_nodes(&mut tree, nodes);
+        tree
+    })
+}
+
+fn add_nodes(tree: &mut Tree<FiffNode>, nodes: Vec<Node>) {
+    for node in nodes {
+        match node {
+            Node::Tag(kind, data) => {
+                tree.add_child(FiffNode::Tag { kind, data });
+            }
+            Node::Block(kind, children) => {
+                let parent = tree.current();
+                let block = tree.add_child(FiffNode::Block { kind });
+                tree.move_to(block);
+                add_nodes(tree, children);
+                tree.move_to(parent);
+            }
+        }
+    }
+}
+
+fn write_tree(tree: &Tree<FiffNode>, directory: bool) -> Vec<u8> {
+    let mut writer = FifWriter::new(Cursor::new(vec![]));
+    if directory {
+        writer = writer.with_directory();
+    }
+
+    writer.write_tree(tree).unwrap();
+    writer.finish().unwrap().into_inner()
+}
+
+proptest! {
+    #[test]
+    fn tree_round_trips(tree in fif_tree()) {
+        let bytes = write_tree(&tree, false);
+        let tags = FifParser::read_tags_from(Cursor::new(&bytes)).unwrap();
+
+        prop_assert_eq!(FifParser::make_fif_tree(tags).unwrap(), tree);
+    }
+
+    #[test]
+    fn rewriting_tags_gives_same_bytes(tree in fif_tree()) {
+        let bytes = write_tree(&tree, false);
+        let tags = FifParser::read_tags_from(Cursor::new(&bytes)).unwrap();
+
+        let mut writer = FifWriter::new(Cursor::new(vec![]));
+        writer.write_tags(&tags).unwrap();
+
+        prop_assert_eq!(writer.finish().unwrap().into_inner(), bytes);
+    }
+
+    #[test]
+    fn tree_round_trips_with_directory(tree in fif_tree()) {
+        let bytes = write_tree(&tree, true);
+        let tags = FifParser::read_tags_from(Cursor::new(&bytes)).unwrap();
+
+        let pointer = match tags[1].data() {
+            Data::Int32(x) => x[0] as usize,
+            x => panic!("unexpected dir pointer {x:?}"),
+        };
+        let code = i32::from_be_bytes(bytes[pointer..pointer + 4].try_into().unwrap());
+        prop_assert_eq!(code, DataTagKind::Dir.to_code());
+
+        let tags: Vec<Tag> = tags
+            .into_iter()
+            .filter(|x| !matches!(x, Tag::Data { kind: DataTagKind::Dir | DataTagKind::DirPointer, .. }))
+            .collect();
+
+        prop_assert_eq!(FifParser::make_fif_tree(tags).unwrap(), tree);
+    }
+
+    #[test]
+    fn raw_tags_match_their_bytes(tree in fif_tree()) {
+        let bytes = write_tree(&tree, false);
+        let raw = FifParser::read_raw_tags_from(Cursor::new(&bytes), 16).unwrap();
+
+        for tag in raw {
+            let start = tag.position as usize;
+            let code = i32::from_be_bytes(bytes[start..start + 4].try_into().unwrap());
+            prop_assert_eq!(code, tag.header.code);
+            prop_assert_eq!(tag.header.code, tag.tag.code());
+
+            let payload = start + 16;
+            let end = payload + tag.preview.len();
+            prop_assert_eq!(&bytes[payload..end], tag.preview.as_slice());
+            prop_assert_eq!(tag.preview.len(), (tag.header.size as usize).min(16));
+        }
+    }
+}